@@ -0,0 +1,138 @@
+-- AlterTable
+ALTER TABLE `Organization` ADD COLUMN `deletedAt` DATETIME(3) NULL,
+    ADD COLUMN `plan` ENUM('FREE', 'TEAM', 'ENTERPRISE') NOT NULL DEFAULT 'FREE',
+    ADD COLUMN `retentionDays` INTEGER NULL,
+    ADD COLUMN `unknownMetrics` ENUM('ACCEPT', 'TAG', 'REJECT') NOT NULL DEFAULT 'ACCEPT';
+
+-- AlterTable
+ALTER TABLE `Project` ADD COLUMN `deletedAt` DATETIME(3) NULL;
+
+-- AlterTable
+ALTER TABLE `User` ADD COLUMN `deletedAt` DATETIME(3) NULL,
+    ADD COLUMN `role` ENUM('OWNER', 'ADMIN', 'MEMBER') NOT NULL DEFAULT 'MEMBER';
+
+-- AlterTable
+ALTER TABLE `Test` ADD COLUMN `baselineRunId` CHAR(12) NULL,
+    ADD COLUMN `deletedAt` DATETIME(3) NULL,
+    ADD COLUMN `regressionTolerancePct` DOUBLE NULL;
+
+-- AlterTable
+ALTER TABLE `TestRun` ADD COLUMN `deletedAt` DATETIME(3) NULL,
+    ADD COLUMN `durationSecs` INTEGER NULL,
+    ADD COLUMN `finishedAt` DATETIME(3) NULL,
+    ADD COLUMN `rawPurgedAt` DATETIME(3) NULL,
+    ADD COLUMN `rolledUpAt` DATETIME(3) NULL,
+    ADD COLUMN `samples` BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN `startedAt` DATETIME(3) NULL,
+    ADD COLUMN `vus` INTEGER NOT NULL DEFAULT 1;
+
+-- AlterTable
+ALTER TABLE `MetricsCatalog` ADD COLUMN `organizationId` VARCHAR(191) NULL;
+
+-- CreateIndex
+CREATE INDEX `MetricsCatalog_organizationId_value_idx` ON `MetricsCatalog`(`organizationId`, `value`);
+
+-- CreateTable
+CREATE TABLE `Regression` (
+    `id` CHAR(12) NOT NULL,
+    `metric` VARCHAR(50) NOT NULL,
+    `url` VARCHAR(2048) NULL,
+    `baselineRunId` CHAR(12) NOT NULL,
+    `baselineMean` DOUBLE NOT NULL,
+    `mean` DOUBLE NOT NULL,
+    `changePct` DOUBLE NOT NULL,
+    `pValue` DOUBLE NULL,
+    `createdAt` DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `runId` VARCHAR(191) NOT NULL,
+
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- CreateTable
+CREATE TABLE `ShareLink` (
+    `id` CHAR(12) NOT NULL,
+    `tokenHash` CHAR(64) NOT NULL,
+    `password` VARCHAR(255) NULL,
+    `createdAt` DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `expiresAt` DATETIME(3) NULL,
+    `revokedAt` DATETIME(3) NULL,
+    `createdBy` CHAR(12) NOT NULL,
+    `runId` VARCHAR(191) NOT NULL,
+
+    UNIQUE INDEX `ShareLink_tokenHash_key`(`tokenHash`),
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- CreateTable
+CREATE TABLE `OidcProvider` (
+    `id` CHAR(12) NOT NULL,
+    `issuer` VARCHAR(255) NOT NULL,
+    `clientId` VARCHAR(255) NOT NULL,
+    `clientSecret` VARCHAR(255) NULL,
+    `redirectUri` VARCHAR(255) NOT NULL,
+    `emailDomain` VARCHAR(255) NOT NULL,
+    `enabled` BOOLEAN NOT NULL DEFAULT true,
+    `createdAt` DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `updatedAt` DATETIME(3) NOT NULL,
+    `organizationId` VARCHAR(191) NOT NULL,
+
+    UNIQUE INDEX `OidcProvider_emailDomain_key`(`emailDomain`),
+    UNIQUE INDEX `OidcProvider_organizationId_key`(`organizationId`),
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- CreateTable
+CREATE TABLE `DataExport` (
+    `id` CHAR(12) NOT NULL,
+    `token` VARCHAR(64) NOT NULL,
+    `status` ENUM('PENDING', 'READY', 'FAILED') NOT NULL DEFAULT 'PENDING',
+    `payload` LONGTEXT NULL,
+    `file` VARCHAR(255) NULL,
+    `createdAt` DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `expiresAt` DATETIME(3) NOT NULL,
+    `userId` VARCHAR(191) NOT NULL,
+
+    UNIQUE INDEX `DataExport_token_key`(`token`),
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- CreateTable
+CREATE TABLE `UsagePeriod` (
+    `id` CHAR(12) NOT NULL,
+    `organizationId` CHAR(12) NOT NULL,
+    `periodStart` DATETIME(3) NOT NULL,
+    `vuSeconds` BIGINT NOT NULL DEFAULT 0,
+    `runs` INTEGER NOT NULL DEFAULT 0,
+    `ingestedSamples` BIGINT NOT NULL DEFAULT 0,
+    `updatedAt` DATETIME(3) NOT NULL,
+
+    UNIQUE INDEX `UsagePeriod_organizationId_periodStart_key`(`organizationId`, `periodStart`),
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- CreateTable
+CREATE TABLE `AuditLog` (
+    `id` CHAR(12) NOT NULL,
+    `createdAt` DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `organizationId` CHAR(12) NOT NULL,
+    `actorId` CHAR(12) NULL,
+    `action` VARCHAR(50) NOT NULL,
+    `targetType` VARCHAR(30) NOT NULL,
+    `targetId` VARCHAR(64) NOT NULL,
+    `before` TEXT NULL,
+    `after` TEXT NULL,
+    `ip` VARCHAR(45) NULL,
+    `requestId` VARCHAR(64) NULL,
+
+    INDEX `AuditLog_organizationId_createdAt_idx`(`organizationId`, `createdAt`),
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- Every existing organization gets an owner, its earliest user
+UPDATE `User` AS `u`
+JOIN (
+    SELECT `id`, ROW_NUMBER() OVER (PARTITION BY `organizationId` ORDER BY `createdAt`, `id`) AS `n`
+    FROM `User`
+) AS `first` ON `first`.`id` = `u`.`id`
+SET `u`.`role` = 'OWNER'
+WHERE `first`.`n` = 1;
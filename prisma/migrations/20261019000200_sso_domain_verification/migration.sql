@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE `OidcProvider` ADD COLUMN `configuredBy` CHAR(12) NULL,
+    ADD COLUMN `domainVerifiedAt` DATETIME(3) NULL;
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE `UsagePeriod` ADD COLUMN `retainedBytes` BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+-- CreateTable
+CREATE TABLE `RunCheck` (
+    `id` CHAR(12) NOT NULL,
+    `kind` ENUM('THRESHOLD', 'CHECK') NOT NULL,
+    `name` VARCHAR(255) NOT NULL,
+    `passes` BIGINT NOT NULL DEFAULT 0,
+    `fails` BIGINT NOT NULL DEFAULT 0,
+    `runId` VARCHAR(191) NOT NULL,
+
+    UNIQUE INDEX `RunCheck_runId_kind_name_key`(`runId`, `kind`, `name`),
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
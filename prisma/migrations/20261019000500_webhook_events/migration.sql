@@ -0,0 +1,12 @@
+-- CreateTable
+CREATE TABLE `WebhookEvent` (
+    `id` CHAR(12) NOT NULL,
+    `createdAt` DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `organizationId` CHAR(12) NOT NULL,
+    `event` VARCHAR(50) NOT NULL,
+    `payload` TEXT NOT NULL,
+    `deliveredAt` DATETIME(3) NULL,
+
+    INDEX `WebhookEvent_deliveredAt_createdAt_idx`(`deliveredAt`, `createdAt`),
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
@@ -0,0 +1,382 @@
+use crate::{
+    configuration::Settings,
+    domain::auth::{validate_password, AuthRegisterForm},
+    jobs::retention::apply_retention,
+    prisma::{metrics_catalog, oidc_provider, organization, user, Plan, PrismaClient, UserRole},
+    questdb::migrations::run_migrations,
+    util::{
+        audit::{record_audit, AuditEntry, RequestMeta},
+        hash::hash_password,
+        nano_id::generate_id,
+    },
+    AppState,
+};
+use axum_login::axum_sessions::async_session::SessionStore;
+use clap::{Parser, Subcommand};
+use eyre::{bail, eyre};
+use prisma_client_rust::chrono::Utc;
+use secrecy::ExposeSecret;
+use validator::Validate;
+
+/// Metrics every environment starts with, as `(label, value, group, unit, description)`.
+const DEFAULT_CATALOG: &[(&str, &str, &str, &str, &str)] = &[
+    (
+        "VUs",
+        "vus",
+        "general",
+        "count",
+        "The number of virtual users",
+    ),
+    (
+        "Request rate",
+        "http_request_rate",
+        "HTTP",
+        "rps",
+        "The rate at which requests are made to a given URL",
+    ),
+    (
+        "Response time",
+        "http_response_rate",
+        "HTTP",
+        "ms",
+        "The time spent waiting for the full response",
+    ),
+    (
+        "Failure rate",
+        "http_failure_rate",
+        "HTTP",
+        "%",
+        "The percentage of requests that resulted in a failure",
+    ),
+    (
+        "Handshake time",
+        "http_handshake_time",
+        "HTTP",
+        "ms",
+        "The time it takes to establish a connection with the server",
+    ),
+    (
+        "Waiting time",
+        "http_waiting_time",
+        "HTTP",
+        "ms",
+        "The time spent waiting for a response from the server",
+    ),
+    (
+        "Blocked time",
+        "http_blocked_time",
+        "HTTP",
+        "ms",
+        "The time spent waiting for an available connection slot",
+    ),
+    (
+        "Sent data",
+        "http_sent_bytes",
+        "HTTP",
+        "bytes",
+        "The amount of data sent in the request",
+    ),
+    (
+        "Received data",
+        "http_recv_bytes",
+        "HTTP",
+        "bytes",
+        "The amount of data received in the response",
+    ),
+    (
+        "CPU usage",
+        "cpu_usage",
+        "System",
+        "%",
+        "The percentage of CPU utilization",
+    ),
+    (
+        "Memory usage",
+        "mem_usage",
+        "System",
+        "%",
+        "The percentage of memory utilization",
+    ),
+];
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "tonsail-server",
+    about = "Tonsail API server and its admin commands"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Starts the API server, the default
+    Serve,
+    /// Applies the MySQL and QuestDB migrations
+    Migrate,
+    /// Adds the default metrics catalog entries that are missing
+    Seed,
+    /// Creates an organization and prints its id
+    CreateOrg {
+        #[arg(long)]
+        name: String,
+        #[arg(long, default_value = "free", value_parser = parse_plan)]
+        plan: Plan,
+    },
+    /// Creates a user in an existing organization and prints its id
+    CreateUser {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        name: String,
+        /// Id of the organization
+        #[arg(long)]
+        org: String,
+        #[arg(long, default_value = "member", value_parser = parse_role)]
+        role: UserRole,
+        #[arg(long, env = "TONSAIL_PASSWORD", hide_env_values = true)]
+        password: String,
+    },
+    /// Sets a new password and logs the user out everywhere
+    ResetPassword {
+        #[arg(long)]
+        email: String,
+        #[arg(long, env = "TONSAIL_PASSWORD", hide_env_values = true)]
+        password: String,
+    },
+    /// Logs every user out by deleting all sessions
+    PurgeSessions,
+    /// Lets the SSO provider of an organization log in its email domain
+    ///
+    /// Run it once the organization proved it owns the domain, e.g. with a DNS record.
+    VerifySsoDomain {
+        /// Id of the organization
+        #[arg(long)]
+        org: String,
+    },
+    /// Metric data retention
+    Retention {
+        #[command(subcommand)]
+        command: RetentionCommand,
+    },
+    /// Validates the configuration without connecting to anything
+    CheckConfig,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RetentionCommand {
+    /// Rolls up finished runs and drops expired raw samples once
+    Run,
+}
+
+fn parse_plan(plan: &str) -> Result<Plan, String> {
+    match plan.to_lowercase().as_str() {
+        "free" => Ok(Plan::Free),
+        "team" => Ok(Plan::Team),
+        "enterprise" => Ok(Plan::Enterprise),
+        _ => Err("expected free, team or enterprise".to_string()),
+    }
+}
+
+fn parse_role(role: &str) -> Result<UserRole, String> {
+    match role.to_lowercase().as_str() {
+        "owner" => Ok(UserRole::Owner),
+        "admin" => Ok(UserRole::Admin),
+        "member" => Ok(UserRole::Member),
+        _ => Err("expected owner, admin or member".to_string()),
+    }
+}
+
+/// Runs an admin command, every command but `serve`.
+pub async fn run(command: Command, config: Settings) -> eyre::Result<()> {
+    config.validate()?;
+    let meta = RequestMeta::default();
+    match command {
+        Command::Serve => bail!("serve is not an admin command"),
+        Command::CheckConfig => println!("Configuration is valid"),
+        Command::Migrate => migrate(&config).await?,
+        Command::Seed => {
+            let state = AppState::connect(&config).await?;
+            let added = seed_catalog(&state.db_client).await?;
+            println!("Added {added} metrics catalog entries");
+        }
+        Command::CreateOrg { name, plan } => {
+            let state = AppState::connect(&config).await?;
+            let org = state
+                .db_client
+                .organization()
+                .create(generate_id(), name, vec![organization::plan::set(plan)])
+                .exec()
+                .await?;
+            let entry = AuditEntry::new("organization.created", &org.id, "organization", &org.id);
+            record_audit(&state, None, &meta, entry).await;
+            println!("{}", org.id);
+        }
+        Command::CreateUser {
+            email,
+            name,
+            org,
+            role,
+            password,
+        } => {
+            let form = AuthRegisterForm {
+                name,
+                email,
+                password,
+            };
+            form.validate()?;
+            let state = AppState::connect(&config).await?;
+            let user = create_user(&state.db_client, form, &org, role).await?;
+            let entry = AuditEntry::new("user.created", &org, "user", &user.id);
+            record_audit(&state, None, &meta, entry).await;
+            println!("{}", user.id);
+        }
+        Command::ResetPassword { email, password } => {
+            validate_password(&password)?;
+            let state = AppState::connect(&config).await?;
+            let user = state
+                .db_client
+                .user()
+                .update(
+                    user::email::equals(email),
+                    vec![user::password::set(hash_password(password.as_bytes()))],
+                )
+                .exec()
+                .await?;
+            let sessions = state
+                .session_store()
+                .destroy_user_sessions(&user.id)
+                .await
+                .map_err(|e| eyre!("Could not log the user out: {e}"))?;
+            let entry = AuditEntry::new(
+                "user.password_reset",
+                &user.organization_id,
+                "user",
+                &user.id,
+            );
+            record_audit(&state, None, &meta, entry).await;
+            println!("Reset the password and ended {sessions} sessions");
+        }
+        Command::PurgeSessions => {
+            let state = AppState::connect(&config).await?;
+            state
+                .session_store()
+                .clear_store()
+                .await
+                .map_err(|e| eyre!("Could not purge the sessions: {e}"))?;
+            println!("Purged every session");
+        }
+        Command::VerifySsoDomain { org } => {
+            let state = AppState::connect(&config).await?;
+            let provider = state
+                .db_client
+                .oidc_provider()
+                .update(
+                    oidc_provider::organization_id::equals(org.clone()),
+                    vec![oidc_provider::domain_verified_at::set(Some(
+                        Utc::now().into(),
+                    ))],
+                )
+                .exec()
+                .await?;
+            let entry = AuditEntry::new(
+                "organization.sso_domain_verified",
+                &org,
+                "oidc_provider",
+                &provider.id,
+            )
+            .after(serde_json::json!({ "emailDomain": provider.email_domain }));
+            record_audit(&state, None, &meta, entry).await;
+            println!("Verified {}", provider.email_domain);
+        }
+        Command::Retention {
+            command: RetentionCommand::Run,
+        } => {
+            let state = AppState::connect(&config).await?;
+            apply_retention(&state, Utc::now()).await?;
+            println!("Applied retention");
+        }
+    }
+    Ok(())
+}
+
+async fn migrate(config: &Settings) -> eyre::Result<()> {
+    let client = PrismaClient::_builder()
+        .with_url(config.database.url.expose_secret().clone())
+        .build()
+        .await?;
+    client
+        ._migrate_deploy()
+        .await
+        .map_err(|e| eyre!("Could not apply the MySQL migrations: {e}"))?;
+    println!("Applied the MySQL migrations");
+
+    let state = AppState::connect(config).await?;
+    let applied = run_migrations(&state.pg_client, &state.rds_client).await?;
+    println!("Applied {} QuestDB migrations {applied:?}", applied.len());
+    Ok(())
+}
+
+async fn create_user(
+    client: &PrismaClient,
+    form: AuthRegisterForm,
+    org_id: &str,
+    role: UserRole,
+) -> eyre::Result<user::Data> {
+    let org = client
+        .organization()
+        .find_first(vec![
+            organization::id::equals(org_id.to_string()),
+            organization::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| eyre!("No such organization exists"))?;
+    let user = client
+        .user()
+        .create(
+            generate_id(),
+            form.email,
+            hash_password(form.password.as_bytes()),
+            form.name,
+            organization::id::equals(org.id),
+            vec![user::role::set(role)],
+        )
+        .exec()
+        .await?;
+    Ok(user)
+}
+
+/// Inserts the built-in catalog entries whose value is not in the catalog yet.
+pub async fn seed_catalog(client: &PrismaClient) -> eyre::Result<usize> {
+    let mut added = 0;
+    for (label, value, group, unit, description) in DEFAULT_CATALOG {
+        let exists = client
+            .metrics_catalog()
+            .find_first(vec![
+                metrics_catalog::value::equals(value.to_string()),
+                metrics_catalog::organization_id::equals(None),
+            ])
+            .exec()
+            .await?
+            .is_some();
+        if exists {
+            continue;
+        }
+        client
+            .metrics_catalog()
+            .create(
+                label.to_string(),
+                value.to_string(),
+                group.to_string(),
+                unit.to_string(),
+                description.to_string(),
+                vec![],
+            )
+            .exec()
+            .await?;
+        added += 1;
+    }
+    Ok(added)
+}
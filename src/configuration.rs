@@ -1,7 +1,21 @@
+use backon::ExponentialBuilder;
 use config::Config;
-use serde::Deserialize;
-use serde_aux::prelude::{deserialize_number_from_string, deserialize_vec_from_string_or_vec};
+use prisma_client_rust::chrono::NaiveDateTime;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use serde_aux::prelude::deserialize_number_from_string;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
 use tracing::info;
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::prisma::Plan;
+use crate::util::{
+    secrets::{deserialize_secret_list, SecretKey},
+    startup_error::StartupError,
+};
 
 #[derive(Deserialize)]
 pub struct Settings {
@@ -9,23 +23,82 @@ pub struct Settings {
     pub redis: RedisSettings,
     pub questdb: QuestDBSettings,
     pub application: ApplicationSettings,
-    #[serde(deserialize_with = "deserialize_vec_from_string_or_vec")]
-    pub secret: Vec<u8>,
+    pub deletion: DeletionSettings,
+    pub exports: ExportSettings,
+    pub retention: RetentionSettings,
+    pub regressions: RegressionSettings,
+    pub telemetry: TelemetrySettings,
+    pub cors: CorsSettings,
+    pub session: SessionSettings,
+    pub rate_limit: RateLimitSettings,
+    pub plans: PlanSettings,
+    /// Signs the session cookies
+    pub secret: SecretKey,
+    /// Keys replaced by `secret` that still verify the cookies they signed
+    #[serde(default, deserialize_with = "deserialize_secret_list")]
+    pub previous_secrets: Vec<SecretKey>,
 }
 
+/// `DATABASE_URL` overrides `url`, it is the variable the Prisma CLI reads as well.
 #[derive(Deserialize)]
 pub struct DatabaseSettings {
-    pub url: String,
+    pub url: Secret<String>,
+    #[serde(default)]
+    pub retry: RetrySettings,
 }
 
 #[derive(Deserialize)]
 pub struct RedisSettings {
-    pub url: String,
+    pub url: Secret<String>,
+    #[serde(default)]
+    pub retry: RetrySettings,
 }
 
 #[derive(Deserialize)]
 pub struct QuestDBSettings {
-    pub url: String,
+    pub url: Secret<String>,
+    #[serde(default)]
+    pub retry: RetrySettings,
+    /// Start without waiting for QuestDB, only the metrics routes fail until it is up
+    #[serde(default)]
+    pub lazy: bool,
+    /// Leave the schema to `tonsail-server migrate` instead of migrating at startup
+    #[serde(default)]
+    pub skip_migrations: bool,
+}
+
+/// Exponential backoff used while connecting to a dependency at startup.
+#[derive(Deserialize, Clone, Copy)]
+pub struct RetrySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_delay_ms: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            min_delay_ms: 500,
+            max_delay_ms: 10_000,
+        }
+    }
+}
+
+impl RetrySettings {
+    pub fn backoff(&self) -> ExponentialBuilder {
+        ExponentialBuilder::default()
+            .with_min_delay(Duration::from_millis(self.min_delay_ms))
+            .with_max_delay(Duration::from_millis(self.max_delay_ms))
+            .with_max_times(self.max_retries)
+    }
+
+    pub fn attempts(&self) -> usize {
+        self.max_retries + 1
+    }
 }
 
 #[derive(Deserialize)]
@@ -33,10 +106,333 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    /// HTTP-date after which the unversioned routes may be removed
+    pub legacy_sunset: String,
+    /// Seconds new connections are still accepted once readiness fails, so load
+    /// balancers stop routing here before the listener closes
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub pre_stop_delay_secs: u64,
+    /// Seconds in-flight requests and background jobs get to finish after the pre-stop delay,
+    /// closing the pools included
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub drain_timeout_secs: u64,
+    /// Reverse proxies whose `X-Forwarded-For` header names the client
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DeletionSettings {
+    /// Days a soft-deleted record is kept before it is purged for good
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub grace_period_days: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub purge_interval_secs: u64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RetentionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_secs: u64,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct RegressionSettings {
+    /// Percent a metric may get worse by, for tests that do not set their own
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub tolerance_pct: f64,
+    /// Highest p-value a regression is flagged at
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub significance: f64,
+    /// Samples both runs need of a metric and URL before they are compared
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_samples: i64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ExportSettings {
+    /// Hours a finished export can be downloaded
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub link_ttl_hours: i64,
+    /// Where run exports too large to stream are written
+    ///
+    /// Downloads are served from this directory by whichever server gets the request, so with
+    /// more than one replica it must be a volume they all share.
+    pub directory: String,
+    /// Run exports with more samples are prepared in the background
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_streamed_rows: i64,
+    /// Size of the QuestDB pool exports read from, apart from the one of ingestion and queries
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
+    /// Seconds an export waits for its reader to take the next chunk before giving up
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub send_timeout_secs: u64,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Compact,
+    Json,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TelemetrySettings {
+    pub log_format: LogFormat,
+    /// OTLP gRPC collector, traces are only exported when it is set
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Share of the traces started here that are exported, between 0 and 1
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub sampling_ratio: f64,
+    #[serde(default)]
+    pub resource_attributes: HashMap<String, String>,
+    /// Bearer token Prometheus scrapes `/internal/metrics` with, the route is hidden without it
+    #[serde(default)]
+    pub metrics_token: Option<Secret<String>>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CorsSettings {
+    /// Exact origins, or patterns like `https://*.tonsail.dev` for every subdomain
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_age_secs: u64,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SessionSettings {
+    pub cookie_name: String,
+    pub cookie_domain: Option<String>,
+    pub secure: bool,
+    pub same_site: SameSitePolicy,
+    /// Redis key prefix of the stored sessions
+    pub prefix: String,
+    /// Lifetime of a session no matter how active it is
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub ttl_secs: u64,
+    /// A session unused for this long is dropped before its TTL
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_timeout_secs: u64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    /// Login, registration and SSO
+    pub auth: BucketSettings,
+    /// Load test result queries
+    pub query: BucketSettings,
+    /// Run creation
+    pub runs: BucketSettings,
+    /// Samples and imports, which load generators send while a run is going
+    pub ingestion: BucketSettings,
+    pub plan_multipliers: PlanMultipliers,
+}
+
+/// Token bucket that holds `capacity` requests and refills `refill_per_minute` of them.
+#[derive(Deserialize, Clone, Copy)]
+pub struct BucketSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub refill_per_minute: u32,
+}
+
+/// Scales every bucket of the organizations on a plan.
+#[derive(Deserialize, Clone, Copy)]
+pub struct PlanMultipliers {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub free: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub team: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub enterprise: u32,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct PlanSettings {
+    pub free: PlanLimits,
+    pub team: PlanLimits,
+    pub enterprise: PlanLimits,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct PlanLimits {
+    /// Runs of the organization that may be started at the same time
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_concurrent_runs: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_vus: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_run_duration_secs: u32,
+    /// Days the samples of a run are kept
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retention_days: u32,
+}
+
+impl PlanSettings {
+    pub fn limits(&self, plan: Plan) -> PlanLimits {
+        match plan {
+            Plan::Free => self.free,
+            Plan::Team => self.team,
+            Plan::Enterprise => self.enterprise,
+        }
+    }
+}
+
+/// axum-sessions refuses cookie signing keys shorter than this
+pub const MIN_SECRET_LENGTH: usize = 64;
+const MIN_METRICS_TOKEN_LENGTH: usize = 32;
+/// IMF-fixdate, the only HTTP date format servers may send
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+impl Settings {
+    /// Catches the mistakes that would otherwise only show up once a dependency is used.
+    pub fn validate(&self) -> Result<(), StartupError> {
+        let mut problems = vec![];
+
+        let keys = std::iter::once(("secret", &self.secret)).chain(
+            self.previous_secrets
+                .iter()
+                .map(|k| ("previous_secrets", k)),
+        );
+        for (name, key) in keys {
+            if key.len() < MIN_SECRET_LENGTH {
+                problems.push(format!(
+                    "{name} must be at least {MIN_SECRET_LENGTH} bytes, got {}",
+                    key.len()
+                ));
+            }
+        }
+
+        let urls = [
+            (
+                "database.url",
+                self.database.url.expose_secret(),
+                &["mysql"][..],
+            ),
+            (
+                "redis.url",
+                self.redis.url.expose_secret(),
+                &["redis", "rediss"],
+            ),
+            (
+                "questdb.url",
+                self.questdb.url.expose_secret(),
+                &["postgres", "postgresql"],
+            ),
+        ];
+        for (name, url, schemes) in urls {
+            match Url::parse(url) {
+                Ok(parsed) if schemes.contains(&parsed.scheme()) => {}
+                Ok(parsed) => problems.push(format!(
+                    "{name} must use one of {schemes:?}, got {}",
+                    parsed.scheme()
+                )),
+                Err(e) => problems.push(format!("{name} is not a valid URL: {e}")),
+            }
+        }
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            if let Err(e) = Url::parse(endpoint) {
+                problems.push(format!("telemetry.otlp_endpoint is not a valid URL: {e}"));
+            }
+        }
+        if let Some(token) = &self.telemetry.metrics_token {
+            if token.expose_secret().len() < MIN_METRICS_TOKEN_LENGTH {
+                problems.push(format!(
+                    "telemetry.metrics_token must be at least {MIN_METRICS_TOKEN_LENGTH} bytes"
+                ));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.telemetry.sampling_ratio) {
+            problems.push("telemetry.sampling_ratio must be between 0 and 1".to_string());
+        }
+        if NaiveDateTime::parse_from_str(&self.application.legacy_sunset, HTTP_DATE_FORMAT).is_err()
+        {
+            problems.push(format!(
+                "application.legacy_sunset must be an HTTP date, got {}",
+                self.application.legacy_sunset
+            ));
+        }
+        for method in &self.cors.allowed_methods {
+            if http::Method::from_bytes(method.to_uppercase().as_bytes()).is_err() {
+                problems.push(format!(
+                    "cors.allowed_methods has an invalid method {method}"
+                ));
+            }
+        }
+        for header in &self.cors.allowed_headers {
+            if http::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                problems.push(format!(
+                    "cors.allowed_headers has an invalid header {header}"
+                ));
+            }
+        }
+        // Browsers reject a wildcard origin on credentialed requests
+        if self.cors.allowed_origins.iter().any(|o| o == "*") {
+            problems.push("cors.allowed_origins can not contain '*'".to_string());
+        }
+        let buckets = [
+            ("auth", self.rate_limit.auth),
+            ("query", self.rate_limit.query),
+            ("runs", self.rate_limit.runs),
+            ("ingestion", self.rate_limit.ingestion),
+        ];
+        for (name, bucket) in buckets {
+            if bucket.capacity == 0 || bucket.refill_per_minute == 0 {
+                problems.push(format!("rate_limit.{name} must allow at least one request"));
+            }
+        }
+        let multipliers = self.rate_limit.plan_multipliers;
+        if [multipliers.free, multipliers.team, multipliers.enterprise].contains(&0) {
+            problems.push("rate_limit.plan_multipliers must be at least 1".to_string());
+        }
+        if self.exports.max_connections == 0 || self.exports.send_timeout_secs == 0 {
+            problems.push(
+                "exports.max_connections and exports.send_timeout_secs must be at least 1"
+                    .to_string(),
+            );
+        }
+        if self.deletion.purge_interval_secs == 0 {
+            problems.push("deletion.purge_interval_secs must be at least 1".to_string());
+        }
+        if self.retention.interval_secs == 0 {
+            problems.push("retention.interval_secs must be at least 1".to_string());
+        }
+        // A p-value is never above 1, and Welch's test needs a sample variance on both sides
+        let regressions = self.regressions;
+        if !(regressions.significance > 0.0 && regressions.significance < 1.0) {
+            problems.push("regressions.significance must be between 0 and 1".to_string());
+        }
+        if regressions.min_samples < 2 {
+            problems.push("regressions.min_samples must be at least 2".to_string());
+        }
+        if matches!(self.session.same_site, SameSitePolicy::None) && !self.session.secure {
+            problems.push("session.same_site none requires session.secure".to_string());
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(StartupError::InvalidConfig(problems.join("; "))),
+        }
+    }
 }
 
 impl DatabaseSettings {
-    pub fn connection_string(&self) -> String {
+    pub fn connection_string(&self) -> Secret<String> {
         self.url.clone()
     }
 }
@@ -48,9 +444,6 @@ impl ApplicationSettings {
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
-    let config_dir = base_path.join("configuration");
-
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into()
@@ -69,17 +462,75 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         }
     }
 
+    configuration_from(environment, environment_variables()?)
+}
+
+/// Settings of `environment`, with `vars` in place of the process environment.
+pub fn configuration_from(
+    environment: Environment,
+    vars: HashMap<String, String>,
+) -> Result<Settings, config::ConfigError> {
+    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
+    let config_dir = base_path.join("configuration");
+
     let settings = Config::builder()
         .add_source(config::File::from(config_dir.join("base")).required(true))
         .add_source(config::File::from(config_dir.join(environment.as_str())).required(true))
         //E.g 'APPLICATION_PORT=5001 would set 'Settings.application.port'
-        .add_source(config::Environment::with_prefix("APP").separator("_"))
-        .add_source(config::Environment::with_prefix("DB").separator("_"))
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .separator("_")
+                .source(Some(vars.clone())),
+        )
+        // Keys that contain '_' need '__' between the levels,
+        // e.g. 'APP_CORS__ALLOWED_ORIGINS=https://a.dev,https://*.b.dev'
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__")
+                .list_separator(",")
+                .with_list_parse_key("cors.allowed_origins")
+                .with_list_parse_key("cors.allowed_methods")
+                .with_list_parse_key("cors.allowed_headers")
+                .try_parsing(true)
+                .source(Some(vars.clone())),
+        )
+        .add_source(
+            config::Environment::with_prefix("DB")
+                .separator("_")
+                .source(Some(vars.clone())),
+        )
+        .set_override_option("database.url", vars.get("DATABASE_URL").cloned())?
         .build()?;
 
     settings.try_deserialize::<Settings>()
 }
 
+/// The environment, with `X` set to the content of the file named by `X_FILE` for Docker
+/// and Kubernetes secrets.
+///
+/// Only `APP_*`, `DB_*` and `DATABASE_URL` are read from files, and a variable that is set wins.
+fn environment_variables() -> Result<HashMap<String, String>, config::ConfigError> {
+    let mut vars: HashMap<String, String> = std::env::vars().collect();
+    let files: Vec<(String, String)> = vars
+        .iter()
+        .filter_map(|(name, path)| Some((name.strip_suffix("_FILE")?, path)))
+        .filter(|(target, _)| {
+            target.starts_with("APP_") || target.starts_with("DB_") || *target == "DATABASE_URL"
+        })
+        .filter(|(target, _)| !vars.contains_key(*target))
+        .map(|(target, path)| (target.to_string(), path.clone()))
+        .collect();
+
+    for (target, path) in files {
+        let value = std::fs::read_to_string(&path).map_err(|e| {
+            config::ConfigError::Message(format!("Could not read {target}_FILE: {e}"))
+        })?;
+        vars.insert(target, value.trim_end_matches(['\n', '\r']).to_string());
+    }
+    Ok(vars)
+}
+
 pub enum Environment {
     Local,
     Production,
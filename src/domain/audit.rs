@@ -0,0 +1,20 @@
+use crate::util::pagination::{default_limit, MAX_LIMIT};
+use prisma_client_rust::chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    pub action: Option<String>,
+    pub actor_id: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub from: Option<DateTime<FixedOffset>>,
+    pub to: Option<DateTime<FixedOffset>>,
+    #[validate(range(min = 1, max = "MAX_LIMIT"))]
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    pub cursor: Option<String>,
+}
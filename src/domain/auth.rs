@@ -1,11 +1,12 @@
 use super::{MAX_NAME_LENGTH, MIN_NAME_LENGTH};
-use crate::prisma::{organization, user, PrismaClient};
+use crate::prisma::{organization, user, Plan, PrismaClient, UserRole};
 use axum::async_trait;
 use axum_login::{secrecy::SecretVec, AuthUser, UserStore};
 use prisma_client_rust::chrono;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use unicode_segmentation::UnicodeSegmentation;
+use utoipa::ToSchema;
 use validator::{Validate, ValidationError};
 
 pub type AuthContext = axum_login::extractors::AuthContext<TonsailUser, TonsailUserStore>;
@@ -16,6 +17,7 @@ pub struct TonsailUser {
     name: String,
     email: String,
     pub password: String,
+    role: UserRole,
     #[serde(rename = "createdAt")]
     created_at: chrono::DateTime<chrono::FixedOffset>,
     #[serde(rename = "updatedAt")]
@@ -32,6 +34,7 @@ impl From<user::Data> for TonsailUser {
             name: u.name,
             email: u.email,
             password: u.password,
+            role: u.role,
             created_at: u.created_at,
             updated_at: u.updated_at,
             organization: u.organization,
@@ -40,6 +43,29 @@ impl From<user::Data> for TonsailUser {
     }
 }
 
+impl TonsailUser {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn role(&self) -> UserRole {
+        self.role
+    }
+
+    pub fn organization_id(&self) -> &str {
+        &self.organization_id
+    }
+
+    /// Plan of the user's organization, `Free` when it was not loaded.
+    pub fn plan(&self) -> Plan {
+        self.organization.as_ref().map_or(Plan::Free, |o| o.plan)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TonsailUserStore {
     db_client: Arc<PrismaClient>,
@@ -62,7 +88,11 @@ where
         let user = self
             .db_client
             .user()
-            .find_unique(user::id::equals(String::from(user_id)))
+            .find_first(vec![
+                user::id::equals(String::from(user_id)),
+                user::deleted_at::equals(None),
+            ])
+            .with(user::organization::fetch())
             .exec()
             .await?;
 
@@ -92,14 +122,14 @@ where
     }
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Validate, Serialize, Deserialize, ToSchema)]
 pub struct AuthLoginForm {
     #[validate(email)]
     pub email: String,
     pub password: String,
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Validate, Serialize, Deserialize, ToSchema)]
 pub struct AuthRegisterForm {
     #[validate(length(min = "MIN_NAME_LENGTH", max = "MAX_NAME_LENGTH"))]
     pub name: String,
@@ -0,0 +1,84 @@
+use crate::{
+    prisma::{metrics_catalog, MetricPolicy, PrismaClient},
+    util::app_error::AppError,
+};
+use prisma_client_rust::{or, QueryError};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+const MAX_METRIC_NAME_LENGTH: usize = 50;
+
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+pub struct CatalogForm {
+    #[validate(length(min = 1, max = 50))]
+    pub label: String,
+    /// Metric name the samples are ingested under
+    #[validate(custom(function = "validate_metric_name"))]
+    pub value: String,
+    #[validate(length(min = 1, max = 50))]
+    pub group: String,
+    #[validate(length(min = 1, max = 20))]
+    pub unit: String,
+    #[serde(default)]
+    #[validate(length(max = 255))]
+    pub description: String,
+}
+
+/// Metric names as k6 emits them: a letter or `_`, then letters, digits or `_`.
+pub fn validate_metric_name(name: &str) -> Result<(), ValidationError> {
+    let mut chars = name.chars();
+    let starts_well = chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_');
+    let rest_is_valid = chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !starts_well || !rest_is_valid || name.len() > MAX_METRIC_NAME_LENGTH {
+        return Err(ValidationError::new(
+            "metric names take up to 50 letters, digits or underscores, not starting with a digit",
+        ));
+    }
+    Ok(())
+}
+
+/// Catalog entries an organization sees, the built-in ones and its own.
+pub fn visible_to(org_id: &str) -> Vec<metrics_catalog::WhereParam> {
+    vec![or![
+        metrics_catalog::organization_id::equals(None),
+        metrics_catalog::organization_id::equals(Some(org_id.to_string())),
+    ]]
+}
+
+/// Names, out of `names`, that neither the built-in catalog nor the organization has.
+pub async fn unknown_metrics<'a>(
+    client: &PrismaClient,
+    org_id: &str,
+    names: impl IntoIterator<Item = &'a str>,
+) -> Result<BTreeSet<String>, QueryError> {
+    let mut unknown: BTreeSet<String> = names.into_iter().map(str::to_string).collect();
+    let mut filter = visible_to(org_id);
+    filter.push(metrics_catalog::value::in_vec(
+        unknown.iter().cloned().collect(),
+    ));
+    for entry in client.metrics_catalog().find_many(filter).exec().await? {
+        unknown.remove(&entry.value);
+    }
+    Ok(unknown)
+}
+
+/// Applies the organization's policy to unknown names, returning the ones to tag.
+pub fn screen_unknown_metrics(
+    policy: MetricPolicy,
+    unknown: BTreeSet<String>,
+) -> Result<BTreeSet<String>, AppError> {
+    match policy {
+        _ if unknown.is_empty() => Ok(unknown),
+        MetricPolicy::Accept => Ok(BTreeSet::new()),
+        MetricPolicy::Tag => Ok(unknown),
+        MetricPolicy::Reject => Err(AppError::BadRequest(format!(
+            "the metrics {} are not in the catalog",
+            unknown.into_iter().collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
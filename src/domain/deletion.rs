@@ -0,0 +1,137 @@
+// Every record below the deleted one gets the same `deletedAt`,
+// so the purge job removes the whole tree once the grace period ends.
+use crate::prisma::{organization, project, test, test_run, user, PrismaClient};
+use prisma_client_rust::{
+    chrono::{DateTime, FixedOffset},
+    QueryError,
+};
+
+/// Returns the number of matching records that were not deleted yet.
+pub async fn soft_delete_run(
+    client: &PrismaClient,
+    run_id: String,
+    at: DateTime<FixedOffset>,
+) -> Result<i64, QueryError> {
+    client
+        .test_run()
+        .update_many(
+            vec![
+                test_run::id::equals(run_id),
+                test_run::deleted_at::equals(None),
+            ],
+            vec![test_run::deleted_at::set(Some(at))],
+        )
+        .exec()
+        .await
+}
+
+pub async fn soft_delete_test(
+    client: &PrismaClient,
+    test_id: String,
+    at: DateTime<FixedOffset>,
+) -> Result<i64, QueryError> {
+    client
+        .test_run()
+        .update_many(
+            vec![
+                test_run::test_id::equals(test_id.clone()),
+                test_run::deleted_at::equals(None),
+            ],
+            vec![test_run::deleted_at::set(Some(at))],
+        )
+        .exec()
+        .await?;
+
+    client
+        .test()
+        .update_many(
+            vec![test::id::equals(test_id), test::deleted_at::equals(None)],
+            vec![test::deleted_at::set(Some(at))],
+        )
+        .exec()
+        .await
+}
+
+pub async fn soft_delete_project(
+    client: &PrismaClient,
+    project_id: String,
+    at: DateTime<FixedOffset>,
+) -> Result<i64, QueryError> {
+    client
+        .test_run()
+        .update_many(
+            vec![
+                test_run::test::is(vec![test::project_id::equals(project_id.clone())]),
+                test_run::deleted_at::equals(None),
+            ],
+            vec![test_run::deleted_at::set(Some(at))],
+        )
+        .exec()
+        .await?;
+
+    client
+        .test()
+        .update_many(
+            vec![
+                test::project_id::equals(project_id.clone()),
+                test::deleted_at::equals(None),
+            ],
+            vec![test::deleted_at::set(Some(at))],
+        )
+        .exec()
+        .await?;
+
+    client
+        .project()
+        .update_many(
+            vec![
+                project::id::equals(project_id),
+                project::deleted_at::equals(None),
+            ],
+            vec![project::deleted_at::set(Some(at))],
+        )
+        .exec()
+        .await
+}
+
+pub async fn soft_delete_organization(
+    client: &PrismaClient,
+    org_id: String,
+    at: DateTime<FixedOffset>,
+) -> Result<i64, QueryError> {
+    let projects = client
+        .project()
+        .find_many(vec![
+            project::organization_id::equals(org_id.clone()),
+            project::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?;
+    for p in projects {
+        soft_delete_project(client, p.id, at).await?;
+    }
+
+    client
+        .user()
+        .update_many(
+            vec![
+                user::organization_id::equals(org_id.clone()),
+                user::deleted_at::equals(None),
+            ],
+            vec![user::deleted_at::set(Some(at))],
+        )
+        .exec()
+        .await?;
+
+    client
+        .organization()
+        .update_many(
+            vec![
+                organization::id::equals(org_id),
+                organization::deleted_at::equals(None),
+            ],
+            vec![organization::deleted_at::set(Some(at))],
+        )
+        .exec()
+        .await
+}
@@ -0,0 +1,129 @@
+use crate::domain::catalog::validate_metric_name;
+use crate::prisma::{audit_log, data_export, organization, token, user, ExportStatus, UserRole};
+use crate::questdb::export::{ExportFormat, SampleFilter};
+use crate::routes::V1_PREFIX;
+use axum_login::axum_sessions::async_session::Session;
+use fred::types::RedisKey;
+use prisma_client_rust::chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportResponse {
+    id: String,
+    #[schema(value_type = crate::domain::schemas::ExportStatus)]
+    status: ExportStatus,
+    created_at: DateTime<FixedOffset>,
+    expires_at: DateTime<FixedOffset>,
+    download_url: Option<String>,
+}
+
+impl From<data_export::Data> for ExportResponse {
+    fn from(e: data_export::Data) -> Self {
+        Self {
+            download_url: (e.status == ExportStatus::Ready)
+                .then(|| format!("{V1_PREFIX}/exports/{}", e.token)),
+            id: e.id,
+            status: e.status,
+            created_at: e.created_at,
+            expires_at: e.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[validate(schema(function = "validate_export_window"))]
+pub struct RunExportQuery {
+    #[param(value_type = crate::questdb::export::ExportFormat)]
+    pub format: ExportFormat,
+    /// Only samples of this metric
+    #[validate(custom(function = "validate_metric_name"))]
+    pub name: Option<String>,
+    /// Samples taken at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Samples taken before this time
+    pub to: Option<DateTime<Utc>>,
+}
+
+fn validate_export_window(query: &RunExportQuery) -> Result<(), ValidationError> {
+    match (query.from, query.to) {
+        (Some(from), Some(to)) if from >= to => Err(ValidationError::new("from must be before to")),
+        _ => Ok(()),
+    }
+}
+
+impl RunExportQuery {
+    pub fn filter(&self) -> SampleFilter {
+        SampleFilter {
+            name: self.name.clone(),
+            from: self.from,
+            to: self.to,
+        }
+    }
+}
+
+/// Everything the server holds about a user, minus the password hash.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserArchive {
+    generated_at: DateTime<Utc>,
+    user: ArchivedUser,
+    organization: Option<organization::Data>,
+    tokens: Vec<token::Data>,
+    sessions: Vec<ArchivedSession>,
+    audit: Vec<audit_log::Data>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArchivedUser {
+    id: String,
+    email: String,
+    name: String,
+    role: UserRole,
+    created_at: DateTime<FixedOffset>,
+    updated_at: DateTime<FixedOffset>,
+    organization_id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArchivedSession {
+    id: String,
+    expires_in_secs: Option<u64>,
+}
+
+impl UserArchive {
+    /// Expects the user to be fetched with its organization and tokens.
+    pub fn new(
+        user: user::Data,
+        sessions: Vec<(RedisKey, Session)>,
+        audit: Vec<audit_log::Data>,
+    ) -> Self {
+        Self {
+            audit,
+            generated_at: Utc::now(),
+            organization: user.organization.map(|o| *o),
+            tokens: user.tokens.unwrap_or_default(),
+            sessions: sessions
+                .into_iter()
+                .map(|(_, s)| ArchivedSession {
+                    id: s.id().to_string(),
+                    expires_in_secs: s.expires_in().map(|d| d.as_secs()),
+                })
+                .collect(),
+            user: ArchivedUser {
+                id: user.id,
+                email: user.email,
+                name: user.name,
+                role: user.role,
+                created_at: user.created_at,
+                updated_at: user.updated_at,
+                organization_id: user.organization_id,
+            },
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use crate::domain::catalog::{screen_unknown_metrics, unknown_metrics, validate_metric_name};
+use crate::prisma::{organization, PrismaClient};
+use crate::questdb::{insert_samples, Sample};
+use crate::util::app_error::AppError;
+use prisma_client_rust::chrono::{DateTime, TimeZone, Utc};
+use quick_xml::events::{BytesStart, Event};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{Pool, Postgres};
+use std::collections::{BTreeSet, HashSet};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+/// Most samples written to QuestDB at once.
+const BATCH_SIZE: usize = 5000;
+
+/// k6 metrics and the names they are stored under, the others keep their name.
+const K6_METRICS: &[(&str, &str)] = &[
+    ("http_req_duration", "http_response_rate"),
+    ("http_req_failed", "http_failure_rate"),
+    ("http_reqs", "http_request_rate"),
+    ("http_req_waiting", "http_waiting_time"),
+    ("http_req_tls_handshaking", "http_handshake_time"),
+    ("http_req_blocked", "http_blocked_time"),
+    ("data_sent", "http_sent_bytes"),
+    ("data_received", "http_recv_bytes"),
+    ("vus", "vus"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// NDJSON written by `k6 run --out json`
+    K6,
+    /// JMeter results, CSV or XML
+    Jtl,
+}
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    #[param(value_type = ImportFormat)]
+    pub format: ImportFormat,
+}
+
+fn bad_line(line: usize, reason: impl std::fmt::Display) -> AppError {
+    AppError::BadRequest(format!("line {line}: {reason}"))
+}
+
+fn read_error(e: impl std::fmt::Display) -> AppError {
+    AppError::BadRequest(format!("could not read the upload: {e}"))
+}
+
+/// What the imported samples tell about the run.
+#[derive(Debug, Default)]
+pub struct ImportStats {
+    pub samples: i64,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub max_vus: f64,
+}
+
+impl ImportStats {
+    /// Seconds between the first and the last sample.
+    pub fn duration_secs(&self) -> i64 {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => (last - first).num_seconds(),
+            _ => 0,
+        }
+    }
+
+    /// Virtual users the run peaked at, at least one.
+    pub fn vus(&self) -> u32 {
+        self.max_vus.max(1.0) as u32
+    }
+}
+
+/// Screens and writes parsed samples to QuestDB in batches.
+pub struct ImportSink<'a> {
+    db_client: &'a PrismaClient,
+    pg_client: &'a Pool<Postgres>,
+    org: &'a organization::Data,
+    run_id: &'a str,
+    batch: Vec<Sample>,
+    catalogued: HashSet<String>,
+    uncatalogued: BTreeSet<String>,
+    stats: ImportStats,
+}
+
+impl<'a> ImportSink<'a> {
+    pub fn new(
+        db_client: &'a PrismaClient,
+        pg_client: &'a Pool<Postgres>,
+        org: &'a organization::Data,
+        run_id: &'a str,
+    ) -> Self {
+        Self {
+            db_client,
+            pg_client,
+            org,
+            run_id,
+            batch: Vec::with_capacity(BATCH_SIZE),
+            catalogued: HashSet::new(),
+            uncatalogued: BTreeSet::new(),
+            stats: ImportStats::default(),
+        }
+    }
+
+    pub async fn push(&mut self, sample: Sample) -> Result<(), AppError> {
+        let stats = &mut self.stats;
+        stats.samples += 1;
+        stats.first = Some(stats.first.map_or(sample.ts, |t| t.min(sample.ts)));
+        stats.last = Some(stats.last.map_or(sample.ts, |t| t.max(sample.ts)));
+        if sample.name == "vus" {
+            stats.max_vus = stats.max_vus.max(sample.value);
+        }
+        self.batch.push(sample);
+        if self.batch.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> Result<ImportStats, AppError> {
+        self.flush().await?;
+        Ok(self.stats)
+    }
+
+    /// Applies the organization's catalog policy to names not seen before, then writes.
+    async fn flush(&mut self) -> Result<(), AppError> {
+        let new: BTreeSet<&str> = self
+            .batch
+            .iter()
+            .map(|s| s.name.as_str())
+            .filter(|n| !self.catalogued.contains(*n) && !self.uncatalogued.contains(*n))
+            .collect();
+        if let Some(name) = new.iter().find(|n| validate_metric_name(n).is_err()) {
+            return Err(AppError::BadRequest(format!(
+                "{name} is not a valid metric name"
+            )));
+        }
+        if !new.is_empty() {
+            let unknown =
+                unknown_metrics(self.db_client, &self.org.id, new.iter().copied()).await?;
+            let tagged = screen_unknown_metrics(self.org.unknown_metrics, unknown)?;
+            let catalogued: Vec<String> = new
+                .iter()
+                .filter(|n| !tagged.contains(**n))
+                .map(|n| n.to_string())
+                .collect();
+            self.catalogued.extend(catalogued);
+            self.uncatalogued.extend(tagged);
+        }
+
+        insert_samples(self.pg_client, self.run_id, &self.batch, &self.uncatalogued).await?;
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+/// Reads k6 NDJSON, where only the `Point` lines hold samples.
+pub async fn read_k6<R>(reader: R, sink: &mut ImportSink<'_>) -> Result<(), AppError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut lines = reader.lines();
+    let mut number = 0;
+    while let Some(line) = lines.next_line().await.map_err(read_error)? {
+        number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(sample) = k6_sample(&line).map_err(|e| bad_line(number, e))? {
+            sink.push(sample).await?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct K6Line {
+    #[serde(rename = "type")]
+    kind: String,
+    metric: String,
+    #[serde(default)]
+    data: Value,
+}
+
+#[derive(Deserialize)]
+struct K6Point {
+    time: DateTime<Utc>,
+    value: f64,
+    #[serde(default)]
+    tags: Option<Map<String, Value>>,
+}
+
+fn k6_sample(line: &str) -> Result<Option<Sample>, serde_json::Error> {
+    let line: K6Line = serde_json::from_str(line)?;
+    if line.kind != "Point" {
+        return Ok(None);
+    }
+    let point: K6Point = serde_json::from_value(line.data)?;
+    let tags = point.tags.unwrap_or_default();
+    let tag = |key: &str| {
+        tags.get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let name = K6_METRICS
+        .iter()
+        .find(|(k6, _)| *k6 == line.metric)
+        .map_or(line.metric.as_str(), |(_, name)| name);
+    // k6 reports failures as a 0 or 1 rate, the catalog as a percentage
+    let value = match name {
+        "http_failure_rate" => point.value * 100.0,
+        _ => point.value,
+    };
+    Ok(Some(Sample {
+        name: name.to_string(),
+        scenario: tag("scenario"),
+        url: tag("url"),
+        method: tag("method"),
+        status: tag("status"),
+        ts: point.time,
+        value,
+    }))
+}
+
+/// One JMeter sample result, from a CSV row or an XML element.
+#[derive(Debug, Default)]
+struct JtlRecord {
+    ts_ms: Option<i64>,
+    elapsed: Option<f64>,
+    latency: Option<f64>,
+    connect: Option<f64>,
+    bytes: Option<f64>,
+    sent_bytes: Option<f64>,
+    all_threads: Option<f64>,
+    success: Option<bool>,
+    label: String,
+    code: String,
+    url: String,
+    method: String,
+}
+
+impl JtlRecord {
+    fn set(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let number = || {
+            value
+                .trim()
+                .parse::<f64>()
+                .map(Some)
+                .map_err(|_| format!("{field} is not a number"))
+        };
+        match field {
+            "timeStamp" | "ts" => {
+                let ts = value.trim().parse::<i64>();
+                self.ts_ms = Some(ts.map_err(|_| format!("{field} is not in milliseconds"))?);
+            }
+            "elapsed" | "t" => self.elapsed = number()?,
+            "Latency" | "lt" => self.latency = number()?,
+            "Connect" | "ct" => self.connect = number()?,
+            "bytes" | "by" => self.bytes = number()?,
+            "sentBytes" | "sby" => self.sent_bytes = number()?,
+            "allThreads" | "na" => self.all_threads = number()?,
+            "success" | "s" => self.success = Some(value.trim() == "true"),
+            "label" | "lb" => self.label = value.to_string(),
+            "responseCode" | "rc" => self.code = value.to_string(),
+            "URL" | "java.net.URL" => self.url = value.to_string(),
+            "method" => self.method = value.to_string(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// One sample per measurement, VUs without the request tags.
+    fn samples(self) -> Result<Vec<Sample>, String> {
+        let ts_ms = self.ts_ms.ok_or("the sample has no timestamp")?;
+        let ts = Utc
+            .timestamp_millis_opt(ts_ms)
+            .single()
+            .ok_or("the timestamp is out of range")?;
+        let request = |name: &str, value: f64| Sample {
+            name: name.to_string(),
+            scenario: self.label.clone(),
+            url: self.url.clone(),
+            method: self.method.clone(),
+            status: self.code.clone(),
+            ts,
+            value,
+        };
+
+        let mut samples = vec![request("http_request_rate", 1.0)];
+        let measured = [
+            ("http_response_rate", self.elapsed),
+            ("http_waiting_time", self.latency),
+            ("http_handshake_time", self.connect),
+            ("http_recv_bytes", self.bytes),
+            ("http_sent_bytes", self.sent_bytes),
+            (
+                "http_failure_rate",
+                self.success.map(|s| if s { 0.0 } else { 100.0 }),
+            ),
+        ];
+        for (name, value) in measured {
+            if let Some(value) = value {
+                samples.push(request(name, value));
+            }
+        }
+        if let Some(threads) = self.all_threads {
+            samples.push(Sample {
+                name: "vus".to_string(),
+                scenario: String::new(),
+                url: String::new(),
+                method: String::new(),
+                status: String::new(),
+                ts,
+                value: threads,
+            });
+        }
+        Ok(samples)
+    }
+}
+
+/// Reads a JTL file, telling CSV from XML by its first character.
+pub async fn read_jtl<R>(mut reader: R, sink: &mut ImportSink<'_>) -> Result<(), AppError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let is_xml = loop {
+        let buf = reader.fill_buf().await.map_err(read_error)?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let blank = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        if blank < buf.len() {
+            break buf[blank] == b'<';
+        }
+        reader.consume(blank);
+    };
+    if is_xml {
+        read_jtl_xml(reader, sink).await
+    } else {
+        read_jtl_csv(reader, sink).await
+    }
+}
+
+async fn read_jtl_csv<R>(reader: R, sink: &mut ImportSink<'_>) -> Result<(), AppError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut lines = reader.lines();
+    let mut header: Option<Vec<String>> = None;
+    let mut record = String::new();
+    let mut number = 0;
+    while let Some(line) = lines.next_line().await.map_err(read_error)? {
+        number += 1;
+        if !record.is_empty() {
+            record.push('\n');
+        }
+        record.push_str(&line);
+        // A quoted field may hold line breaks
+        if record.matches('"').count() % 2 == 1 {
+            continue;
+        }
+        let fields = split_csv(&std::mem::take(&mut record));
+        let columns = match &header {
+            Some(columns) => columns,
+            None => {
+                if !fields.iter().any(|f| f == "timeStamp") {
+                    return Err(bad_line(number, "the header has no timeStamp column"));
+                }
+                header = Some(fields);
+                continue;
+            }
+        };
+        if fields.iter().all(|f| f.is_empty()) {
+            continue;
+        }
+
+        let mut jtl = JtlRecord::default();
+        for (column, value) in columns.iter().zip(&fields) {
+            jtl.set(column, value).map_err(|e| bad_line(number, e))?;
+        }
+        for sample in jtl.samples().map_err(|e| bad_line(number, e))? {
+            sink.push(sample).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits one CSV record, unquoting fields.
+fn split_csv(record: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+async fn read_jtl_xml<R>(reader: R, sink: &mut ImportSink<'_>) -> Result<(), AppError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut xml = quick_xml::Reader::from_reader(reader);
+    xml.trim_text(true);
+    let bad_xml = |xml: &quick_xml::Reader<R>, e: &dyn std::fmt::Display| {
+        AppError::BadRequest(format!("byte {}: {e}", xml.buffer_position()))
+    };
+
+    // Samples nest, e.g. the redirects of a request. Only the top-level ones are kept, as in
+    // JMeter's CSV output, their children are already part of their time.
+    let mut open: Vec<JtlRecord> = vec![];
+    let mut field: Option<&'static str> = None;
+    let mut buf = vec![];
+    loop {
+        let event = match xml.read_event_into_async(&mut buf).await {
+            Ok(event) => event,
+            Err(e) => return Err(bad_xml(&xml, &e)),
+        };
+        let finished = match event {
+            Event::Start(e) if is_sample(&e) => {
+                open.push(jtl_attributes(&e).map_err(|r| bad_xml(&xml, &r))?);
+                None
+            }
+            Event::Empty(e) if is_sample(&e) => {
+                let record = jtl_attributes(&e).map_err(|r| bad_xml(&xml, &r))?;
+                open.is_empty().then_some(record)
+            }
+            Event::Start(e) => {
+                field = match e.name().as_ref() {
+                    b"java.net.URL" => Some("URL"),
+                    b"method" => Some("method"),
+                    _ => None,
+                };
+                None
+            }
+            Event::Text(text) => {
+                if let (Some(name), Some(record)) = (field, open.last_mut()) {
+                    let text = text.unescape().map_err(|e| bad_xml(&xml, &e))?;
+                    record.set(name, &text).map_err(|r| bad_xml(&xml, &r))?;
+                }
+                None
+            }
+            Event::End(e) if matches!(e.name().as_ref(), b"httpSample" | b"sample") => {
+                field = None;
+                open.pop().filter(|_| open.is_empty())
+            }
+            Event::End(_) => {
+                field = None;
+                None
+            }
+            Event::Eof => break,
+            _ => None,
+        };
+
+        if let Some(record) = finished {
+            for sample in record.samples().map_err(|r| bad_xml(&xml, &r))? {
+                sink.push(sample).await?;
+            }
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+fn is_sample(e: &BytesStart) -> bool {
+    matches!(e.name().as_ref(), b"httpSample" | b"sample")
+}
+
+fn jtl_attributes(e: &BytesStart) -> Result<JtlRecord, String> {
+    let mut record = JtlRecord::default();
+    for attribute in e.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        let value = attribute.unescape_value().map_err(|e| e.to_string())?;
+        record.set(&key, &value)?;
+    }
+    Ok(record)
+}
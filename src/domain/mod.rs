@@ -1,6 +1,18 @@
+pub mod audit;
 pub mod auth;
+pub mod catalog;
+pub mod deletion;
+pub mod export;
+pub mod import;
+pub mod oidc;
 pub mod organization;
+pub mod regression;
+pub mod report;
+pub mod schemas;
+pub mod share;
+pub mod usage;
 pub mod user;
+pub mod webhook;
 
 const MIN_NAME_LENGTH: u8 = 2;
 const MAX_NAME_LENGTH: u8 = 90;
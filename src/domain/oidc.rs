@@ -0,0 +1,60 @@
+use crate::prisma::UserRole;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+pub struct OidcProviderForm {
+    #[validate(url)]
+    pub issuer: String,
+    #[validate(length(min = 1, max = 255))]
+    pub client_id: String,
+    #[validate(length(max = 255))]
+    pub client_secret: Option<String>,
+    #[validate(url)]
+    pub redirect_uri: String,
+    #[validate(length(min = 3, max = 255))]
+    pub email_domain: String,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SsoLoginQuery {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SsoCallbackQuery {
+    pub code: String,
+    pub state: String,
+}
+
+/// Login attempt kept in Redis between the redirect to the IdP and the callback.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingSsoLogin {
+    pub provider_id: String,
+    /// SHA-256 of the cookie that ties the login to the browser that started it
+    pub browser_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// Rank of a role, each may do what the ones below it do.
+pub fn role_rank(role: UserRole) -> u8 {
+    match role {
+        UserRole::Member => 0,
+        UserRole::Admin => 1,
+        UserRole::Owner => 2,
+    }
+}
+
+/// Returns the lowercased domain part of an email address.
+pub fn email_domain(email: &str) -> Option<String> {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
@@ -1,11 +1,88 @@
+use crate::prisma::{organization, project, test, test_run, MetricPolicy, PrismaClient};
+use prisma_client_rust::{raw, PrismaValue, QueryError};
 use serde::Deserialize;
+use utoipa::ToSchema;
 use validator::Validate;
 
 const MIN_NAME_LENGTH: u8 = 2;
 const MAX_NAME_LENGTH: u8 = 90;
 
-#[derive(Debug, Validate, Deserialize)]
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct OrgUpdateForm {
     #[validate(length(min = "MIN_NAME_LENGTH", max = "MAX_NAME_LENGTH"))]
     pub name: String,
+    /// Days raw samples are kept, at most the plan's retention
+    #[validate(range(min = 1))]
+    pub retention_days: Option<u32>,
+    /// What ingestion does with metric names missing from the catalog
+    #[schema(value_type = Option<crate::domain::schemas::MetricPolicy>)]
+    pub unknown_metrics: Option<MetricPolicy>,
+}
+
+/// Locks the organization's row until the transaction ends.
+///
+/// Checks over its users or runs that take it first run one after the other, so concurrent
+/// requests can not all pass them.
+pub async fn lock_organization(client: &PrismaClient, org_id: &str) -> Result<(), QueryError> {
+    client
+        ._query_raw::<serde_json::Value>(raw!(
+            "SELECT id FROM Organization WHERE id = {} FOR UPDATE",
+            PrismaValue::String(org_id.to_string())
+        ))
+        .exec()
+        .await?;
+    Ok(())
+}
+
+/// Returns the id of the organization that owns the test.
+pub async fn organization_of_test(
+    client: &PrismaClient,
+    test_id: &str,
+) -> Result<Option<String>, QueryError> {
+    let org = client
+        .organization()
+        .find_first(vec![organization::projects::some(vec![
+            project::tests::some(vec![test::id::equals(test_id.to_string())]),
+        ])])
+        .exec()
+        .await?;
+    Ok(org.map(|o| o.id))
+}
+
+/// Returns the id of the organization that owns the test run.
+pub async fn organization_of_run(
+    client: &PrismaClient,
+    run_id: &str,
+) -> Result<Option<String>, QueryError> {
+    let org = client
+        .organization()
+        .find_first(vec![organization::projects::some(vec![
+            project::tests::some(vec![test::runs::some(vec![test_run::id::equals(
+                run_id.to_string(),
+            )])]),
+        ])])
+        .exec()
+        .await?;
+    Ok(org.map(|o| o.id))
+}
+
+/// Returns the run, fetched with its test, when it is live and the organization owns it.
+pub async fn find_organization_run(
+    client: &PrismaClient,
+    org_id: &str,
+    run_id: &str,
+) -> Result<Option<test_run::Data>, QueryError> {
+    client
+        .test_run()
+        .find_first(vec![
+            test_run::id::equals(run_id.to_string()),
+            test_run::deleted_at::equals(None),
+            test_run::test::is(vec![test::project::is(vec![
+                project::organization_id::equals(org_id.to_string()),
+            ])]),
+        ])
+        .with(test_run::test::fetch())
+        .exec()
+        .await
 }
@@ -0,0 +1,267 @@
+use crate::configuration::RegressionSettings;
+use crate::domain::report::report_resolution;
+use crate::prisma::{regression, test, test_run, PrismaClient, RunStatus};
+use crate::questdb::summary::{metric_moments, MetricMoments};
+use crate::util::app_error::AppError;
+use crate::util::nano_id::generate_id;
+use prisma_client_rust::QueryError;
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::collections::BTreeMap;
+use tracing::instrument;
+use utoipa::ToSchema;
+use validator::Validate;
+
+/// Metrics compared with the baseline, each of them gets worse as it grows.
+const COMPARED_METRICS: &[&str] = &[
+    "http_response_rate",
+    "http_waiting_time",
+    "http_handshake_time",
+    "http_blocked_time",
+    "http_failure_rate",
+];
+
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BaselineForm {
+    /// A finished run of the test
+    #[validate(length(equal = 12))]
+    pub run_id: String,
+    /// Percent a metric may get worse by, the server default applies when empty
+    #[validate(range(min = 0.0, max = 1000.0))]
+    pub tolerance_pct: Option<f64>,
+}
+
+/// Running sums of a metric, over the whole run or against one URL.
+#[derive(Debug, Clone, Copy)]
+struct Moments {
+    count: i64,
+    sum: f64,
+    sq_sum: Option<f64>,
+}
+
+impl Moments {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            sq_sum: Some(0.0),
+        }
+    }
+
+    fn add(&mut self, row: &MetricMoments) {
+        self.count += row.count;
+        self.sum += row.sum;
+        self.sq_sum = self.sq_sum.zip(row.sq_sum).map(|(a, b)| a + b);
+    }
+
+    fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    /// Sample variance, unknown for rollups without squared sums.
+    fn variance(&self) -> Option<f64> {
+        let n = self.count as f64;
+        match self.sq_sum {
+            Some(sq_sum) if self.count > 1 => {
+                Some(((sq_sum - self.sum * self.sum / n) / (n - 1.0)).max(0.0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Moments keyed by metric and URL, `None` standing for the whole run.
+type RunMoments = BTreeMap<(String, Option<String>), Moments>;
+
+fn group_moments(rows: Vec<MetricMoments>) -> RunMoments {
+    let mut moments = RunMoments::new();
+    for row in rows {
+        moments
+            .entry((row.name.clone(), None))
+            .or_insert_with(Moments::new)
+            .add(&row);
+        if let Some(url) = row.url.as_ref().filter(|u| !u.is_empty()) {
+            moments
+                .entry((row.name.clone(), Some(url.clone())))
+                .or_insert_with(Moments::new)
+                .add(&row);
+        }
+    }
+    moments
+}
+
+/// A metric that got worse by more than the tolerance, and not by chance.
+#[derive(Debug)]
+struct Finding {
+    metric: String,
+    url: Option<String>,
+    baseline_mean: f64,
+    mean: f64,
+    change_pct: f64,
+    p_value: Option<f64>,
+}
+
+/// Coefficients of the Chebyshev fit of `erfc` from Numerical Recipes, lowest first.
+const ERFC_COEFFS: [f64; 10] = [
+    -1.265_512_23,
+    1.000_023_68,
+    0.374_091_96,
+    0.096_784_18,
+    -0.186_288_06,
+    0.278_868_07,
+    -1.135_203_98,
+    1.488_515_87,
+    -0.822_152_23,
+    0.170_872_77,
+];
+
+/// Upper tail of the standard normal distribution, within 1.2e-7 of the exact value.
+fn normal_sf(z: f64) -> f64 {
+    let x = z / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let poly = ERFC_COEFFS.iter().rev().fold(0.0, |acc, c| c + t * acc);
+    let erfc = t * (poly - x * x).exp();
+    0.5 * if x >= 0.0 { erfc } else { 2.0 - erfc }
+}
+
+/// One-sided Welch test of `current` having a higher mean than `baseline`.
+///
+/// Both runs have at least `min_samples` samples, so the normal distribution
+/// stands in for Student's.
+fn welch_p_value(baseline: &Moments, current: &Moments) -> Option<f64> {
+    let se = (baseline.variance()? / baseline.count as f64
+        + current.variance()? / current.count as f64)
+        .sqrt();
+    let diff = current.mean() - baseline.mean();
+    if se == 0.0 {
+        return Some(if diff > 0.0 { 0.0 } else { 1.0 });
+    }
+    Some(normal_sf(diff / se))
+}
+
+fn compare(
+    baseline: &RunMoments,
+    current: &RunMoments,
+    tolerance_pct: f64,
+    settings: &RegressionSettings,
+) -> Vec<Finding> {
+    let mut findings = vec![];
+    for ((metric, url), cur) in current {
+        let Some(base) = baseline.get(&(metric.clone(), url.clone())) else {
+            continue;
+        };
+        if cur.count < settings.min_samples || base.count < settings.min_samples {
+            continue;
+        }
+        let (baseline_mean, mean) = (base.mean(), cur.mean());
+        // Against a zero baseline, e.g. no failures at all, the change is in units
+        let change_pct = 100.0 * (mean - baseline_mean) / baseline_mean.abs().max(1.0);
+        if change_pct <= tolerance_pct {
+            continue;
+        }
+        let p_value = welch_p_value(base, cur);
+        if matches!(p_value, Some(p) if p > settings.significance) {
+            continue;
+        }
+        findings.push(Finding {
+            metric: metric.clone(),
+            url: url.clone(),
+            baseline_mean,
+            mean,
+            change_pct,
+            p_value,
+        });
+    }
+    findings
+}
+
+/// Compares a finished run with the baseline of its test and stores what regressed.
+///
+/// Replaces the regressions found before, and finds none when the test has no
+/// live baseline or the run is the baseline.
+#[instrument(name = "Detecting regressions", skip_all, fields(run_id = %run.id))]
+pub async fn detect_regressions(
+    client: &PrismaClient,
+    pg_client: &Pool<Postgres>,
+    settings: &RegressionSettings,
+    run: &test_run::Data,
+) -> Result<Vec<regression::Data>, AppError> {
+    let test = client
+        .test()
+        .find_unique(test::id::equals(run.test_id.clone()))
+        .exec()
+        .await?;
+    let Some(test) = test else {
+        return Ok(vec![]);
+    };
+    let Some(baseline_id) = test.baseline_run_id.filter(|id| *id != run.id) else {
+        return Ok(vec![]);
+    };
+    let baseline = client
+        .test_run()
+        .find_first(vec![
+            test_run::id::equals(baseline_id),
+            test_run::deleted_at::equals(None),
+            test_run::status::equals(RunStatus::Finished),
+        ])
+        .exec()
+        .await?;
+    let Some(baseline) = baseline else {
+        return Ok(vec![]);
+    };
+
+    let rows = metric_moments(
+        pg_client,
+        &baseline.id,
+        COMPARED_METRICS,
+        report_resolution(&baseline),
+    )
+    .await?;
+    let baseline_moments = group_moments(rows);
+    let rows = metric_moments(pg_client, &run.id, COMPARED_METRICS, report_resolution(run)).await?;
+    let tolerance_pct = test
+        .regression_tolerance_pct
+        .unwrap_or(settings.tolerance_pct);
+    let findings = compare(
+        &baseline_moments,
+        &group_moments(rows),
+        tolerance_pct,
+        settings,
+    );
+
+    let run_id = run.id.clone();
+    let regressions = client
+        ._transaction()
+        .run(|client| async move {
+            client
+                .regression()
+                .delete_many(vec![regression::run_id::equals(run_id.clone())])
+                .exec()
+                .await?;
+            let mut regressions = Vec::with_capacity(findings.len());
+            for f in findings {
+                let data = client
+                    .regression()
+                    .create(
+                        generate_id(),
+                        f.metric,
+                        baseline.id.clone(),
+                        f.baseline_mean,
+                        f.mean,
+                        f.change_pct,
+                        test_run::id::equals(run_id.clone()),
+                        vec![
+                            regression::url::set(f.url),
+                            regression::p_value::set(f.p_value),
+                        ],
+                    )
+                    .exec()
+                    .await?;
+                regressions.push(data);
+            }
+            Ok::<_, QueryError>(regressions)
+        })
+        .await?;
+    Ok(regressions)
+}
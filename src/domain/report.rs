@@ -0,0 +1,454 @@
+use crate::prisma::{run_check, test_run, CheckKind, RunStatus};
+use crate::questdb::{
+    rollup::Resolution,
+    summary::{
+        endpoint_statuses, metric_series, metric_summaries, EndpointStatus, MetricSummary,
+        SeriesPoint,
+    },
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::{collections::BTreeMap, fmt::Write};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+/// Points a chart of the HTML report is drawn from, at most.
+const CHART_POINTS: i64 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Junit,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportFormat::Junit => "junit",
+            ReportFormat::Html => "html",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ReportFormat::Junit => "application/xml",
+            ReportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Junit => "xml",
+            ReportFormat::Html => "html",
+        }
+    }
+
+    pub fn filename(&self, run_id: &str) -> String {
+        format!("run-{run_id}-report.{}", self.extension())
+    }
+}
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReportQuery {
+    #[param(value_type = ReportFormat)]
+    pub format: ReportFormat,
+}
+
+/// Longest name of a threshold or check.
+pub const MAX_CHECK_NAME_LENGTH: usize = 255;
+
+/// Outcome of one threshold or check, as the load generator counted it.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CheckOutcome {
+    #[schema(value_type = crate::domain::schemas::CheckKind)]
+    pub kind: CheckKind,
+    pub name: String,
+    /// A threshold passes or fails once
+    pub passes: u64,
+    pub fails: u64,
+}
+
+/// Samples taken against one endpoint, with the count of each error status.
+#[derive(Debug, Clone)]
+pub struct EndpointOutcome {
+    pub scenario: String,
+    pub method: String,
+    pub url: String,
+    pub samples: i64,
+    pub errors: BTreeMap<String, i64>,
+}
+
+impl EndpointOutcome {
+    pub fn error_count(&self) -> i64 {
+        self.errors.values().sum()
+    }
+}
+
+fn is_error_status(status: &str) -> bool {
+    status.parse::<u16>().map_or(false, |s| s >= 400)
+}
+
+/// Groups per status counts by endpoint, keeping the order of `rows`.
+pub fn endpoint_outcomes(rows: Vec<EndpointStatus>) -> Vec<EndpointOutcome> {
+    let mut outcomes: Vec<EndpointOutcome> = vec![];
+    for row in rows {
+        let scenario = row.scenario.unwrap_or_default();
+        let method = row.method.unwrap_or_default();
+        let outcome = match outcomes.last_mut() {
+            Some(o) if o.scenario == scenario && o.method == method && o.url == row.url => o,
+            _ => {
+                outcomes.push(EndpointOutcome {
+                    scenario,
+                    method,
+                    url: row.url,
+                    samples: 0,
+                    errors: BTreeMap::new(),
+                });
+                outcomes.last_mut().unwrap()
+            }
+        };
+        outcome.samples += row.count;
+        if let Some(status) = row.status.filter(|s| is_error_status(s)) {
+            *outcome.errors.entry(status).or_default() += row.count;
+        }
+    }
+    outcomes
+}
+
+/// What a finished run is reported with.
+pub struct RunReport {
+    pub run: test_run::Data,
+    pub test_name: String,
+    pub metrics: Vec<MetricSummary>,
+    pub endpoints: Vec<EndpointOutcome>,
+    /// Thresholds first, then checks, each sorted by name
+    pub checks: Vec<run_check::Data>,
+    pub series: Vec<SeriesPoint>,
+}
+
+impl RunReport {
+    /// Reads the aggregates of a finished run, from its rollups once the raw samples are gone.
+    pub async fn load(
+        pg_client: &Pool<Postgres>,
+        run: test_run::Data,
+        test_name: String,
+        mut checks: Vec<run_check::Data>,
+    ) -> Result<Self, sqlx::Error> {
+        let resolution = report_resolution(&run);
+        let bucket_secs = chart_bucket_secs(&run);
+        checks.sort_by(|a, b| {
+            let order = |c: &run_check::Data| (c.kind == CheckKind::Check, c.name.clone());
+            order(a).cmp(&order(b))
+        });
+        Ok(Self {
+            checks,
+            metrics: metric_summaries(pg_client, &run.id, resolution).await?,
+            endpoints: endpoint_outcomes(endpoint_statuses(pg_client, &run.id, resolution).await?),
+            series: metric_series(pg_client, &run.id, None, resolution, bucket_secs).await?,
+            run,
+            test_name,
+        })
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Junit => self.junit(),
+            ReportFormat::Html => self.html(),
+        }
+    }
+
+    /// One testcase for the run completing, one per threshold and check, and one per endpoint
+    /// failed by error statuses.
+    fn junit(&self) -> String {
+        let aborted = self.run.status == RunStatus::Aborted;
+        let failed_endpoints = self.endpoints.iter().filter(|e| e.error_count() > 0);
+        let failed_checks = self.checks.iter().filter(|c| c.fails > 0);
+        let tests = self.endpoints.len() + self.checks.len() + 1;
+        let failures = failed_endpoints.count() + failed_checks.count() + usize::from(aborted);
+        let time = run_duration_secs(&self.run);
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuites name=\"tonsail\" tests=\"{tests}\" failures=\"{failures}\" \
+             time=\"{time:.3}\">"
+        );
+        let timestamp = self
+            .run
+            .started_at
+            .unwrap_or(self.run.created_at)
+            .naive_utc()
+            .format("%Y-%m-%dT%H:%M:%S");
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" id=\"{}\" tests=\"{tests}\" failures=\"{failures}\" \
+             errors=\"0\" skipped=\"0\" timestamp=\"{timestamp}\" time=\"{time:.3}\">",
+            escape(&self.test_name),
+            escape(&self.run.id),
+        );
+
+        xml.push_str("    <properties>\n");
+        let _ = writeln!(
+            xml,
+            "      <property name=\"vus\" value=\"{}\"/>",
+            self.run.vus
+        );
+        for m in &self.metrics {
+            for (stat, value) in [("min", m.min), ("max", m.max), ("mean", m.mean)] {
+                let _ = writeln!(
+                    xml,
+                    "      <property name=\"{}.{stat}\" value=\"{value:.3}\"/>",
+                    escape(&m.name)
+                );
+            }
+        }
+        xml.push_str("    </properties>\n");
+
+        let _ = write!(
+            xml,
+            "    <testcase classname=\"run\" name=\"completed\" time=\"{time:.3}\""
+        );
+        if aborted {
+            xml.push_str(">\n      <failure type=\"aborted\" message=\"The run was aborted\"/>\n");
+            xml.push_str("    </testcase>\n");
+        } else {
+            xml.push_str("/>\n");
+        }
+
+        for c in &self.checks {
+            let (classname, failure) = match c.kind {
+                CheckKind::Threshold => ("thresholds", "threshold"),
+                CheckKind::Check => ("checks", "check"),
+            };
+            let _ = write!(
+                xml,
+                "    <testcase classname=\"{classname}\" name=\"{}\"",
+                escape(&c.name),
+            );
+            if c.fails == 0 {
+                xml.push_str("/>\n");
+                continue;
+            }
+            let message = match c.kind {
+                CheckKind::Threshold => "The threshold was crossed".to_string(),
+                CheckKind::Check => format!("{} of {} checks failed", c.fails, c.passes + c.fails),
+            };
+            let _ = writeln!(
+                xml,
+                ">\n      <failure type=\"{failure}\" message=\"{message}\"/>\n    </testcase>",
+            );
+        }
+
+        for e in &self.endpoints {
+            let classname = match e.scenario.as_str() {
+                "" => "default",
+                scenario => scenario,
+            };
+            let _ = write!(
+                xml,
+                "    <testcase classname=\"{}\" name=\"{}\"",
+                escape(classname),
+                escape(format!("{} {}", e.method, e.url).trim()),
+            );
+            let errors = e.error_count();
+            if errors == 0 {
+                xml.push_str("/>\n");
+                continue;
+            }
+            let statuses: Vec<_> = e.errors.iter().map(|(s, n)| format!("{s}: {n}")).collect();
+            let _ = writeln!(
+                xml,
+                ">\n      <failure type=\"http_errors\" message=\"{errors} of {} samples had \
+                 error statuses\">{}</failure>\n    </testcase>",
+                e.samples,
+                escape(&statuses.join("\n")),
+            );
+        }
+
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+
+    /// A standalone page, the charts are inline SVG.
+    fn html(&self) -> String {
+        let title = format!("{} run {}", self.test_name, self.run.id);
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            escape(&title),
+            escape(&title),
+        );
+
+        html.push_str("<h2>Summary</h2>\n<table>\n");
+        let started = self.run.started_at.map(|t| t.to_rfc3339());
+        let finished = self.run.finished_at.map(|t| t.to_rfc3339());
+        for (label, value) in [
+            ("Status", format!("{:?}", self.run.status)),
+            ("Virtual users", self.run.vus.to_string()),
+            ("Started", started.unwrap_or_default()),
+            ("Finished", finished.unwrap_or_default()),
+            ("Duration", format!("{:.0}s", run_duration_secs(&self.run))),
+        ] {
+            let _ = writeln!(html, "<tr><th>{label}</th><td>{}</td></tr>", escape(&value));
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Metrics</h2>\n<table>\n");
+        html.push_str("<tr><th>Metric</th><th>Samples</th><th>Min</th><th>Mean</th>");
+        html.push_str("<th>Max</th></tr>\n");
+        for m in &self.metrics {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td></tr>",
+                escape(&m.name),
+                m.count,
+                m.min,
+                m.mean,
+                m.max,
+            );
+        }
+        html.push_str("</table>\n");
+
+        if !self.checks.is_empty() {
+            html.push_str("<h2>Thresholds and checks</h2>\n<table>\n");
+            html.push_str("<tr><th>Kind</th><th>Name</th><th>Passes</th><th>Fails</th></tr>\n");
+            for c in &self.checks {
+                let class = if c.fails > 0 { " class=\"failed\"" } else { "" };
+                let kind = match c.kind {
+                    CheckKind::Threshold => "Threshold",
+                    CheckKind::Check => "Check",
+                };
+                let _ = writeln!(
+                    html,
+                    "<tr{class}><td>{kind}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&c.name),
+                    c.passes,
+                    c.fails,
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        if !self.endpoints.is_empty() {
+            html.push_str("<h2>Endpoints</h2>\n<table>\n");
+            html.push_str("<tr><th>Scenario</th><th>Method</th><th>URL</th><th>Samples</th>");
+            html.push_str("<th>Errors</th></tr>\n");
+            for e in &self.endpoints {
+                let class = if e.error_count() > 0 {
+                    " class=\"failed\""
+                } else {
+                    ""
+                };
+                let _ = writeln!(
+                    html,
+                    "<tr{class}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&e.scenario),
+                    escape(&e.method),
+                    escape(&e.url),
+                    e.samples,
+                    e.error_count(),
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("<h2>Charts</h2>\n");
+        for m in &self.metrics {
+            let points: Vec<_> = self.series.iter().filter(|p| p.name == m.name).collect();
+            let _ = writeln!(
+                html,
+                "<figure>\n<figcaption>{}</figcaption>\n{}</figure>",
+                escape(&m.name),
+                svg_chart(&points),
+            );
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1em}\
+th,td{border:1px solid #ccc;padding:4px 8px;text-align:left}\
+tr.failed td{background:#fde8e8}\
+figure{margin:0 0 1.5em}figcaption{font-weight:bold;margin-bottom:4px}\
+svg{border:1px solid #ccc;background:#fafafa}";
+
+/// Bucket size that keeps a chart of the whole run under `CHART_POINTS` points.
+pub fn chart_bucket_secs(run: &test_run::Data) -> i64 {
+    (run_duration_secs(run) as i64 / CHART_POINTS).max(1)
+}
+
+/// Resolution a finished run's aggregates are read at.
+pub fn report_resolution(run: &test_run::Data) -> Resolution {
+    match run.raw_purged_at {
+        Some(_) => Resolution::Minute,
+        None => Resolution::Raw,
+    }
+}
+
+/// Seconds the run took, or was planned to take when it never finished.
+fn run_duration_secs(run: &test_run::Data) -> f64 {
+    match (run.started_at, run.finished_at) {
+        (Some(started), Some(finished)) => {
+            (finished - started).num_milliseconds().max(0) as f64 / 1000.0
+        }
+        _ => run.duration_secs.unwrap_or(0) as f64,
+    }
+}
+
+/// A line chart of the points, scaled to fill the box.
+fn svg_chart(points: &[&SeriesPoint]) -> String {
+    const WIDTH: f64 = 600.0;
+    const HEIGHT: f64 = 160.0;
+    const PAD: f64 = 10.0;
+
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return String::new();
+    };
+    let (min, max) = points.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| {
+        (lo.min(p.value), hi.max(p.value))
+    });
+    let span_ms = (last.ts - first.ts).num_milliseconds().max(1) as f64;
+    let range = if max > min { max - min } else { 1.0 };
+
+    let coords: Vec<String> = points
+        .iter()
+        .map(|p| {
+            let x = (p.ts - first.ts).num_milliseconds() as f64 / span_ms * (WIDTH - 2.0 * PAD);
+            let y = (HEIGHT - PAD) - (p.value - min) / range * (HEIGHT - 2.0 * PAD);
+            format!("{:.1},{y:.1}", x + PAD)
+        })
+        .collect();
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{HEIGHT}\" \
+         viewBox=\"0 0 {WIDTH} {HEIGHT}\">\
+         <polyline fill=\"none\" stroke=\"#2563eb\" stroke-width=\"1.5\" points=\"{}\"/>\
+         <text x=\"{PAD}\" y=\"{}\" font-size=\"10\">{max:.3}</text>\
+         <text x=\"{PAD}\" y=\"{}\" font-size=\"10\">{min:.3}</text></svg>\n",
+        coords.join(" "),
+        PAD + 8.0,
+        HEIGHT - PAD - 2.0,
+    )
+}
+
+/// Escapes text for XML and HTML content and attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
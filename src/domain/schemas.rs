@@ -0,0 +1,199 @@
+// OpenAPI schemas of the Prisma models. The generated `Data` types can not derive
+// `ToSchema`, so these mirror their JSON form and must follow `schema.prisma`.
+use prisma_client_rust::chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UserRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RunStatus {
+    NotStarted,
+    Started,
+    Finished,
+    Aborted,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Plan {
+    Free,
+    Team,
+    Enterprise,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CheckKind {
+    Threshold,
+    Check,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MetricPolicy {
+    Accept,
+    Tag,
+    Reject,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Organization {
+    id: String,
+    name: String,
+    plan: Plan,
+    retention_days: Option<i32>,
+    unknown_metrics: MetricPolicy,
+    created_at: DateTime<FixedOffset>,
+    updated_at: DateTime<FixedOffset>,
+    deleted_at: Option<DateTime<FixedOffset>>,
+    users: Option<Vec<User>>,
+    projects: Option<Vec<Project>>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    id: String,
+    name: String,
+    created_at: DateTime<FixedOffset>,
+    updated_at: DateTime<FixedOffset>,
+    deleted_at: Option<DateTime<FixedOffset>>,
+    organization_id: String,
+}
+
+/// The password hash is left out on purpose, clients must not rely on it.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    id: String,
+    email: String,
+    name: String,
+    role: UserRole,
+    created_at: DateTime<FixedOffset>,
+    updated_at: DateTime<FixedOffset>,
+    deleted_at: Option<DateTime<FixedOffset>>,
+    organization_id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Test {
+    id: String,
+    name: String,
+    created_at: DateTime<FixedOffset>,
+    updated_at: DateTime<FixedOffset>,
+    deleted_at: Option<DateTime<FixedOffset>>,
+    project_id: String,
+    baseline_run_id: Option<String>,
+    regression_tolerance_pct: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TestRun {
+    id: String,
+    created_at: DateTime<FixedOffset>,
+    status: RunStatus,
+    deleted_at: Option<DateTime<FixedOffset>>,
+    vus: i32,
+    duration_secs: Option<i32>,
+    started_at: Option<DateTime<FixedOffset>>,
+    finished_at: Option<DateTime<FixedOffset>>,
+    samples: i64,
+    rolled_up_at: Option<DateTime<FixedOffset>>,
+    raw_purged_at: Option<DateTime<FixedOffset>>,
+    test_id: String,
+    regressions: Option<Vec<Regression>>,
+    checks: Option<Vec<RunCheck>>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunCheck {
+    id: String,
+    kind: CheckKind,
+    name: String,
+    passes: i64,
+    fails: i64,
+    run_id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Regression {
+    id: String,
+    metric: String,
+    url: Option<String>,
+    baseline_run_id: String,
+    baseline_mean: f64,
+    mean: f64,
+    change_pct: f64,
+    p_value: Option<f64>,
+    created_at: DateTime<FixedOffset>,
+    run_id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsCatalog {
+    id: i32,
+    label: String,
+    value: String,
+    group: String,
+    unit: String,
+    description: String,
+    organization_id: Option<String>,
+}
+
+/// `clientSecret` is never returned.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcProvider {
+    id: String,
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    email_domain: String,
+    /// Logins only start once an operator verified the domain
+    domain_verified_at: Option<DateTime<FixedOffset>>,
+    configured_by: Option<String>,
+    enabled: bool,
+    created_at: DateTime<FixedOffset>,
+    updated_at: DateTime<FixedOffset>,
+    organization_id: String,
+}
+
+/// `before` and `after` hold JSON encoded snapshots of the target.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLog {
+    id: String,
+    created_at: DateTime<FixedOffset>,
+    organization_id: String,
+    actor_id: Option<String>,
+    action: String,
+    target_type: String,
+    target_id: String,
+    before: Option<String>,
+    after: Option<String>,
+    ip: Option<String>,
+    request_id: Option<String>,
+}
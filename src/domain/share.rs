@@ -0,0 +1,161 @@
+use crate::prisma::{share_link, test_run, RunStatus};
+use crate::questdb::summary::{MetricSummary, SeriesPoint};
+use crate::routes::V1_PREFIX;
+use crate::util::app_error::AppError;
+use fred::{pool::RedisPool, prelude::*};
+use prisma_client_rust::chrono::{DateTime, FixedOffset, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+/// Header a visitor sends the password of a protected link in.
+pub const SHARE_PASSWORD_HEADER: &str = "x-share-password";
+/// Wrong passwords a link takes before it stops checking them.
+pub const MAX_FAILED_ATTEMPTS: i64 = 5;
+/// Seconds a locked link stays locked, counted from the last wrong password.
+pub const LOCKOUT_SECS: i64 = 900;
+const FAILED_ATTEMPTS_PREFIX: &str = "tonsail-share-attempts/";
+
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareForm {
+    /// The link never expires when empty
+    #[validate(range(min = 1, max = 8760))]
+    pub expires_in_hours: Option<u32>,
+    /// Visitors must send it in the `X-Share-Password` header
+    #[validate(length(min = 8, max = 128))]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareLinkResponse {
+    id: String,
+    run_id: String,
+    created_at: DateTime<FixedOffset>,
+    expires_at: Option<DateTime<FixedOffset>>,
+    revoked_at: Option<DateTime<FixedOffset>>,
+    created_by: String,
+    has_password: bool,
+    /// Only returned when the link is created
+    url: Option<String>,
+}
+
+impl ShareLinkResponse {
+    /// Includes the link itself, which can not be recovered later.
+    pub fn created(link: share_link::Data, token: &str) -> Self {
+        Self {
+            url: Some(format!("{V1_PREFIX}/shared/{token}")),
+            ..Self::from(link)
+        }
+    }
+}
+
+impl From<share_link::Data> for ShareLinkResponse {
+    fn from(l: share_link::Data) -> Self {
+        Self {
+            id: l.id,
+            run_id: l.run_id,
+            created_at: l.created_at,
+            expires_at: l.expires_at,
+            revoked_at: l.revoked_at,
+            created_by: l.created_by,
+            has_password: l.password.is_some(),
+            url: None,
+        }
+    }
+}
+
+/// Links are looked up by a digest, so a leaked database does not leak the links.
+pub fn hash_share_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Refuses to check a password while the link is locked, each check costs an Argon2 hash.
+pub async fn check_lockout(rds_client: &RedisPool, link_id: &str) -> Result<(), AppError> {
+    let failed: Option<i64> = rds_client
+        .get(format!("{FAILED_ATTEMPTS_PREFIX}{link_id}"))
+        .await?;
+    if failed.unwrap_or(0) >= MAX_FAILED_ATTEMPTS {
+        return Err(AppError::TooManyRequests(format!(
+            "Too many wrong passwords, retry in {} minutes",
+            LOCKOUT_SECS / 60
+        )));
+    }
+    Ok(())
+}
+
+/// Counts a wrong password against the link, whoever sent it.
+pub async fn record_failed_attempt(rds_client: &RedisPool, link_id: &str) -> Result<(), AppError> {
+    let key = format!("{FAILED_ATTEMPTS_PREFIX}{link_id}");
+    rds_client.incr::<i64, _>(&key).await?;
+    rds_client.expire::<i64, _>(&key, LOCKOUT_SECS).await?;
+    Ok(())
+}
+
+/// What a share link shows of a run.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedRun {
+    id: String,
+    test_name: String,
+    #[schema(value_type = crate::domain::schemas::RunStatus)]
+    status: RunStatus,
+    vus: i32,
+    started_at: Option<DateTime<FixedOffset>>,
+    finished_at: Option<DateTime<FixedOffset>>,
+    metrics: Vec<MetricSummary>,
+}
+
+impl SharedRun {
+    pub fn new(run: test_run::Data, test_name: String, metrics: Vec<MetricSummary>) -> Self {
+        Self {
+            id: run.id,
+            test_name,
+            status: run.status,
+            vus: run.vus,
+            started_at: run.started_at,
+            finished_at: run.finished_at,
+            metrics,
+        }
+    }
+}
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SharedSeriesQuery {
+    #[validate(length(min = 1, max = 50))]
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedSeries {
+    name: String,
+    /// Seconds each value is averaged over
+    bucket_secs: i64,
+    values: Vec<SharedPoint>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SharedPoint {
+    ts: NaiveDateTime,
+    value: f64,
+}
+
+impl SharedSeries {
+    pub fn new(name: String, bucket_secs: i64, points: Vec<SeriesPoint>) -> Self {
+        Self {
+            name,
+            bucket_secs,
+            values: points
+                .into_iter()
+                .map(|p| SharedPoint {
+                    ts: p.ts,
+                    value: p.value,
+                })
+                .collect(),
+        }
+    }
+}
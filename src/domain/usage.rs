@@ -0,0 +1,229 @@
+use crate::{
+    configuration::{PlanLimits, PlanSettings},
+    domain::organization::lock_organization,
+    prisma::{organization, project, test, test_run, usage_period, Plan, PrismaClient, RunStatus},
+    util::{app_error::AppError, nano_id::generate_id},
+};
+use prisma_client_rust::{
+    chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeZone, Utc},
+    QueryError,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+/// Rough size of one row of `metrics` in QuestDB, used to estimate the retained bytes.
+pub const SAMPLE_BYTES: i64 = 48;
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsageQuery {
+    /// Billing month as `YYYY-MM`, the current one by default
+    #[validate(custom(function = "validate_period"))]
+    pub period: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub organization_id: String,
+    #[schema(value_type = crate::domain::schemas::Plan)]
+    pub plan: Plan,
+    pub period_start: DateTime<FixedOffset>,
+    pub period_end: DateTime<FixedOffset>,
+    pub vu_hours: f64,
+    pub runs: i32,
+    pub ingested_samples: i64,
+    /// Estimate for the raw samples that are kept, live for the current period and as last
+    /// snapshotted for a past one
+    pub retained_bytes: i64,
+    pub retention_days: i64,
+    pub limits: PlanLimits,
+}
+
+/// What a single event adds to the usage of its billing period.
+#[derive(Debug, Default)]
+pub struct UsageDelta {
+    pub vu_seconds: i64,
+    pub runs: i32,
+    pub ingested_samples: i64,
+}
+
+fn validate_period(period: &str) -> Result<(), ValidationError> {
+    match parse_period(period) {
+        Some(_) => Ok(()),
+        None => Err(ValidationError::new("period must be formatted as YYYY-MM")),
+    }
+}
+
+fn parse_period(period: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&format!("{period}-01"), "%Y-%m-%d").ok()
+}
+
+/// Start and end of the calendar month, in UTC, that `period` or else `now` falls in.
+pub fn billing_period(
+    period: Option<&str>,
+    now: DateTime<Utc>,
+) -> (DateTime<FixedOffset>, DateTime<FixedOffset>) {
+    let first = period
+        .and_then(parse_period)
+        .unwrap_or_else(|| NaiveDate::from_ymd_opt(now.year(), now.month(), 1).unwrap());
+    let next = match first.month() {
+        12 => NaiveDate::from_ymd_opt(first.year() + 1, 1, 1),
+        month => NaiveDate::from_ymd_opt(first.year(), month + 1, 1),
+    }
+    .unwrap();
+
+    let at_midnight = |d: NaiveDate| Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap());
+    (at_midnight(first).into(), at_midnight(next).into())
+}
+
+pub async fn plan_of(client: &PrismaClient, org_id: &str) -> Result<Plan, AppError> {
+    client
+        .organization()
+        .find_first(vec![
+            organization::id::equals(org_id.to_string()),
+            organization::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .map(|o| o.plan)
+        .ok_or_else(|| AppError::NotFound("No such organization exists".to_string()))
+}
+
+/// Adds `delta` to the current billing period of the organization.
+pub async fn record_usage(
+    client: &PrismaClient,
+    org_id: &str,
+    delta: UsageDelta,
+) -> Result<(), QueryError> {
+    let (period_start, _) = billing_period(None, Utc::now());
+    client
+        .usage_period()
+        .upsert(
+            usage_period::organization_id_period_start(org_id.to_string(), period_start),
+            usage_period::create(
+                generate_id(),
+                org_id.to_string(),
+                period_start,
+                vec![
+                    usage_period::vu_seconds::set(delta.vu_seconds),
+                    usage_period::runs::set(delta.runs),
+                    usage_period::ingested_samples::set(delta.ingested_samples),
+                ],
+            ),
+            vec![
+                usage_period::vu_seconds::increment(delta.vu_seconds),
+                usage_period::runs::increment(delta.runs),
+                usage_period::ingested_samples::increment(delta.ingested_samples),
+            ],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
+
+/// Rejects a run that asks for more than the plan allows.
+pub fn check_run_limits(
+    limits: &PlanLimits,
+    vus: u32,
+    duration_secs: Option<u32>,
+) -> Result<(), AppError> {
+    if vus > limits.max_vus {
+        return Err(AppError::QuotaExceeded(format!(
+            "the plan allows at most {} virtual users per run",
+            limits.max_vus
+        )));
+    }
+    if duration_secs.map_or(false, |d| d > limits.max_run_duration_secs) {
+        return Err(AppError::QuotaExceeded(format!(
+            "the plan allows runs of at most {} seconds",
+            limits.max_run_duration_secs
+        )));
+    }
+    Ok(())
+}
+
+/// Rejects starting one more run once the organization runs as many as the plan allows.
+///
+/// Must run inside the transaction that starts the run: it locks the organization, so
+/// concurrent starts wait for each other instead of all seeing a free slot.
+pub async fn check_concurrent_runs(
+    client: &PrismaClient,
+    org_id: &str,
+    limits: &PlanLimits,
+) -> Result<(), AppError> {
+    lock_organization(client, org_id).await?;
+
+    let running = client
+        .test_run()
+        .count(vec![
+            test_run::status::equals(RunStatus::Started),
+            test_run::deleted_at::equals(None),
+            test_run::test::is(vec![test::project::is(vec![
+                project::organization_id::equals(org_id.to_string()),
+            ])]),
+        ])
+        .exec()
+        .await?;
+
+    if running >= i64::from(limits.max_concurrent_runs) {
+        return Err(AppError::QuotaExceeded(format!(
+            "the plan allows {} concurrent runs",
+            limits.max_concurrent_runs
+        )));
+    }
+    Ok(())
+}
+
+/// Days the raw samples of the organization are kept, its own setting may only shorten the plan's.
+pub fn retention_days(plans: &PlanSettings, org: &organization::Data) -> i64 {
+    let limit = plans.limits(org.plan).retention_days;
+    let days = org
+        .retention_days
+        .map_or(limit, |d| (d.max(1) as u32).min(limit));
+    i64::from(days)
+}
+
+/// Raw samples of the organization's runs that are still kept.
+pub async fn retained_samples(client: &PrismaClient, org_id: &str) -> Result<i64, QueryError> {
+    let runs = client
+        .test_run()
+        .find_many(vec![
+            test_run::deleted_at::equals(None),
+            test_run::raw_purged_at::equals(None),
+            test_run::test::is(vec![test::project::is(vec![
+                project::organization_id::equals(org_id.to_string()),
+            ])]),
+        ])
+        .exec()
+        .await?;
+    Ok(runs.iter().map(|r| r.samples).sum())
+}
+
+/// Stores the retained bytes estimate of the organization on the billing period of `now`.
+///
+/// Each snapshot replaces the last, so a closed period keeps the last one taken in it.
+pub async fn snapshot_retained_bytes(
+    client: &PrismaClient,
+    org_id: &str,
+    now: DateTime<Utc>,
+) -> Result<(), QueryError> {
+    let bytes = retained_samples(client, org_id).await? * SAMPLE_BYTES;
+    let (period_start, _) = billing_period(None, now);
+    client
+        .usage_period()
+        .upsert(
+            usage_period::organization_id_period_start(org_id.to_string(), period_start),
+            usage_period::create(
+                generate_id(),
+                org_id.to_string(),
+                period_start,
+                vec![usage_period::retained_bytes::set(bytes)],
+            ),
+            vec![usage_period::retained_bytes::set(bytes)],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
@@ -1,9 +1,10 @@
 use super::auth::validate_password;
 use super::{MAX_NAME_LENGTH, MIN_NAME_LENGTH};
 use serde::Deserialize;
+use utoipa::ToSchema;
 use validator::Validate;
 
-#[derive(Debug, Validate, Deserialize)]
+#[derive(Debug, Validate, Deserialize, ToSchema)]
 pub struct UserUpdateForm {
     #[validate(length(min = "MIN_NAME_LENGTH", max = "MAX_NAME_LENGTH"))]
     pub name: Option<String>,
@@ -11,7 +12,7 @@ pub struct UserUpdateForm {
     pub email: Option<String>,
 }
 
-#[derive(Debug, Validate, Deserialize)]
+#[derive(Debug, Validate, Deserialize, ToSchema)]
 pub struct UserPasswordForm {
     pub old: String,
     #[validate(custom(function = "validate_password"))]
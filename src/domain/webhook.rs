@@ -0,0 +1,115 @@
+use crate::domain::report::ReportFormat;
+use crate::prisma::{regression, test_run, webhook_event, PrismaClient, RunStatus};
+use crate::util::nano_id::generate_id;
+use prisma_client_rust::QueryError;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// An event for the organization's webhooks, queued in the `WebhookEvent` outbox.
+///
+/// The README documents each payload, a dispatcher posts them as they are, so changing one
+/// breaks the endpoints it is delivered to.
+#[derive(Debug)]
+pub enum WebhookEvent {
+    /// `run.finished`, a run is over, whether it finished or was aborted
+    RunFinished(RunFinished),
+    /// `run.regressed`, a finished run got worse than the baseline of its test
+    RunRegressed(RunRegressed),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunFinished {
+    pub run_id: String,
+    pub test_id: String,
+    pub status: RunStatus,
+    /// Reports to attach to the notification
+    pub reports: Vec<ReportAttachment>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportAttachment {
+    pub format: ReportFormat,
+    pub filename: String,
+    pub content_type: &'static str,
+    /// Where the API serves it, the dispatcher may also render it with `RunReport` instead
+    pub path: String,
+}
+
+impl RunFinished {
+    /// Announces a run that is over, with its report in every format.
+    pub fn of(run: &test_run::Data) -> Self {
+        let reports = [ReportFormat::Junit, ReportFormat::Html]
+            .into_iter()
+            .map(|format| ReportAttachment {
+                format,
+                filename: format.filename(&run.id),
+                content_type: format.content_type(),
+                path: format!("/v1/runs/{}/report?format={}", run.id, format.as_str()),
+            })
+            .collect();
+        Self {
+            run_id: run.id.clone(),
+            test_id: run.test_id.clone(),
+            status: run.status,
+            reports,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunRegressed {
+    pub run_id: String,
+    pub test_id: String,
+    pub baseline_run_id: String,
+    pub regressions: Vec<regression::Data>,
+}
+
+impl WebhookEvent {
+    fn event_type(&self) -> &'static str {
+        match self {
+            WebhookEvent::RunFinished(_) => "run.finished",
+            WebhookEvent::RunRegressed(_) => "run.regressed",
+        }
+    }
+
+    fn payload(&self) -> Value {
+        match self {
+            WebhookEvent::RunFinished(data) => json!(data),
+            WebhookEvent::RunRegressed(data) => json!(data),
+        }
+    }
+}
+
+/// Queues an event for delivery.
+pub async fn queue_event(
+    client: &PrismaClient,
+    org_id: &str,
+    event: WebhookEvent,
+) -> Result<(), QueryError> {
+    client
+        .webhook_event()
+        .create(
+            generate_id(),
+            org_id.to_string(),
+            event.event_type().to_string(),
+            event.payload().to_string(),
+            vec![],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
+
+/// The body a dispatcher posts for a queued event.
+pub fn delivery_body(event: &webhook_event::Data) -> Value {
+    json!({
+        "id": event.id,
+        "type": event.event,
+        "organizationId": event.organization_id,
+        "createdAt": event.created_at,
+        "data": serde_json::from_str::<Value>(&event.payload).unwrap_or(Value::Null),
+    })
+}
@@ -0,0 +1,151 @@
+use crate::{
+    domain::export::UserArchive,
+    prisma::{audit_log, data_export, user, ExportStatus},
+    questdb::export::{stream_samples, ExportFormat, SampleFilter},
+    AppState,
+};
+use eyre::eyre;
+use futures::TryStreamExt;
+use prisma_client_rust::chrono::{self, Utc};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{fs::File, io::AsyncWriteExt, task::JoinHandle};
+use tracing::{error, instrument, warn};
+
+/// Builds the user's archive in the background and marks the export as ready or failed.
+pub fn spawn_user_export(state: AppState, export_id: String, user_id: String) -> JoinHandle<()> {
+    state.tasks.clone().spawn(async move {
+        let params = match build_user_archive(&state, &user_id).await {
+            Ok(archive) => {
+                let expires_at = Utc::now() + chrono::Duration::hours(state.exports.link_ttl_hours);
+                vec![
+                    data_export::status::set(ExportStatus::Ready),
+                    data_export::payload::set(Some(archive)),
+                    data_export::expires_at::set(expires_at.into()),
+                ]
+            }
+            Err(e) => {
+                error!(error = e.to_string(), "Could not export user data");
+                vec![data_export::status::set(ExportStatus::Failed)]
+            }
+        };
+
+        let resp = state
+            .db_client
+            .data_export()
+            .update(data_export::id::equals(export_id), params)
+            .exec()
+            .await;
+        if let Err(e) = resp {
+            error!(error = e.to_string(), "Could not update the export");
+        }
+    })
+}
+
+/// Writes a run export to the exports directory and marks it as ready or failed.
+pub fn spawn_run_export(
+    state: AppState,
+    export_id: String,
+    run_id: String,
+    filter: SampleFilter,
+    format: ExportFormat,
+) -> JoinHandle<()> {
+    state.tasks.clone().spawn(async move {
+        let path = Path::new(&state.exports.directory)
+            .join(format!("run-{run_id}-{export_id}.{}", format.extension()));
+        let params = match write_run_export(&state, &path, &run_id, &filter, format).await {
+            Ok(()) => {
+                let expires_at = Utc::now() + chrono::Duration::hours(state.exports.link_ttl_hours);
+                vec![
+                    data_export::status::set(ExportStatus::Ready),
+                    data_export::file::set(Some(path.to_string_lossy().into_owned())),
+                    data_export::expires_at::set(expires_at.into()),
+                ]
+            }
+            Err(e) => {
+                error!(error = e.to_string(), "Could not export run samples");
+                remove_export_files([path]).await;
+                vec![data_export::status::set(ExportStatus::Failed)]
+            }
+        };
+
+        let resp = state
+            .db_client
+            .data_export()
+            .update(data_export::id::equals(export_id), params)
+            .exec()
+            .await;
+        if let Err(e) = resp {
+            error!(error = e.to_string(), "Could not update the export");
+        }
+    })
+}
+
+#[instrument(name = "Writing run export", skip(state, filter))]
+async fn write_run_export(
+    state: &AppState,
+    path: &Path,
+    run_id: &str,
+    filter: &SampleFilter,
+    format: ExportFormat,
+) -> eyre::Result<()> {
+    tokio::fs::create_dir_all(&state.exports.directory).await?;
+    let mut file = File::create(path).await?;
+    let mut chunks = stream_samples(
+        state.export_pg_client.clone(),
+        &state.tasks,
+        run_id,
+        filter,
+        format,
+        Duration::from_secs(state.exports.send_timeout_secs),
+    );
+    while let Some(chunk) = chunks.try_next().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    Ok(())
+}
+
+/// Deletes export files, a file that is already gone is not an error.
+pub async fn remove_export_files(paths: impl IntoIterator<Item = PathBuf>) {
+    for path in paths {
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                warn!(error = e.to_string(), path = %path.display(), "Could not delete export file")
+            }
+            _ => {}
+        }
+    }
+}
+
+#[instrument(name = "Building user archive", skip(state))]
+pub async fn build_user_archive(state: &AppState, user_id: &str) -> eyre::Result<String> {
+    let user = state
+        .db_client
+        .user()
+        .find_unique(user::id::equals(user_id.to_string()))
+        .with(user::organization::fetch())
+        .with(user::tokens::fetch(vec![]))
+        .exec()
+        .await?
+        .ok_or_else(|| eyre!("User {user_id} does not exist"))?;
+
+    let session_store = state.session_store();
+    let sessions = session_store
+        .user_sessions(user_id)
+        .await
+        .map_err(eyre::Report::msg)?;
+
+    let audit = state
+        .db_client
+        .audit_log()
+        .find_many(vec![audit_log::actor_id::equals(Some(user_id.to_string()))])
+        .exec()
+        .await?;
+
+    Ok(serde_json::to_string_pretty(&UserArchive::new(
+        user, sessions, audit,
+    ))?)
+}
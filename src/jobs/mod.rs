@@ -0,0 +1,3 @@
+pub mod export;
+pub mod purge;
+pub mod retention;
@@ -0,0 +1,171 @@
+use crate::{
+    configuration::DeletionSettings,
+    jobs::export::remove_export_files,
+    prisma::{
+        audit_log, data_export, metrics_catalog, oidc_provider, organization, project, regression,
+        run_check, share_link, test, test_run, token, user, webhook_event,
+    },
+    questdb::delete_runs,
+    AppState,
+};
+use prisma_client_rust::chrono::{self, DateTime, FixedOffset, Utc};
+use std::{path::PathBuf, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::{error, info, instrument};
+
+/// Periodically hard-deletes records whose grace period has run out, until shutdown.
+pub fn spawn_purge_job(state: AppState, settings: DeletionSettings) -> JoinHandle<()> {
+    state.tasks.clone().spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(settings.purge_interval_secs));
+        loop {
+            // A purge that already started is finished before shutting down
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = state.shutdown.cancelled() => break,
+            }
+            let cutoff = Utc::now() - chrono::Duration::days(settings.grace_period_days);
+            if let Err(e) = purge_deleted(&state, cutoff.into()).await {
+                error!(error = e.to_string(), "Could not purge deleted records");
+            }
+        }
+    })
+}
+
+/// Removes everything soft-deleted before `cutoff`, children before parents.
+#[instrument(name = "Purging deleted records", skip(state))]
+pub async fn purge_deleted(state: &AppState, cutoff: DateTime<FixedOffset>) -> eyre::Result<()> {
+    let db = &state.db_client;
+
+    let now = Utc::now();
+    let expired = || vec![data_export::expires_at::lt(now.into())];
+    let files = export_files(state, expired()).await?;
+    db.data_export().delete_many(expired()).exec().await?;
+    remove_export_files(files).await;
+
+    // Samples go before the runs so a failed QuestDB purge is retried on the next tick
+    let run_ids: Vec<String> = db
+        .test_run()
+        .find_many(vec![test_run::deleted_at::lt(cutoff)])
+        .exec()
+        .await?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+    delete_runs(&state.pg_client, &run_ids).await?;
+    db.share_link()
+        .delete_many(vec![share_link::run_id::in_vec(run_ids.clone())])
+        .exec()
+        .await?;
+    db.regression()
+        .delete_many(vec![regression::run_id::in_vec(run_ids.clone())])
+        .exec()
+        .await?;
+    db.run_check()
+        .delete_many(vec![run_check::run_id::in_vec(run_ids.clone())])
+        .exec()
+        .await?;
+    let runs = db
+        .test_run()
+        .delete_many(vec![test_run::id::in_vec(run_ids)])
+        .exec()
+        .await?;
+
+    let tests = db
+        .test()
+        .delete_many(vec![test::deleted_at::lt(cutoff)])
+        .exec()
+        .await?;
+    let projects = db
+        .project()
+        .delete_many(vec![project::deleted_at::lt(cutoff)])
+        .exec()
+        .await?;
+
+    let user_ids: Vec<String> = db
+        .user()
+        .find_many(vec![user::deleted_at::lt(cutoff)])
+        .exec()
+        .await?
+        .into_iter()
+        .map(|u| u.id)
+        .collect();
+    let session_store = state.session_store();
+    for user_id in &user_ids {
+        session_store
+            .destroy_user_sessions(user_id)
+            .await
+            .map_err(eyre::Report::msg)?;
+    }
+    db.token()
+        .delete_many(vec![token::user_id::in_vec(user_ids.clone())])
+        .exec()
+        .await?;
+    let exports = || vec![data_export::user_id::in_vec(user_ids.clone())];
+    let files = export_files(state, exports()).await?;
+    db.data_export().delete_many(exports()).exec().await?;
+    remove_export_files(files).await;
+    let users = db
+        .user()
+        .delete_many(vec![user::id::in_vec(user_ids)])
+        .exec()
+        .await?;
+
+    let org_ids: Vec<String> = db
+        .organization()
+        .find_many(vec![organization::deleted_at::lt(cutoff)])
+        .exec()
+        .await?
+        .into_iter()
+        .map(|o| o.id)
+        .collect();
+    db.oidc_provider()
+        .delete_many(vec![oidc_provider::organization_id::in_vec(
+            org_ids.clone(),
+        )])
+        .exec()
+        .await?;
+    db.audit_log()
+        .delete_many(vec![audit_log::organization_id::in_vec(org_ids.clone())])
+        .exec()
+        .await?;
+    db.webhook_event()
+        .delete_many(vec![webhook_event::organization_id::in_vec(
+            org_ids.clone(),
+        )])
+        .exec()
+        .await?;
+    db.metrics_catalog()
+        .delete_many(vec![metrics_catalog::organization::is(vec![
+            organization::id::in_vec(org_ids.clone()),
+        ])])
+        .exec()
+        .await?;
+    let organizations = db
+        .organization()
+        .delete_many(vec![organization::id::in_vec(org_ids)])
+        .exec()
+        .await?;
+
+    info!(
+        runs,
+        tests, projects, users, organizations, "Purged deleted records"
+    );
+    Ok(())
+}
+
+/// Files of the exports matching `filter`, to delete once their rows are gone.
+async fn export_files(
+    state: &AppState,
+    filter: Vec<data_export::WhereParam>,
+) -> eyre::Result<Vec<PathBuf>> {
+    let exports = state
+        .db_client
+        .data_export()
+        .find_many(filter)
+        .exec()
+        .await?;
+    Ok(exports
+        .into_iter()
+        .filter_map(|e| e.file.map(PathBuf::from))
+        .collect())
+}
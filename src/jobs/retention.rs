@@ -0,0 +1,116 @@
+use crate::{
+    configuration::RetentionSettings,
+    domain::usage::{retention_days, snapshot_retained_bytes},
+    prisma::{organization, project, test, test_run, RunStatus},
+    questdb::{
+        delete_raw_samples, drop_deleted_partitions,
+        rollup::{roll_up_run, Resolution},
+    },
+    AppState,
+};
+use prisma_client_rust::chrono::{self, DateTime, Utc};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, instrument};
+
+/// Periodically rolls up finished runs and drops raw samples past retention, until shutdown.
+pub fn spawn_retention_job(state: AppState, settings: RetentionSettings) -> JoinHandle<()> {
+    state.tasks.clone().spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(settings.interval_secs));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = state.shutdown.cancelled() => break,
+            }
+            if let Err(e) = apply_retention(&state, Utc::now()).await {
+                error!(error = e.to_string(), "Could not apply retention");
+            }
+        }
+    })
+}
+
+/// Raw samples are only dropped once the run is rolled up, so its trends survive.
+#[instrument(name = "Applying retention", skip(state))]
+pub async fn apply_retention(state: &AppState, now: DateTime<Utc>) -> eyre::Result<()> {
+    let db = &state.db_client;
+
+    let finished = db
+        .test_run()
+        .find_many(vec![
+            test_run::status::in_vec(vec![RunStatus::Finished, RunStatus::Aborted]),
+            test_run::rolled_up_at::equals(None),
+            test_run::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?;
+    let mut rolled_up = 0;
+    for run in &finished {
+        // One run that fails to roll up must not hold back the others
+        match roll_up(state, &run.id, now).await {
+            Ok(()) => rolled_up += 1,
+            Err(e) => error!(
+                run_id = run.id,
+                error = e.to_string(),
+                "Could not roll up run"
+            ),
+        }
+    }
+
+    let orgs = db
+        .organization()
+        .find_many(vec![organization::deleted_at::equals(None)])
+        .exec()
+        .await?;
+    let mut expired = vec![];
+    for org in &orgs {
+        let days = retention_days(&state.plans, org);
+        let cutoff = now - chrono::Duration::days(days);
+        let runs = db
+            .test_run()
+            .find_many(vec![
+                test_run::rolled_up_at::not(None),
+                test_run::raw_purged_at::equals(None),
+                test_run::finished_at::lt(cutoff.into()),
+                test_run::test::is(vec![test::project::is(vec![
+                    project::organization_id::equals(org.id.clone()),
+                ])]),
+            ])
+            .exec()
+            .await?;
+        expired.extend(runs.into_iter().map(|r| r.id));
+    }
+
+    delete_raw_samples(&state.pg_client, &expired).await?;
+    let purged = db
+        .test_run()
+        .update_many(
+            vec![test_run::id::in_vec(expired)],
+            vec![test_run::raw_purged_at::set(Some(now.into()))],
+        )
+        .exec()
+        .await?;
+
+    for resolution in [Resolution::Raw, Resolution::Minute, Resolution::Hour] {
+        drop_deleted_partitions(&state.pg_client, resolution).await?;
+    }
+    for org in &orgs {
+        snapshot_retained_bytes(db, &org.id, now).await?;
+    }
+
+    info!(rolled_up, purged, "Applied retention");
+    Ok(())
+}
+
+async fn roll_up(state: &AppState, run_id: &str, now: DateTime<Utc>) -> eyre::Result<()> {
+    roll_up_run(&state.pg_client, run_id).await?;
+    state
+        .db_client
+        .test_run()
+        .update(
+            test_run::id::equals(run_id.to_string()),
+            vec![test_run::rolled_up_at::set(Some(now.into()))],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
@@ -1,19 +1,45 @@
-use axum::{routing::IntoMakeService, Router, Server};
-use backon::{ExponentialBuilder, Retryable};
-use configuration::Settings;
+use axum::{extract::connect_info::IntoMakeServiceWithConnectInfo, Router, Server};
+use backon::Retryable;
+use configuration::{
+    ExportSettings, PlanSettings, RegressionSettings, RetrySettings, SessionSettings, Settings,
+};
 use fred::{pool::RedisPool, prelude::RedisError, types::RedisConfig};
 use hyper::server::conn::AddrIncoming;
+use jobs::{
+    purge::spawn_purge_job,
+    retention::{apply_retention, spawn_retention_job},
+};
 use prisma::PrismaClient;
-use prisma_client_rust::NewClientError;
+use prisma_client_rust::{
+    chrono::{DateTime, Utc},
+    NewClientError,
+};
+use questdb::migrations::run_migrations;
 use routes::create_router;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
-use std::{net::SocketAddr, str::FromStr, sync::Arc};
-use tracing::{info, instrument};
-use util::app_error::AppError;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::Instant;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{error, info, instrument, warn};
+use util::rate_limit::RateLimiter;
+use util::redis_session_store::RedisSessionStore;
+use util::secrets::SigningKeys;
+use util::server_metrics::ServerMetrics;
+use util::shutdown::shutdown_signal;
+use util::startup_error::{redact_url, StartupError};
 
+pub mod admin;
 pub mod configuration;
 pub mod domain;
+pub mod jobs;
 pub mod prisma;
+pub mod questdb;
 pub mod routes;
 pub mod util;
 
@@ -21,78 +47,301 @@ pub mod util;
 pub struct AppState {
     db_client: Arc<PrismaClient>,
     pg_client: Pool<Postgres>,
+    /// Exports hold a connection for as long as the client downloads, so they get their own
+    export_pg_client: Pool<Postgres>,
     rds_client: RedisPool,
-    secret: Vec<u8>,
+    http_client: reqwest::Client,
+    exports: ExportSettings,
+    regressions: RegressionSettings,
+    session: SessionSettings,
+    rate_limiter: RateLimiter,
+    plans: PlanSettings,
+    server_metrics: ServerMetrics,
+    /// Cancelled once the server starts draining
+    shutdown: CancellationToken,
+    /// Background work that has to finish before the pools are closed
+    tasks: TaskTracker,
+    signing_keys: SigningKeys,
+    trusted_proxies: Arc<[IpAddr]>,
+    metrics_token: Option<Arc<Secret<String>>>,
 }
 impl AppState {
     fn new(
         client: PrismaClient,
         rds_client: RedisPool,
         pg_client: Pool<Postgres>,
-        secret: Vec<u8>,
-    ) -> Self {
-        Self {
+        export_pg_client: Pool<Postgres>,
+        http_client: reqwest::Client,
+        config: &Settings,
+    ) -> Result<Self, StartupError> {
+        Ok(Self {
+            export_pg_client,
+            rate_limiter: RateLimiter::new(rds_client.clone(), config.rate_limit.clone()),
             db_client: Arc::new(client),
             pg_client,
             rds_client,
-            secret,
+            http_client,
+            exports: config.exports.clone(),
+            regressions: config.regressions,
+            session: config.session.clone(),
+            plans: config.plans,
+            server_metrics: ServerMetrics::new()?,
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            signing_keys: SigningKeys::new(&config.secret, &config.previous_secrets),
+            trusted_proxies: config.application.trusted_proxies.clone().into(),
+            metrics_token: config
+                .telemetry
+                .metrics_token
+                .as_ref()
+                .map(|t| Arc::new(Secret::new(t.expose_secret().clone()))),
+        })
+    }
+
+    /// Connects to MySQL, QuestDB and Redis, retrying each as configured.
+    pub async fn connect(config: &Settings) -> Result<Self, StartupError> {
+        let retry = config.database.retry;
+        let prisma_client = { || try_connect_prisma(config.database.url.expose_secret()) }
+            .retry(&retry.backoff())
+            .notify(|e, delay| warn!(error = %e, ?delay, "Retrying MySQL"))
+            .await
+            .map_err(|source| StartupError::Prisma {
+                attempts: retry.attempts(),
+                source,
+            })?;
+
+        let retry = config.questdb.retry;
+        let pg_pool = match config.questdb.lazy {
+            true => PgPoolOptions::new()
+                .max_connections(10)
+                .connect_lazy(config.questdb.url.expose_secret()),
+            false => {
+                { || try_connect_postgres(config.questdb.url.expose_secret()) }
+                    .retry(&retry.backoff())
+                    .notify(|e, delay| warn!(error = %e, ?delay, "Retrying QuestDB"))
+                    .await
+            }
+        }
+        .map_err(|source| StartupError::QuestDB {
+            url: redact_url(config.questdb.url.expose_secret()),
+            attempts: retry.attempts(),
+            source,
+        })?;
+
+        let retry = config.redis.retry;
+        let rds_pool = { || try_connect_redis(config.redis.url.expose_secret()) }
+            .retry(&retry.backoff())
+            .notify(|e, delay| warn!(error = %e, ?delay, "Retrying Redis"))
+            .await
+            .map_err(|source| StartupError::Redis {
+                url: redact_url(config.redis.url.expose_secret()),
+                attempts: retry.attempts(),
+                source,
+            })?;
+
+        let export_pool = PgPoolOptions::new()
+            .max_connections(config.exports.max_connections)
+            .connect_lazy(config.questdb.url.expose_secret())
+            .map_err(|e| StartupError::InvalidConfig(format!("questdb.url: {e}")))?;
+
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(StartupError::HttpClient)?;
+
+        Self::new(
+            prisma_client,
+            rds_pool,
+            pg_pool,
+            export_pool,
+            http_client,
+            config,
+        )
+    }
+
+    pub fn session_store(&self) -> RedisSessionStore {
+        RedisSessionStore::from_pool(self.rds_client.clone(), Some(self.session.prefix.clone()))
+            .with_idle_timeout(Duration::from_secs(self.session.idle_timeout_secs))
+    }
+
+    /// Waits up to `timeout` for background jobs, then closes the QuestDB and Redis pools.
+    ///
+    /// The Prisma engine has no explicit disconnect, it is closed when the client is dropped.
+    async fn close(&self, timeout: Duration) {
+        self.tasks.close();
+        if tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            warn!("Background jobs did not finish in time");
+        }
+        self.pg_client.close().await;
+        self.export_pg_client.close().await;
+        if let Err(e) = self.rds_client.quit_pool().await {
+            warn!(error = e.to_string(), "Could not close the Redis pool");
         }
     }
 }
 
 pub struct Application {
-    pub server: Server<AddrIncoming, IntoMakeService<Router>>,
+    pub server: Server<AddrIncoming, IntoMakeServiceWithConnectInfo<Router, SocketAddr>>,
     pub router: Router,
+    state: AppState,
+    pre_stop_delay: Duration,
+    drain_timeout: Duration,
 }
 
 impl Application {
-    pub async fn build(config: Settings) -> Result<Self, AppError> {
-        let prisma_client = try_connect_prisma
-            .retry(&ExponentialBuilder::default())
-            .await
-            .expect("Failed to get Prisma client");
+    pub async fn build(config: Settings) -> Result<Self, StartupError> {
+        config.validate()?;
+        let app_addr = config.application.address_string();
+        let addr = SocketAddr::from_str(&app_addr).map_err(|e| {
+            StartupError::InvalidConfig(format!("{app_addr} is not a valid address: {e}"))
+        })?;
 
-        let pg_pool = { || try_connect_postgres(&config.questdb.url) }
-            .retry(&ExponentialBuilder::default())
-            .await
-            .expect("Could not connect to Postgres");
+        let state = AppState::connect(&config).await?;
+        if !config.questdb.skip_migrations {
+            migrate_questdb(&state, config.questdb.lazy, config.questdb.retry).await?;
+        }
+        spawn_purge_job(state.clone(), config.deletion);
+        spawn_retention_job(state.clone(), config.retention);
+        let router = create_router(
+            state.clone(),
+            &config.application.legacy_sunset,
+            &config.cors,
+        );
 
-        let rds_pool = { || try_connect_redis(&config.redis.url) }
-            .retry(&ExponentialBuilder::default())
-            .await
-            .expect("Could not connect to Redis");
+        let server = axum::Server::try_bind(&addr)
+            .map_err(|source| StartupError::Bind {
+                address: app_addr,
+                source,
+            })?
+            .serve(
+                router
+                    .clone()
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            );
+        Ok(Self {
+            server,
+            router,
+            state,
+            pre_stop_delay: Duration::from_secs(config.application.pre_stop_delay_secs),
+            drain_timeout: Duration::from_secs(config.application.drain_timeout_secs),
+        })
+    }
 
-        let app_addr = config.application.address_string();
-        let state = AppState::new(prisma_client, rds_pool, pg_pool, config.secret);
-        let router = create_router(state);
+    /// Runs the retention job once, as if it was `now`.
+    pub async fn apply_retention(&self, now: DateTime<Utc>) -> eyre::Result<()> {
+        apply_retention(&self.state, now).await
+    }
 
-        let addr = SocketAddr::from_str(&app_addr).expect("Could not parse the address");
-        let server = axum::Server::bind(&addr).serve(router.clone().into_make_service());
-        Ok(Self { server, router })
+    /// Cancelling it starts the same drain as SIGTERM.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.state.shutdown.clone()
     }
 
+    /// Serves until SIGINT or SIGTERM, then drains in-flight requests and background jobs.
     pub async fn run_until_stopped(self) -> Result<(), hyper::Error> {
         info!("Listening on {}", self.server.local_addr());
-        self.server.await
+        let shutdown = self.state.shutdown.clone();
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown_signal().await;
+                shutdown.cancel();
+            }
+        });
+
+        // Readiness answers 503 from here on, the listener only closes after the pre-stop delay
+        let pre_stop_delay = self.pre_stop_delay;
+        let stop_accepting = {
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown.cancelled().await;
+                info!("Accepting connections for {:?} more", pre_stop_delay);
+                tokio::time::sleep(pre_stop_delay).await;
+            }
+        };
+        let server = self.server.with_graceful_shutdown(stop_accepting);
+        tokio::pin!(server);
+
+        // In-flight requests, background jobs and closing the pools share one deadline
+        let (result, deadline) = tokio::select! {
+            result = &mut server => (result, Instant::now() + self.drain_timeout),
+            _ = shutdown.cancelled() => {
+                let deadline = Instant::now() + pre_stop_delay + self.drain_timeout;
+                info!("Draining for at most {:?}", deadline - Instant::now());
+                let result = tokio::time::timeout_at(deadline, &mut server)
+                    .await
+                    .unwrap_or_else(|_| {
+                        warn!("Drain timeout reached, dropping the remaining connections");
+                        Ok(())
+                    });
+                (result, deadline)
+            }
+        };
+
+        self.state
+            .close(deadline.saturating_duration_since(Instant::now()))
+            .await;
+        info!("Shut down");
+        result
     }
 }
 
-#[instrument(name = "Connecting Prisma")]
-async fn try_connect_prisma() -> Result<PrismaClient, NewClientError> {
-    PrismaClient::_builder().build().await
+#[instrument(name = "Connecting Prisma", skip_all)]
+async fn try_connect_prisma(url: &str) -> Result<PrismaClient, NewClientError> {
+    PrismaClient::_builder()
+        .with_url(url.to_string())
+        .build()
+        .await
 }
 
-#[instrument(name = "Connecting Postgres")]
+#[instrument(name = "Connecting Redis", skip_all)]
 async fn try_connect_redis(url: &str) -> Result<RedisPool, RedisError> {
     // Redis pool creation
-    let rds_config = RedisConfig::from_url(url)?;
+    let mut rds_config = RedisConfig::from_url(url)?;
+    // Each command gets a span under the one that sends it
+    rds_config.tracing.enabled = true;
     let rds_pool = RedisPool::new(rds_config, None, None, 6)?;
     rds_pool.connect();
     rds_pool.wait_for_connect().await?;
     Ok(rds_pool)
 }
 
-#[instrument(name = "Connecting Redis")]
+/// A lazy start only migrates once QuestDB is reachable, without holding up the server.
+async fn migrate_questdb(
+    state: &AppState,
+    lazy: bool,
+    retry: RetrySettings,
+) -> Result<(), StartupError> {
+    if !lazy {
+        run_migrations(&state.pg_client, &state.rds_client)
+            .await
+            .map_err(StartupError::QuestDBMigration)?;
+        return Ok(());
+    }
+
+    let pg_client = state.pg_client.clone();
+    let rds_client = state.rds_client.clone();
+    let shutdown = state.shutdown.clone();
+    state.tasks.spawn(async move {
+        let migrate = { || run_migrations(&pg_client, &rds_client) }
+            .retry(&retry.backoff())
+            .notify(|e, delay| warn!(error = %e, ?delay, "Retrying QuestDB migrations"));
+        tokio::select! {
+            result = migrate => {
+                if let Err(e) = result {
+                    error!(error = %e, "Could not migrate the QuestDB schema");
+                }
+            }
+            _ = shutdown.cancelled() => {}
+        }
+    });
+    Ok(())
+}
+
+#[instrument(name = "Connecting QuestDB", skip_all)]
 async fn try_connect_postgres(url: &str) -> Result<Pool<Postgres>, sqlx::Error> {
     // set up connection pool for QuestDB
     PgPoolOptions::new().max_connections(10).connect(url).await
@@ -1,16 +1,31 @@
+use clap::Parser;
 use tonsail_server::{
-    configuration::get_configuration, util::tracing::initialize_tracing, Application,
+    admin::{self, Cli, Command},
+    configuration::get_configuration,
+    util::tracing::{initialize_tracing, shutdown_tracing},
+    Application,
 };
 
 #[tokio::main]
-async fn main() -> Result<(), hyper::Error> {
-    initialize_tracing().await;
+async fn main() -> eyre::Result<()> {
+    let cli = Cli::parse();
+    let config = get_configuration()?;
 
-    let config = get_configuration().expect("Failed to read configuration");
+    initialize_tracing(&config.telemetry).await?;
 
-    let application = Application::build(config)
-        .await
-        .expect("Could not build the app");
+    if let Some(command) = cli.command.filter(|c| !matches!(c, Command::Serve)) {
+        let result = admin::run(command, config).await;
+        shutdown_tracing();
+        return result;
+    }
 
-    application.run_until_stopped().await
+    // The error names the dependency or setting that stopped the startup
+    let application = Application::build(config).await.map_err(|e| {
+        tracing::error!(error = %e, "Could not build the app");
+        e
+    })?;
+
+    let result = application.run_until_stopped().await;
+    shutdown_tracing();
+    Ok(result?)
 }
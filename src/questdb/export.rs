@@ -0,0 +1,365 @@
+use super::{is_deleted, rollup::Resolution, Metrics};
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, TimestampMicrosecondArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use bytes::Bytes;
+use futures::{channel::mpsc, SinkExt, Stream, TryStreamExt};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use prisma_client_rust::chrono::{DateTime, NaiveDateTime, Utc};
+use sea_query::{Alias, Expr, Order, PostgresQueryBuilder, Query as SeaQuery, SelectStatement};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio_util::task::TaskTracker;
+use tracing::error;
+use utoipa::ToSchema;
+
+/// Text formats are sent in chunks of about this size.
+const CHUNK_BYTES: usize = 64 * 1024;
+/// Rows per Parquet row group, each group is sent once it is complete.
+const ROW_GROUP_ROWS: usize = 65_536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        [Self::Csv, Self::Jsonl, Self::Parquet]
+            .into_iter()
+            .find(|f| f.extension() == extension)
+    }
+}
+
+/// Which samples of a run are exported.
+#[derive(Debug, Clone, Default)]
+pub struct SampleFilter {
+    pub name: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// One raw sample, with the column names of the `metrics` table.
+#[derive(Debug, Serialize, FromRow)]
+struct ExportRow {
+    name: String,
+    #[serde(rename = "runID")]
+    #[sqlx(rename = "runID")]
+    run_id: String,
+    scenario: Option<String>,
+    url: Option<String>,
+    method: Option<String>,
+    status: Option<String>,
+    ts: NaiveDateTime,
+    value: f64,
+}
+
+fn filtered(select: &mut SelectStatement, run_id: &str, filter: &SampleFilter) {
+    let format = |ts: &DateTime<Utc>| ts.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string();
+    select
+        .from(Metrics::Table)
+        .and_where(Expr::col(Metrics::RunID).eq(run_id));
+    if let Some(name) = &filter.name {
+        select.and_where(Expr::col(Metrics::Name).eq(name.as_str()));
+    }
+    if let Some(from) = &filter.from {
+        select.and_where(Expr::col(Metrics::Ts).gte(format(from)));
+    }
+    if let Some(to) = &filter.to {
+        select.and_where(Expr::col(Metrics::Ts).lt(format(to)));
+    }
+}
+
+/// Number of raw samples an export would hold.
+pub async fn count_samples(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+    filter: &SampleFilter,
+) -> Result<i64, sqlx::Error> {
+    if is_deleted(pg_client, run_id, Resolution::Raw).await? {
+        return Ok(0);
+    }
+    let mut select = SeaQuery::select();
+    select.expr(Expr::cust("count()"));
+    filtered(&mut select, run_id, filter);
+    let (count,): (i64,) = sqlx::query_as(&select.to_string(PostgresQueryBuilder))
+        .fetch_one(pg_client)
+        .await?;
+    Ok(count)
+}
+
+/// Streams the encoded samples, reading them from QuestDB as the receiver keeps up.
+///
+/// The reader runs on `tasks`, so a shutdown waits for the exports in flight. It gives up
+/// once the receiver takes no chunk for `send_timeout`, which frees its connection.
+pub fn stream_samples(
+    pg_client: Pool<Postgres>,
+    tasks: &TaskTracker,
+    run_id: &str,
+    filter: &SampleFilter,
+    format: ExportFormat,
+    send_timeout: Duration,
+) -> impl Stream<Item = Result<Bytes, io::Error>> {
+    let mut select = SeaQuery::select();
+    select
+        .columns([
+            Metrics::Name,
+            Metrics::RunID,
+            Metrics::Scenario,
+            Metrics::Url,
+            Metrics::Method,
+            Metrics::Status,
+            Metrics::Ts,
+        ])
+        .expr_as(Expr::cust("cast(value AS double)"), Alias::new("value"))
+        .order_by(Metrics::Ts, Order::Asc);
+    filtered(&mut select, run_id, filter);
+    let sql = select.to_string(PostgresQueryBuilder);
+
+    let run_id = run_id.to_string();
+    let (mut tx, rx) = mpsc::channel(4);
+    tasks.spawn(async move {
+        let result = encode_rows(&pg_client, &run_id, &sql, format, send_timeout, &mut tx).await;
+        if let Err(e) = result {
+            error!(error = e.to_string(), "Could not export samples");
+            // A receiver that stopped reading would never take it
+            let _ = tx.try_send(Err(io::Error::new(io::ErrorKind::Other, e.to_string())));
+        }
+    });
+    rx
+}
+
+/// Encodes the rows `sql` selects, a deleted run exports as an empty file.
+async fn encode_rows(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+    sql: &str,
+    format: ExportFormat,
+    send_timeout: Duration,
+    tx: &mut mpsc::Sender<Result<Bytes, io::Error>>,
+) -> eyre::Result<()> {
+    let mut encoder = Encoder::new(format)?;
+    if !is_deleted(pg_client, run_id, Resolution::Raw).await? {
+        let mut rows = sqlx::query_as::<_, ExportRow>(sql).fetch(pg_client);
+        while let Some(row) = rows.try_next().await? {
+            if let Some(chunk) = encoder.push(row)? {
+                send_chunk(tx, chunk, send_timeout).await?;
+            }
+        }
+    }
+    let chunk = encoder.finish()?;
+    if !chunk.is_empty() {
+        send_chunk(tx, chunk, send_timeout).await?;
+    }
+    Ok(())
+}
+
+async fn send_chunk(
+    tx: &mut mpsc::Sender<Result<Bytes, io::Error>>,
+    chunk: Bytes,
+    send_timeout: Duration,
+) -> eyre::Result<()> {
+    tokio::time::timeout(send_timeout, tx.send(Ok(chunk)))
+        .await
+        .map_err(|_| eyre::eyre!("the receiver took no chunk for {send_timeout:?}"))??;
+    Ok(())
+}
+
+/// Bytes the Parquet writer wrote since they were last taken.
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuf {
+    fn take(&self) -> Bytes {
+        Bytes::from(std::mem::take(&mut *self.0.lock().unwrap()))
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Encoder {
+    Text {
+        format: ExportFormat,
+        buf: Vec<u8>,
+    },
+    Parquet {
+        writer: ArrowWriter<SharedBuf>,
+        sink: SharedBuf,
+        schema: SchemaRef,
+        rows: Vec<ExportRow>,
+    },
+}
+
+impl Encoder {
+    fn new(format: ExportFormat) -> eyre::Result<Self> {
+        Ok(match format {
+            ExportFormat::Csv => Encoder::Text {
+                format,
+                buf: b"name,runID,scenario,url,method,status,ts,value\n".to_vec(),
+            },
+            ExportFormat::Jsonl => Encoder::Text {
+                format,
+                buf: vec![],
+            },
+            ExportFormat::Parquet => {
+                let schema = parquet_schema();
+                let sink = SharedBuf::default();
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                Encoder::Parquet {
+                    writer: ArrowWriter::try_new(sink.clone(), schema.clone(), Some(props))?,
+                    sink,
+                    schema,
+                    rows: Vec::with_capacity(ROW_GROUP_ROWS),
+                }
+            }
+        })
+    }
+
+    /// Adds a row, returning a chunk once enough is buffered.
+    fn push(&mut self, row: ExportRow) -> eyre::Result<Option<Bytes>> {
+        match self {
+            Encoder::Text { format, buf } => {
+                match format {
+                    ExportFormat::Csv => write_csv_row(buf, &row),
+                    _ => {
+                        serde_json::to_writer(&mut *buf, &row)?;
+                        buf.push(b'\n');
+                    }
+                }
+                Ok((buf.len() >= CHUNK_BYTES).then(|| Bytes::from(std::mem::take(buf))))
+            }
+            Encoder::Parquet {
+                writer,
+                sink,
+                schema,
+                rows,
+            } => {
+                rows.push(row);
+                if rows.len() < ROW_GROUP_ROWS {
+                    return Ok(None);
+                }
+                writer.write(&record_batch(schema, rows)?)?;
+                writer.flush()?;
+                rows.clear();
+                Ok(Some(sink.take()))
+            }
+        }
+    }
+
+    /// Whatever is left, with the Parquet footer.
+    fn finish(self) -> eyre::Result<Bytes> {
+        match self {
+            Encoder::Text { buf, .. } => Ok(Bytes::from(buf)),
+            Encoder::Parquet {
+                mut writer,
+                sink,
+                schema,
+                rows,
+            } => {
+                if !rows.is_empty() {
+                    writer.write(&record_batch(&schema, &rows)?)?;
+                }
+                writer.close()?;
+                Ok(sink.take())
+            }
+        }
+    }
+}
+
+fn write_csv_row(buf: &mut Vec<u8>, row: &ExportRow) {
+    let fields = [
+        row.name.as_str(),
+        row.run_id.as_str(),
+        row.scenario.as_deref().unwrap_or_default(),
+        row.url.as_deref().unwrap_or_default(),
+        row.method.as_deref().unwrap_or_default(),
+        row.status.as_deref().unwrap_or_default(),
+    ];
+    for field in fields {
+        if field.contains([',', '"', '\n', '\r']) {
+            buf.push(b'"');
+            buf.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+            buf.push(b'"');
+        } else {
+            buf.extend_from_slice(field.as_bytes());
+        }
+        buf.push(b',');
+    }
+    let ts = row.ts.format("%Y-%m-%dT%H:%M:%S%.6fZ");
+    let _ = writeln!(buf, "{ts},{}", row.value);
+}
+
+fn parquet_schema() -> SchemaRef {
+    let text = |name: &str| Field::new(name, DataType::Utf8, true);
+    Arc::new(Schema::new(vec![
+        Field::new("name", DataType::Utf8, false),
+        Field::new("runID", DataType::Utf8, false),
+        text("scenario"),
+        text("url"),
+        text("method"),
+        text("status"),
+        Field::new(
+            "ts",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("value", DataType::Float64, false),
+    ]))
+}
+
+fn record_batch(schema: &SchemaRef, rows: &[ExportRow]) -> eyre::Result<RecordBatch> {
+    let required = |f: fn(&ExportRow) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(rows.iter().map(f)))
+    };
+    let optional = |f: fn(&ExportRow) -> Option<&str>| -> ArrayRef {
+        Arc::new(rows.iter().map(f).collect::<StringArray>())
+    };
+    let ts =
+        TimestampMicrosecondArray::from_iter_values(rows.iter().map(|r| r.ts.timestamp_micros()))
+            .with_timezone("UTC".to_string());
+    let columns = vec![
+        required(|r| &r.name),
+        required(|r| &r.run_id),
+        optional(|r| r.scenario.as_deref()),
+        optional(|r| r.url.as_deref()),
+        optional(|r| r.method.as_deref()),
+        optional(|r| r.status.as_deref()),
+        Arc::new(ts) as ArrayRef,
+        Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.value))),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
@@ -0,0 +1,261 @@
+use super::table_exists;
+use crate::util::nano_id::generate_id;
+use fred::{pool::RedisPool, prelude::*};
+use sqlx::{Pool, Postgres};
+use std::{collections::HashSet, time::Duration};
+use tracing::{info, instrument, warn};
+
+const MIGRATIONS_TABLE: &str = "questdb_migrations";
+/// Held by the server that migrates, the others wait for it
+const LOCK_KEY: &str = "tonsail-questdb-migrations/lock";
+/// Frees the lock of a server that died while migrating
+const LOCK_TTL_SECS: i64 = 600;
+
+// Deletes the lock only if it is still ours, it may have expired and been taken since.
+const RELEASE_LOCK: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+  return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error(transparent)]
+    QuestDB(#[from] sqlx::Error),
+    #[error("Could not lock the QuestDB migrations")]
+    Lock(#[from] RedisError),
+}
+
+enum Step {
+    Sql(&'static str),
+    /// Rebuilds a table created without a designated timestamp, e.g. by ILP auto-creation
+    Partition(&'static str),
+    /// Adds a column as `(table, column, type)` unless the table has it
+    AddColumn(&'static str, &'static str, &'static str),
+}
+
+struct Migration {
+    version: i64,
+    name: &'static str,
+    steps: &'static [Step],
+}
+
+/// Every migration, in the order they are applied. Never edit one that has shipped.
+///
+/// Each step must be safe to run again, a crash may leave a migration half applied.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_metrics",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS metrics (\
+                 name SYMBOL CAPACITY 256 CACHE, \
+                 runID SYMBOL CAPACITY 65536 NOCACHE, \
+                 scenario SYMBOL CAPACITY 1024 CACHE, \
+                 url SYMBOL CAPACITY 65536 NOCACHE, \
+                 method SYMBOL CAPACITY 16 CACHE, \
+                 status SYMBOL CAPACITY 128 CACHE, \
+                 ts TIMESTAMP, value FLOAT) \
+                 TIMESTAMP(ts) PARTITION BY DAY",
+            ),
+            Step::Partition("metrics"),
+        ],
+    },
+    Migration {
+        version: 2,
+        name: "create_rollups",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS metrics_1m (\
+                 name SYMBOL CAPACITY 256 CACHE, \
+                 runID SYMBOL CAPACITY 65536 NOCACHE, \
+                 scenario SYMBOL CAPACITY 1024 CACHE, \
+                 url SYMBOL CAPACITY 65536 NOCACHE, \
+                 method SYMBOL CAPACITY 16 CACHE, \
+                 status SYMBOL CAPACITY 128 CACHE, \
+                 ts TIMESTAMP, value_sum DOUBLE, value_count LONG, \
+                 value_min DOUBLE, value_max DOUBLE) \
+                 TIMESTAMP(ts) PARTITION BY DAY",
+            ),
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS metrics_1h (\
+                 name SYMBOL CAPACITY 256 CACHE, \
+                 runID SYMBOL CAPACITY 65536 NOCACHE, \
+                 scenario SYMBOL CAPACITY 1024 CACHE, \
+                 url SYMBOL CAPACITY 65536 NOCACHE, \
+                 method SYMBOL CAPACITY 16 CACHE, \
+                 status SYMBOL CAPACITY 128 CACHE, \
+                 ts TIMESTAMP, value_sum DOUBLE, value_count LONG, \
+                 value_min DOUBLE, value_max DOUBLE) \
+                 TIMESTAMP(ts) PARTITION BY MONTH",
+            ),
+        ],
+    },
+    Migration {
+        version: 3,
+        name: "tag_uncatalogued_metrics",
+        steps: &[Step::AddColumn("metrics", "uncatalogued", "BOOLEAN")],
+    },
+    Migration {
+        version: 4,
+        name: "roll_up_squared_sums",
+        steps: &[
+            Step::AddColumn("metrics_1m", "value_sq_sum", "DOUBLE"),
+            Step::AddColumn("metrics_1h", "value_sq_sum", "DOUBLE"),
+        ],
+    },
+    Migration {
+        version: 5,
+        name: "create_metrics_tombstones",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS metrics_tombstones (\
+             runID SYMBOL CAPACITY 65536 NOCACHE, rawOnly BOOLEAN, ts TIMESTAMP) \
+             TIMESTAMP(ts) PARTITION BY MONTH",
+        )],
+    },
+];
+
+/// Applies the migrations that are not recorded yet and returns their versions.
+///
+/// Servers starting together take turns through a Redis lock, the later ones find the
+/// migrations recorded and apply nothing.
+#[instrument(name = "Migrating QuestDB", skip_all)]
+pub async fn run_migrations(
+    pg_client: &Pool<Postgres>,
+    rds_client: &RedisPool,
+) -> Result<Vec<i64>, MigrationError> {
+    let token = generate_id();
+    loop {
+        let locked: Option<String> = rds_client
+            .set(
+                LOCK_KEY,
+                token.as_str(),
+                Some(Expiration::EX(LOCK_TTL_SECS)),
+                Some(SetOptions::NX),
+                false,
+            )
+            .await?;
+        if locked.is_some() {
+            break;
+        }
+        info!("Waiting for another server to migrate QuestDB");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let result = apply_migrations(pg_client).await;
+    let released: Result<i64, _> = rds_client
+        .eval(RELEASE_LOCK, LOCK_KEY, token.as_str())
+        .await;
+    if let Err(e) = released {
+        warn!(error = %e, "Could not release the QuestDB migration lock");
+    }
+    Ok(result?)
+}
+
+async fn apply_migrations(pg_client: &Pool<Postgres>) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS {MIGRATIONS_TABLE} \
+         (version LONG, name STRING, applied_at TIMESTAMP)"
+    ))
+    .execute(pg_client)
+    .await?;
+    let applied: HashSet<i64> = applied_versions(pg_client).await?.into_iter().collect();
+
+    let mut ran = vec![];
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        for step in migration.steps {
+            match step {
+                Step::Sql(sql) => {
+                    sqlx::query(sql).execute(pg_client).await?;
+                }
+                Step::Partition(table) => partition_by_day(pg_client, table).await?,
+                Step::AddColumn(table, column, kind) => {
+                    add_column(pg_client, table, column, kind).await?
+                }
+            }
+        }
+        sqlx::query(&format!(
+            "INSERT INTO {MIGRATIONS_TABLE} VALUES ($1, $2, now())"
+        ))
+        .bind(migration.version)
+        .bind(migration.name)
+        .execute(pg_client)
+        .await?;
+        info!(
+            version = migration.version,
+            name = migration.name,
+            "Applied migration"
+        );
+        ran.push(migration.version);
+    }
+    Ok(ran)
+}
+
+/// Versions recorded as applied, oldest first.
+pub async fn applied_versions(pg_client: &Pool<Postgres>) -> Result<Vec<i64>, sqlx::Error> {
+    if !table_exists(pg_client, MIGRATIONS_TABLE).await? {
+        return Ok(vec![]);
+    }
+    let rows: Vec<(i64,)> = sqlx::query_as(&format!(
+        "SELECT DISTINCT version FROM {MIGRATIONS_TABLE} ORDER BY version"
+    ))
+    .fetch_all(pg_client)
+    .await?;
+    Ok(rows.into_iter().map(|(v,)| v).collect())
+}
+
+async fn add_column(
+    pg_client: &Pool<Postgres>,
+    table: &str,
+    column: &str,
+    kind: &str,
+) -> Result<(), sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as(&format!(
+        "SELECT count() FROM table_columns('{table}') WHERE \"column\" = $1"
+    ))
+    .bind(column)
+    .fetch_one(pg_client)
+    .await?;
+    if count == 0 {
+        sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"))
+            .execute(pg_client)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Copies a table without a designated timestamp into one partitioned by day.
+///
+/// Only runs under the migration lock, the copy is named after the table so nothing else
+/// writes to it.
+async fn partition_by_day(pg_client: &Pool<Postgres>, table: &str) -> Result<(), sqlx::Error> {
+    let (designated,): (Option<String>,) =
+        sqlx::query_as("SELECT designatedTimestamp FROM tables() WHERE name = $1")
+            .bind(table)
+            .fetch_one(pg_client)
+            .await?;
+    if designated.is_some() {
+        return Ok(());
+    }
+
+    let copy = format!("{table}_partitioned");
+    sqlx::query(&format!("DROP TABLE IF EXISTS {copy}"))
+        .execute(pg_client)
+        .await?;
+    sqlx::query(&format!(
+        "CREATE TABLE {copy} AS (SELECT * FROM {table} ORDER BY ts) \
+         TIMESTAMP(ts) PARTITION BY DAY"
+    ))
+    .execute(pg_client)
+    .await?;
+    sqlx::query(&format!("DROP TABLE {table}"))
+        .execute(pg_client)
+        .await?;
+    sqlx::query(&format!("RENAME TABLE {copy} TO {table}"))
+        .execute(pg_client)
+        .await?;
+    info!(table, "Partitioned table by day");
+    Ok(())
+}
@@ -0,0 +1,270 @@
+use prisma_client_rust::chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use rollup::Resolution;
+use sea_query::Iden;
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::collections::BTreeSet;
+use tracing::{info, instrument};
+use utoipa::ToSchema;
+
+pub mod export;
+pub mod migrations;
+pub mod rollup;
+pub mod summary;
+
+const TOMBSTONES_TABLE: &str = "metrics_tombstones";
+/// Rows of one bound `INSERT`, nine parameters each stay below the protocol's 65535
+const MAX_ROWS_PER_INSERT: usize = 5000;
+
+#[derive(Iden)]
+pub enum Metrics {
+    Table,
+    Name,
+    #[iden = "runID"]
+    RunID,
+    Scenario,
+    Url,
+    Method,
+    Status,
+    Ts,
+    Value,
+    Uncatalogued,
+}
+
+/// One measurement of a run, as sent to the ingestion route.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct Sample {
+    pub name: String,
+    #[serde(default)]
+    pub scenario: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub method: String,
+    #[serde(default)]
+    pub status: String,
+    pub ts: DateTime<Utc>,
+    pub value: f64,
+}
+
+/// Writes the samples of a run into `metrics`, flagging the names in `uncatalogued`.
+#[instrument(name = "Inserting samples", skip_all, fields(samples = samples.len()))]
+pub async fn insert_samples(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+    samples: &[Sample],
+    uncatalogued: &BTreeSet<String>,
+) -> Result<(), sqlx::Error> {
+    if samples.is_empty() {
+        return Ok(());
+    }
+
+    for chunk in samples.chunks(MAX_ROWS_PER_INSERT) {
+        let rows: Vec<String> = (0..chunk.len())
+            .map(|row| {
+                let params: Vec<String> = (1..=9)
+                    .map(|column| format!("${}", row * 9 + column))
+                    .collect();
+                format!(
+                    "({}, cast({} AS TIMESTAMP), cast({} AS FLOAT), {})",
+                    params[..6].join(", "),
+                    params[6],
+                    params[7],
+                    params[8]
+                )
+            })
+            .collect();
+        let sql = format!(
+            "INSERT INTO metrics \
+             (name, runID, scenario, url, method, status, ts, value, uncatalogued) VALUES {}",
+            rows.join(", ")
+        );
+
+        let mut query = sqlx::query(&sql);
+        for sample in chunk {
+            // QuestDB casts the string to the designated timestamp
+            let ts = sample.ts.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string();
+            query = query
+                .bind(&sample.name)
+                .bind(run_id)
+                .bind(&sample.scenario)
+                .bind(&sample.url)
+                .bind(&sample.method)
+                .bind(&sample.status)
+                .bind(ts)
+                .bind(sample.value)
+                .bind(uncatalogued.contains(&sample.name));
+        }
+        query.execute(pg_client).await?;
+    }
+    Ok(())
+}
+
+#[instrument(name = "Checking QuestDB table", skip(pg_client))]
+pub async fn table_exists(pg_client: &Pool<Postgres>, table: &str) -> Result<bool, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as("SELECT count() FROM tables() WHERE name = $1")
+        .bind(table)
+        .fetch_one(pg_client)
+        .await?;
+    Ok(count > 0)
+}
+
+/// Number of samples ingested for one run.
+#[instrument(name = "Counting run samples", skip(pg_client))]
+pub async fn run_sample_count(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+) -> Result<i64, sqlx::Error> {
+    if !table_exists(pg_client, "metrics").await? {
+        return Ok(0);
+    }
+    let (count,): (i64,) = sqlx::query_as("SELECT count() FROM metrics WHERE runID = $1")
+        .bind(run_id)
+        .fetch_one(pg_client)
+        .await?;
+    Ok(count)
+}
+
+/// Marks every sample of the given runs as deleted, at every resolution.
+#[instrument(name = "Deleting run metrics", skip_all, fields(runs = run_ids.len()))]
+pub async fn delete_runs(
+    pg_client: &Pool<Postgres>,
+    run_ids: &[String],
+) -> Result<(), sqlx::Error> {
+    tombstone_runs(pg_client, run_ids, false).await
+}
+
+/// Marks the raw samples of the given runs as deleted, their rollups are kept.
+#[instrument(name = "Deleting raw run samples", skip_all, fields(runs = run_ids.len()))]
+pub async fn delete_raw_samples(
+    pg_client: &Pool<Postgres>,
+    run_ids: &[String],
+) -> Result<(), sqlx::Error> {
+    tombstone_runs(pg_client, run_ids, true).await
+}
+
+/// QuestDB has no row level `DELETE`, so deleted runs are only recorded. Reads check
+/// `is_deleted` and skip them, `drop_deleted_partitions` frees their storage later.
+async fn tombstone_runs(
+    pg_client: &Pool<Postgres>,
+    run_ids: &[String],
+    raw_only: bool,
+) -> Result<(), sqlx::Error> {
+    if run_ids.is_empty() {
+        return Ok(());
+    }
+
+    let rows: Vec<String> = (1..=run_ids.len())
+        .map(|i| format!("(${i}, {raw_only}, now())"))
+        .collect();
+    let sql = format!("INSERT INTO {TOMBSTONES_TABLE} VALUES {}", rows.join(", "));
+    let mut query = sqlx::query(&sql);
+    for run_id in run_ids {
+        query = query.bind(run_id);
+    }
+    query.execute(pg_client).await?;
+    Ok(())
+}
+
+/// Whether the samples of a run at `resolution` are deleted.
+///
+/// Every read of run samples checks this first, the rows stay on disk until their partition
+/// is dropped.
+#[instrument(name = "Checking run tombstones", skip(pg_client))]
+pub async fn is_deleted(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+    resolution: Resolution,
+) -> Result<bool, sqlx::Error> {
+    let filter = match resolution {
+        Resolution::Raw => "",
+        _ => " AND NOT rawOnly",
+    };
+    let (count,): (i64,) = sqlx::query_as(&format!(
+        "SELECT count() FROM {TOMBSTONES_TABLE} WHERE runID = $1{filter}"
+    ))
+    .bind(run_id)
+    .fetch_one(pg_client)
+    .await?;
+    Ok(count > 0)
+}
+
+/// Drops the oldest partitions of `resolution` while they only hold deleted samples.
+///
+/// Partitions are dropped up to the first sample of a run that is not deleted, so
+/// historical runs and samples without a tombstone are kept with every later partition.
+/// Raw samples are therefore freed at the latest once every older run is past retention,
+/// the rollups of a deleted run once every older run is deleted.
+#[instrument(name = "Dropping deleted partitions", skip(pg_client))]
+pub async fn drop_deleted_partitions(
+    pg_client: &Pool<Postgres>,
+    resolution: Resolution,
+) -> Result<(), sqlx::Error> {
+    let table = resolution.table();
+    let partitioned: Option<(String,)> =
+        sqlx::query_as("SELECT partitionBy FROM tables() WHERE name = $1")
+            .bind(table)
+            .fetch_optional(pg_client)
+            .await?;
+    // A partition is matched by its start, so it is kept unless it spans no live sample
+    let span = match partitioned.as_ref().map(|(p,)| p.as_str()) {
+        Some("HOUR") => Duration::hours(1),
+        Some("DAY") => Duration::days(1),
+        Some("MONTH") => Duration::days(31),
+        Some("YEAR") => Duration::days(366),
+        _ => return Ok(()),
+    };
+    if !table_exists(pg_client, TOMBSTONES_TABLE).await? {
+        return Ok(());
+    }
+
+    let filter = match resolution {
+        Resolution::Raw => "",
+        _ => " WHERE NOT rawOnly",
+    };
+    let deleted: Vec<(String,)> = sqlx::query_as(&format!(
+        "SELECT DISTINCT runID FROM {TOMBSTONES_TABLE}{filter}"
+    ))
+    .fetch_all(pg_client)
+    .await?;
+    let deleted: BTreeSet<String> = deleted.into_iter().map(|(id,)| id).collect();
+    let runs: Vec<(String, NaiveDateTime)> = sqlx::query_as(&format!(
+        "SELECT runID, min(ts) FROM {table} GROUP BY runID"
+    ))
+    .fetch_all(pg_client)
+    .await?;
+    let Some(oldest) = runs.iter().map(|(_, ts)| *ts).min() else {
+        return Ok(());
+    };
+    let kept = runs
+        .iter()
+        .filter(|(run_id, _)| !deleted.contains(run_id))
+        .map(|(_, ts)| *ts)
+        .min();
+    // QuestDB never drops the partition it writes to, the newest one
+    let keep_from = match kept {
+        Some(ts) => ts,
+        None => {
+            let (newest,): (NaiveDateTime,) =
+                sqlx::query_as(&format!("SELECT max(ts) FROM {table}"))
+                    .fetch_one(pg_client)
+                    .await?;
+            newest
+        }
+    };
+    let cutoff = keep_from - span;
+    // Dropping fails when no partition matches
+    if oldest >= cutoff {
+        return Ok(());
+    }
+
+    // QuestDB does not take bind variables in ALTER TABLE
+    let cutoff = cutoff.format("%Y-%m-%dT%H:%M:%S%.6fZ");
+    sqlx::query(&format!(
+        "ALTER TABLE {table} DROP PARTITION WHERE ts < '{cutoff}'"
+    ))
+    .execute(pg_client)
+    .await?;
+    info!(table, %cutoff, "Dropped deleted partitions");
+    Ok(())
+}
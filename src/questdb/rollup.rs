@@ -0,0 +1,75 @@
+use super::table_exists;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use tracing::{info, instrument};
+use utoipa::ToSchema;
+
+/// Resolution of the samples a query reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub enum Resolution {
+    #[serde(rename = "raw")]
+    Raw,
+    #[serde(rename = "1m")]
+    Minute,
+    #[serde(rename = "1h")]
+    Hour,
+}
+
+impl Resolution {
+    pub fn table(&self) -> &'static str {
+        match self {
+            Resolution::Raw => "metrics",
+            Resolution::Minute => "metrics_1m",
+            Resolution::Hour => "metrics_1h",
+        }
+    }
+
+    fn sample_by(&self) -> &'static str {
+        match self {
+            Resolution::Raw => "",
+            Resolution::Minute => "1m",
+            Resolution::Hour => "1h",
+        }
+    }
+}
+
+/// Aggregates the raw samples of a finished run into every rollup table.
+///
+/// QuestDB can not delete the rows of a run, so tables that already hold rows of the run are
+/// skipped, e.g. when the job stopped before it recorded the rollup.
+#[instrument(name = "Rolling up run metrics", skip(pg_client))]
+pub async fn roll_up_run(pg_client: &Pool<Postgres>, run_id: &str) -> Result<(), sqlx::Error> {
+    if !table_exists(pg_client, Resolution::Raw.table()).await? {
+        return Ok(());
+    }
+
+    for resolution in [Resolution::Minute, Resolution::Hour] {
+        let sql = format!(
+            "SELECT count() FROM {} WHERE runID = $1",
+            resolution.table()
+        );
+        let (rows,): (i64,) = sqlx::query_as(&sql)
+            .bind(run_id)
+            .fetch_one(pg_client)
+            .await?;
+        if rows > 0 {
+            info!(?resolution, "Run is already rolled up");
+            continue;
+        }
+
+        // `timestamp(ts)` lets SAMPLE BY work on tables without a designated timestamp
+        let sql = format!(
+            "INSERT INTO {table} \
+             SELECT name, runID, scenario, url, method, status, ts, sum(value) value_sum, \
+             count() value_count, min(value) value_min, max(value) value_max, \
+             sum(cast(value AS double) * cast(value AS double)) value_sq_sum \
+             FROM (SELECT * FROM metrics WHERE runID = $1 ORDER BY ts) timestamp(ts) \
+             SAMPLE BY {bucket} ALIGN TO CALENDAR",
+            table = resolution.table(),
+            bucket = resolution.sample_by(),
+        );
+        let result = sqlx::query(&sql).bind(run_id).execute(pg_client).await?;
+        info!(rows = result.rows_affected(), ?resolution, "Rolled up run");
+    }
+    Ok(())
+}
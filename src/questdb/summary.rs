@@ -0,0 +1,177 @@
+use super::{is_deleted, rollup::Resolution};
+use prisma_client_rust::chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::{FromRow, Pool, Postgres};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+/// Aggregates of one metric over a whole run.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct MetricSummary {
+    pub name: String,
+    pub count: i64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// Samples taken against one endpoint with one response status.
+#[derive(Debug, Clone, FromRow)]
+pub struct EndpointStatus {
+    pub scenario: Option<String>,
+    pub method: Option<String>,
+    pub url: String,
+    pub status: Option<String>,
+    pub count: i64,
+}
+
+/// Mean of a metric over one bucket of the run.
+#[derive(Debug, Clone, FromRow)]
+pub struct SeriesPoint {
+    pub name: String,
+    pub ts: NaiveDateTime,
+    pub value: f64,
+}
+
+/// Sums of one metric against one URL, enough to compare its mean between runs.
+#[derive(Debug, Clone, FromRow)]
+pub struct MetricMoments {
+    pub name: String,
+    pub url: Option<String>,
+    pub count: i64,
+    pub sum: f64,
+    /// Missing from rollups written before squared sums were kept
+    pub sq_sum: Option<f64>,
+}
+
+/// Column expressions over raw samples or over the rollups, as `(count, min, max, mean)`.
+fn aggregates(resolution: Resolution) -> (&'static str, &'static str, &'static str, &'static str) {
+    match resolution {
+        Resolution::Raw => (
+            "count()",
+            "cast(min(value) AS double)",
+            "cast(max(value) AS double)",
+            "avg(cast(value AS double))",
+        ),
+        _ => (
+            "sum(value_count)",
+            "min(value_min)",
+            "max(value_max)",
+            "sum(value_sum) / sum(value_count)",
+        ),
+    }
+}
+
+/// Per metric aggregates of a run, sorted by name.
+#[instrument(name = "Summarizing run metrics", skip(pg_client))]
+pub async fn metric_summaries(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+    resolution: Resolution,
+) -> Result<Vec<MetricSummary>, sqlx::Error> {
+    if is_deleted(pg_client, run_id, resolution).await? {
+        return Ok(vec![]);
+    }
+    let (count, min, max, mean) = aggregates(resolution);
+    let sql = format!(
+        "SELECT name, {count} \"count\", {min} \"min\", {max} \"max\", {mean} \"mean\" \
+         FROM {table} WHERE runID = $1 ORDER BY name",
+        table = resolution.table(),
+    );
+    sqlx::query_as(&sql).bind(run_id).fetch_all(pg_client).await
+}
+
+/// Sample counts of a run per endpoint and response status.
+#[instrument(name = "Counting run statuses", skip(pg_client))]
+pub async fn endpoint_statuses(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+    resolution: Resolution,
+) -> Result<Vec<EndpointStatus>, sqlx::Error> {
+    if is_deleted(pg_client, run_id, resolution).await? {
+        return Ok(vec![]);
+    }
+    let (count, ..) = aggregates(resolution);
+    let sql = format!(
+        "SELECT scenario, method, url, status, {count} \"count\" \
+         FROM {table} WHERE runID = $1 AND url IS NOT NULL AND url != '' \
+         ORDER BY scenario, url, method, status",
+        table = resolution.table(),
+    );
+    sqlx::query_as(&sql).bind(run_id).fetch_all(pg_client).await
+}
+
+/// Moments of each of `names` in a run, per URL.
+#[instrument(name = "Measuring run moments", skip(pg_client))]
+pub async fn metric_moments(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+    names: &[&str],
+    resolution: Resolution,
+) -> Result<Vec<MetricMoments>, sqlx::Error> {
+    if is_deleted(pg_client, run_id, resolution).await? {
+        return Ok(vec![]);
+    }
+    let (count, ..) = aggregates(resolution);
+    let (sum, sq_sum) = match resolution {
+        Resolution::Raw => (
+            "sum(cast(value AS double))",
+            "sum(cast(value AS double) * cast(value AS double))",
+        ),
+        _ => ("sum(value_sum)", "sum(value_sq_sum)"),
+    };
+    let placeholders: Vec<String> = (0..names.len()).map(|i| format!("${}", i + 2)).collect();
+    let sql = format!(
+        "SELECT name, url, {count} \"count\", {sum} \"sum\", {sq_sum} sq_sum \
+         FROM {table} WHERE runID = $1 AND name IN ({names}) \
+         ORDER BY name, url",
+        table = resolution.table(),
+        names = placeholders.join(", "),
+    );
+    let mut query = sqlx::query_as(&sql).bind(run_id);
+    for name in names {
+        query = query.bind(*name);
+    }
+    query.fetch_all(pg_client).await
+}
+
+/// Bucket a series is sampled by, rollups can not be sampled finer than they were aggregated.
+pub fn series_bucket_secs(resolution: Resolution, bucket_secs: i64) -> i64 {
+    match resolution {
+        Resolution::Raw => bucket_secs.max(1),
+        Resolution::Minute => bucket_secs.max(60),
+        Resolution::Hour => bucket_secs.max(3600),
+    }
+}
+
+/// Each metric of a run, or only `name`, averaged over buckets of `bucket_secs`.
+///
+/// Points are sorted by name, then time.
+#[instrument(name = "Sampling run series", skip(pg_client))]
+pub async fn metric_series(
+    pg_client: &Pool<Postgres>,
+    run_id: &str,
+    name: Option<&str>,
+    resolution: Resolution,
+    bucket_secs: i64,
+) -> Result<Vec<SeriesPoint>, sqlx::Error> {
+    if is_deleted(pg_client, run_id, resolution).await? {
+        return Ok(vec![]);
+    }
+    let (.., mean) = aggregates(resolution);
+    let bucket_secs = series_bucket_secs(resolution, bucket_secs);
+    let sql = format!(
+        "SELECT name, ts, {mean} value \
+         FROM (SELECT * FROM {table} WHERE runID = $1{name_filter} ORDER BY ts) timestamp(ts) \
+         SAMPLE BY {bucket_secs}s ALIGN TO CALENDAR",
+        table = resolution.table(),
+        name_filter = if name.is_some() { " AND name = $2" } else { "" },
+    );
+    let mut query = sqlx::query_as(&sql).bind(run_id);
+    if let Some(name) = name {
+        query = query.bind(name);
+    }
+    let mut points: Vec<SeriesPoint> = query.fetch_all(pg_client).await?;
+    points.sort_by(|a, b| (&a.name, a.ts).cmp(&(&b.name, b.ts)));
+    Ok(points)
+}
@@ -0,0 +1,83 @@
+use super::AppState;
+use crate::{
+    domain::{audit::AuditQuery, auth::TonsailUser},
+    prisma::{audit_log, UserRole},
+    util::{
+        app_error::AppError,
+        pagination::{decode_cursor, Page},
+        validation::ValidatedQuery,
+    },
+};
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use prisma_client_rust::Direction;
+use tracing::instrument;
+
+#[utoipa::path(
+    get, path = "/v1/organizations/{organization_id}/audit", tag = "audit",
+    security(("session" = [])),
+    params(("organization_id" = String, Path, description = "Organization id"), AuditQuery),
+    responses(
+        (
+            status = 200,
+            description = "One page of audit entries, newest first",
+            body = crate::util::pagination::AuditLogPage
+        ),
+        (
+            status = 403,
+            description = "Requires an owner or admin of the organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Fetching audit log", skip_all)]
+pub async fn get_audit_log(
+    Path(org_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(user): Extension<TonsailUser>,
+    ValidatedQuery(query): ValidatedQuery<AuditQuery>,
+) -> Result<Response, AppError> {
+    if user.organization_id() != org_id || user.role() == UserRole::Member {
+        return Err(AppError::RequireAdmin("the audit log".to_string()));
+    }
+
+    let mut filters = vec![audit_log::organization_id::equals(org_id)];
+    if let Some(action) = query.action {
+        filters.push(audit_log::action::equals(action));
+    }
+    if let Some(actor_id) = query.actor_id {
+        filters.push(audit_log::actor_id::equals(Some(actor_id)));
+    }
+    if let Some(target_type) = query.target_type {
+        filters.push(audit_log::target_type::equals(target_type));
+    }
+    if let Some(target_id) = query.target_id {
+        filters.push(audit_log::target_id::equals(target_id));
+    }
+    if let Some(from) = query.from {
+        filters.push(audit_log::created_at::gte(from));
+    }
+    if let Some(to) = query.to {
+        filters.push(audit_log::created_at::lte(to));
+    }
+
+    let mut find = state
+        .db_client
+        .audit_log()
+        .find_many(filters)
+        .order_by(audit_log::created_at::order(Direction::Desc))
+        .order_by(audit_log::id::order(Direction::Desc))
+        .take(query.limit + 1);
+    if let Some(cursor) = query.cursor {
+        find = find
+            .cursor(audit_log::id::equals(decode_cursor(&cursor)?))
+            .skip(1);
+    }
+    let items = find.exec().await?;
+
+    Ok(Json(Page::new(items, query.limit, |i| &i.id)).into_response())
+}
@@ -1,7 +1,8 @@
 use super::AppState;
 use crate::domain::auth::{AuthContext, AuthLoginForm, AuthRegisterForm, TonsailUser};
-use crate::prisma::{organization, user};
+use crate::prisma::{organization, user, UserRole};
 use crate::util::app_error::AppError;
+use crate::util::audit::{record_audit, AuditEntry, RequestMeta};
 use crate::util::hash::{check_hash, hash_password};
 use crate::util::nano_id::generate_id;
 use axum::extract::State;
@@ -11,6 +12,19 @@ use http::StatusCode;
 use prisma_client_rust::QueryError;
 use tracing::instrument;
 
+#[utoipa::path(
+    get, path = "/v1/me", tag = "auth",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The logged in user", body = crate::domain::schemas::User),
+        (
+            status = 401,
+            description = "Not logged in",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 pub async fn check_me(auth: AuthContext) -> Result<Response, AppError> {
     match auth.current_user {
         Some(user) => Ok(Json(user).into_response()),
@@ -18,31 +32,70 @@ pub async fn check_me(auth: AuthContext) -> Result<Response, AppError> {
     }
 }
 
+#[utoipa::path(
+    post, path = "/v1/logout", tag = "auth",
+    security(("session" = [])),
+    responses((status = 200, description = "Logged out"))
+)]
 #[instrument(name = "User attempting to logout", skip_all)]
 pub async fn logout(mut auth: AuthContext) -> StatusCode {
     auth.logout().await;
     StatusCode::OK
 }
 
+#[utoipa::path(
+    post, path = "/v1/login", tag = "auth",
+    request_body(
+        content = crate::domain::auth::AuthLoginForm,
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Logged in, sets the session cookie",
+            body = crate::domain::schemas::User
+        ),
+        (
+            status = 401,
+            description = "Wrong credentials",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "User attempting to login", skip_all)]
 pub async fn login(
     State(state): State<AppState>,
+    meta: RequestMeta,
     mut auth: AuthContext,
     Form(user): Form<AuthLoginForm>,
 ) -> Result<Response, AppError> {
     let resp = state
         .db_client
         .user()
-        .find_first(vec![user::email::equals(user.email)])
+        .find_first(vec![
+            user::email::equals(user.email),
+            user::deleted_at::equals(None),
+        ])
         .exec()
         .await?;
 
     match resp {
         Some(data) => {
-            check_hash(user.password.as_bytes(), &data.password)?;
+            if let Err(e) = check_hash(user.password.as_bytes(), &data.password) {
+                let entry =
+                    AuditEntry::new("user.login_failed", &data.organization_id, "user", &data.id);
+                record_audit(&state, None, &meta, entry).await;
+                return Err(e);
+            }
             let user = TonsailUser::from(data);
             match auth.login(&user).await {
-                Ok(_) => Ok((StatusCode::OK, Json(user)).into_response()),
+                Ok(_) => {
+                    let entry =
+                        AuditEntry::new("user.login", user.organization_id(), "user", user.id());
+                    record_audit(&state, Some(user.id()), &meta, entry).await;
+                    Ok((StatusCode::OK, Json(user)).into_response())
+                }
                 Err(_) => Err(AppError::UnAuthorized("Unable to login".to_string())),
             }
         }
@@ -50,12 +103,35 @@ pub async fn login(
     }
 }
 
+#[utoipa::path(
+    post, path = "/v1/register", tag = "auth",
+    request_body(
+        content = crate::domain::auth::AuthRegisterForm,
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The new user, owner of a new organization",
+            body = crate::domain::schemas::User
+        ),
+        (
+            status = 400,
+            description = "Invalid form",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Registering new user", skip_all)]
 pub async fn register_new_user(
     State(state): State<AppState>,
+    meta: RequestMeta,
     Form(user): Form<AuthRegisterForm>,
 ) -> Result<Response, AppError> {
-    let user = create_user(State(state), Form(user)).await?;
+    let user = create_user(State(state.clone()), Form(user)).await?;
+    let entry = AuditEntry::new("user.registered", &user.organization_id, "user", &user.id);
+    record_audit(&state, Some(&user.id), &meta, entry).await;
     Ok(Json(user).into_response())
 }
 
@@ -82,7 +158,7 @@ async fn create_user(
                     hash_password(user.password.as_bytes()),
                     user.name,
                     organization::id::equals(new_org.id.clone()),
-                    vec![],
+                    vec![user::role::set(UserRole::Owner)],
                 )
                 .exec()
                 .await
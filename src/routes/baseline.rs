@@ -0,0 +1,190 @@
+use super::AppState;
+use crate::{
+    domain::{
+        auth::TonsailUser,
+        organization::{find_organization_run, organization_of_test},
+        regression::{detect_regressions, BaselineForm},
+        webhook::{queue_event, RunRegressed, WebhookEvent},
+    },
+    prisma::{test, test_run, RunStatus},
+    util::{
+        app_error::AppError,
+        audit::{record_audit, AuditEntry, RequestMeta},
+        validation::ValidatedForm,
+    },
+};
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use http::StatusCode;
+use serde_json::json;
+use tracing::{instrument, warn};
+
+/// Owner of the test when it is one of the actor's organization.
+async fn actor_test_org(
+    state: &AppState,
+    actor: &TonsailUser,
+    test_id: &str,
+) -> Result<String, AppError> {
+    organization_of_test(&state.db_client, test_id)
+        .await?
+        .filter(|org_id| org_id == actor.organization_id())
+        .ok_or_else(|| AppError::NotFound("No such test exists".to_string()))
+}
+
+#[utoipa::path(
+    put, path = "/v1/tests/{test_id}/baseline", tag = "tests",
+    security(("session" = [])),
+    params(("test_id" = String, Path, description = "Test id")),
+    request_body(content = BaselineForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (
+            status = 200,
+            description = "The test with its new baseline",
+            body = crate::domain::schemas::Test
+        ),
+        (
+            status = 404,
+            description = "No such test, or no such run of it",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "The run has not finished",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Setting test baseline", skip_all)]
+pub async fn set_baseline(
+    Path(test_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+    ValidatedForm(form): ValidatedForm<BaselineForm>,
+) -> Result<Response, AppError> {
+    let org_id = actor_test_org(&state, &actor, &test_id).await?;
+    let run = find_organization_run(&state.db_client, &org_id, &form.run_id)
+        .await?
+        .filter(|r| r.test_id == test_id)
+        .ok_or_else(|| AppError::NotFound("No such run of the test exists".to_string()))?;
+    if run.status != RunStatus::Finished {
+        return Err(AppError::Conflict(
+            "Only a finished run can be a baseline".to_string(),
+        ));
+    }
+
+    let before = state
+        .db_client
+        .test()
+        .find_unique(test::id::equals(test_id.clone()))
+        .exec()
+        .await?;
+    let data = state
+        .db_client
+        .test()
+        .update(
+            test::id::equals(test_id),
+            vec![
+                test::baseline_run_id::set(Some(run.id)),
+                test::regression_tolerance_pct::set(form.tolerance_pct),
+            ],
+        )
+        .exec()
+        .await?;
+
+    let entry = AuditEntry::new("test.baseline_set", org_id, "test", &data.id)
+        .before(json!({
+            "baselineRunId": before.as_ref().and_then(|t| t.baseline_run_id.as_ref()),
+            "regressionTolerancePct": before.and_then(|t| t.regression_tolerance_pct),
+        }))
+        .after(json!({
+            "baselineRunId": data.baseline_run_id,
+            "regressionTolerancePct": data.regression_tolerance_pct,
+        }));
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(Json(data).into_response())
+}
+
+#[utoipa::path(
+    delete, path = "/v1/tests/{test_id}/baseline", tag = "tests",
+    security(("session" = [])),
+    params(("test_id" = String, Path, description = "Test id")),
+    responses(
+        (status = 204, description = "New runs are no longer compared"),
+        (
+            status = 404,
+            description = "No such test",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Clearing test baseline", skip_all)]
+pub async fn clear_baseline(
+    Path(test_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    let org_id = actor_test_org(&state, &actor, &test_id).await?;
+    state
+        .db_client
+        .test()
+        .update(
+            test::id::equals(test_id.clone()),
+            vec![
+                test::baseline_run_id::set(None),
+                test::regression_tolerance_pct::set(None),
+            ],
+        )
+        .exec()
+        .await?;
+
+    let entry = AuditEntry::new("test.baseline_cleared", org_id, "test", test_id);
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Attaches the regressions of a run that just finished, auditing them as `run.regressed`.
+///
+/// The run has finished either way, so a failed comparison is only logged.
+pub async fn flag_regressions(
+    state: &AppState,
+    meta: &RequestMeta,
+    org_id: &str,
+    run: &mut test_run::Data,
+) {
+    let detected =
+        detect_regressions(&state.db_client, &state.pg_client, &state.regressions, run).await;
+    let regressions = match detected {
+        Ok(regressions) => regressions,
+        Err(e) => {
+            warn!(error = %e, run_id = %run.id, "Could not compare run with its baseline");
+            return;
+        }
+    };
+
+    if !regressions.is_empty() {
+        let entry = AuditEntry::new("run.regressed", org_id, "run", &run.id).after(json!({
+            "baselineRunId": regressions[0].baseline_run_id,
+            "regressions": regressions,
+        }));
+        record_audit(state, None, meta, entry).await;
+
+        let event = WebhookEvent::RunRegressed(RunRegressed {
+            run_id: run.id.clone(),
+            test_id: run.test_id.clone(),
+            baseline_run_id: regressions[0].baseline_run_id.clone(),
+            regressions: regressions.clone(),
+        });
+        if let Err(e) = queue_event(&state.db_client, org_id, event).await {
+            warn!(error = %e, run_id = %run.id, "Could not queue webhook event");
+        }
+    }
+    run.regressions = Some(regressions);
+}
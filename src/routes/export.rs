@@ -0,0 +1,289 @@
+use super::AppState;
+use crate::{
+    domain::{
+        auth::TonsailUser,
+        export::{ExportResponse, RunExportQuery},
+        organization::find_organization_run,
+    },
+    jobs::export::{spawn_run_export, spawn_user_export},
+    prisma::{data_export, user, ExportStatus},
+    questdb::export::{count_samples, stream_samples, ExportFormat},
+    util::{
+        app_error::AppError,
+        nano_id::{generate_id, generate_token},
+        validation::ValidatedQuery,
+    },
+};
+use axum::{
+    body::StreamBody,
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use http::{header, StatusCode};
+use prisma_client_rust::chrono::{self, Utc};
+use std::time::Duration;
+use tokio_util::io::ReaderStream;
+use tracing::instrument;
+
+#[utoipa::path(
+    post, path = "/v1/users/{user_id}/exports", tag = "exports",
+    security(("session" = [])),
+    params(("user_id" = String, Path, description = "User id")),
+    responses(
+        (
+            status = 202,
+            description = "Export is being prepared",
+            body = crate::domain::export::ExportResponse
+        ),
+        (
+            status = 401,
+            description = "Not your own data",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Requesting user data export", skip_all)]
+pub async fn create_user_export(
+    Path(user_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(user): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    if user.id() != user_id {
+        return Err(AppError::UnAuthorized(
+            "Only your own data can be exported".to_string(),
+        ));
+    }
+
+    // Replaced with the real deadline once the archive is ready
+    let expires_at = Utc::now() + chrono::Duration::hours(state.exports.link_ttl_hours);
+    let data = state
+        .db_client
+        .data_export()
+        .create(
+            generate_id(),
+            generate_token(),
+            expires_at.into(),
+            user::id::equals(user_id.clone()),
+            vec![],
+        )
+        .exec()
+        .await?;
+
+    spawn_user_export(state, data.id.clone(), user_id);
+    Ok((StatusCode::ACCEPTED, Json(ExportResponse::from(data))).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/v1/users/{user_id}/exports/{export_id}", tag = "exports",
+    security(("session" = [])),
+    params(
+        ("user_id" = String, Path, description = "User id"),
+        ("export_id" = String, Path, description = "Export id")
+    ),
+    responses(
+        (status = 200, description = "Export status", body = crate::domain::export::ExportResponse),
+        (
+            status = 404,
+            description = "No such export",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Fetching user data export", skip_all)]
+pub async fn get_user_export(
+    Path((user_id, export_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Extension(user): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    if user.id() != user_id {
+        return Err(AppError::UnAuthorized(
+            "Only your own exports can be viewed".to_string(),
+        ));
+    }
+
+    let data = state
+        .db_client
+        .data_export()
+        .find_first(vec![
+            data_export::id::equals(export_id),
+            data_export::user_id::equals(user_id),
+        ])
+        .exec()
+        .await?;
+
+    match data {
+        Some(data) => Ok(Json(ExportResponse::from(data)).into_response()),
+        None => Err(AppError::NotFound("No such export exists".to_string())),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/v1/runs/{run_id}/export", tag = "exports",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id"), RunExportQuery),
+    responses(
+        (
+            status = 200,
+            description = "The raw samples as an attachment, gzipped when accepted",
+            content(
+                ("text/csv" = String),
+                ("application/x-ndjson" = String),
+                ("application/vnd.apache.parquet" = Vec<u8>)
+            )
+        ),
+        (
+            status = 202,
+            description = "Too many samples to stream, the export is being prepared",
+            body = crate::domain::export::ExportResponse
+        ),
+        (
+            status = 400,
+            description = "Invalid format, metric name or time window",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "The raw samples are past retention",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Exporting run samples", skip_all)]
+pub async fn export_run(
+    Path(run_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(user): Extension<TonsailUser>,
+    ValidatedQuery(query): ValidatedQuery<RunExportQuery>,
+) -> Result<Response, AppError> {
+    let run = find_organization_run(&state.db_client, user.organization_id(), &run_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such run exists".to_string()))?;
+    if run.raw_purged_at.is_some() {
+        return Err(AppError::Conflict(
+            "The raw samples of this run are past retention".to_string(),
+        ));
+    }
+
+    let filter = query.filter();
+    let count = count_samples(&state.pg_client, &run_id, &filter).await?;
+    if count > state.exports.max_streamed_rows {
+        // Replaced with the real deadline once the file is written
+        let expires_at = Utc::now() + chrono::Duration::hours(state.exports.link_ttl_hours);
+        let data = state
+            .db_client
+            .data_export()
+            .create(
+                generate_id(),
+                generate_token(),
+                expires_at.into(),
+                user::id::equals(user.id().to_string()),
+                vec![],
+            )
+            .exec()
+            .await?;
+        spawn_run_export(state, data.id.clone(), run_id, filter, query.format);
+        return Ok((StatusCode::ACCEPTED, Json(ExportResponse::from(data))).into_response());
+    }
+
+    let body = StreamBody::new(stream_samples(
+        state.export_pg_client.clone(),
+        &state.tasks,
+        &run_id,
+        &filter,
+        query.format,
+        Duration::from_secs(state.exports.send_timeout_secs),
+    ));
+    Ok((
+        attachment_headers(query.format, &format!("run-{run_id}")),
+        body,
+    )
+        .into_response())
+}
+
+fn attachment_headers(format: ExportFormat, stem: &str) -> [(header::HeaderName, String); 2] {
+    [
+        (header::CONTENT_TYPE, format.content_type().to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{stem}.{}\"", format.extension()),
+        ),
+    ]
+}
+
+#[utoipa::path(
+    get, path = "/v1/exports/{token}", tag = "exports",
+    params(("token" = String, Path, description = "Download token of a ready export")),
+    responses(
+        (
+            status = 200,
+            description = "The archive as a JSON attachment, or the run export file",
+            body = Object
+        ),
+        (
+            status = 404,
+            description = "Invalid or expired link",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Downloading user data export", skip_all)]
+pub async fn download_export(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let data = state
+        .db_client
+        .data_export()
+        .find_first(vec![
+            data_export::token::equals(token),
+            data_export::status::equals(ExportStatus::Ready),
+            data_export::expires_at::gt(Utc::now().into()),
+        ])
+        .exec()
+        .await?;
+
+    let not_found = || AppError::NotFound("Export link is invalid or expired".to_string());
+    let data = data.ok_or_else(not_found)?;
+    let Some(file) = data.file else {
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "application/json".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"tonsail-export-{}.json\"", data.id),
+                ),
+            ],
+            data.payload.unwrap_or_default(),
+        )
+            .into_response());
+    };
+
+    let path = std::path::Path::new(&file);
+    let format = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(ExportFormat::from_extension)
+        .ok_or_else(not_found)?;
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file = tokio::fs::File::open(path).await.map_err(|_| not_found())?;
+    Ok((
+        attachment_headers(format, &stem),
+        StreamBody::new(ReaderStream::new(file)),
+    )
+        .into_response())
+}
@@ -1,5 +1,145 @@
-use http::StatusCode;
+use super::AppState;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use fred::interfaces::ClientLike;
+use http::{header, StatusCode};
+use prisma_client_rust::raw;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    time::{Duration, Instant},
+};
+use tracing::instrument;
+use utoipa::ToSchema;
 
-pub async fn health_check() -> StatusCode {
+/// Longest a dependency may take to answer before the pod is reported as not ready.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStatus {
+    Up,
+    Down,
+    Draining,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyHealth {
+    status: ProbeStatus,
+    latency_ms: u64,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    status: ProbeStatus,
+    dependencies: BTreeMap<String, DependencyHealth>,
+}
+
+#[utoipa::path(
+    get, path = "/health/live", tag = "health",
+    responses((status = 200, description = "Process is running"))
+)]
+pub async fn health_live() -> StatusCode {
     StatusCode::OK
 }
+
+/// The probe deployments used before `/health/live`, kept until they move over.
+#[utoipa::path(
+    get, path = "/health_check", tag = "health",
+    responses((status = 200, description = "Process is running"))
+)]
+pub async fn health_check() -> Response {
+    (
+        [
+            (header::HeaderName::from_static("deprecation"), "true"),
+            (header::LINK, "</health/live>; rel=\"successor-version\""),
+        ],
+        health_live().await,
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get, path = "/health/ready", tag = "health",
+    responses(
+        (status = 200, description = "Every dependency answers", body = Readiness),
+        (
+            status = 503,
+            description = "A dependency is down or the server is draining",
+            body = Readiness
+        )
+    )
+)]
+#[instrument(name = "Checking readiness", skip_all)]
+pub async fn health_ready(State(state): State<AppState>) -> Response {
+    if state.shutdown.is_cancelled() {
+        let readiness = Readiness {
+            status: ProbeStatus::Draining,
+            dependencies: BTreeMap::new(),
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(readiness)).into_response();
+    }
+
+    let (mysql, questdb, redis) = tokio::join!(
+        probe(async {
+            state
+                .db_client
+                ._query_raw::<serde_json::Value>(raw!("SELECT 1"))
+                .exec()
+                .await
+                .map(|_| ())
+        }),
+        probe(async {
+            sqlx::query("SELECT 1")
+                .execute(&state.pg_client)
+                .await
+                .map(|_| ())
+        }),
+        probe(async { state.rds_client.ping::<String>().await.map(|_| ()) }),
+    );
+
+    let dependencies = BTreeMap::from([
+        ("mysql".to_string(), mysql),
+        ("questdb".to_string(), questdb),
+        ("redis".to_string(), redis),
+    ]);
+    let ready = dependencies
+        .values()
+        .all(|d| matches!(d.status, ProbeStatus::Up));
+    let (code, status) = match ready {
+        true => (StatusCode::OK, ProbeStatus::Up),
+        false => (StatusCode::SERVICE_UNAVAILABLE, ProbeStatus::Down),
+    };
+
+    (
+        code,
+        Json(Readiness {
+            status,
+            dependencies,
+        }),
+    )
+        .into_response()
+}
+
+async fn probe<E: ToString>(check: impl Future<Output = Result<(), E>>) -> DependencyHealth {
+    let start = Instant::now();
+    let error = match tokio::time::timeout(PROBE_TIMEOUT, check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("No answer within {PROBE_TIMEOUT:?}")),
+    };
+    DependencyHealth {
+        status: match error {
+            None => ProbeStatus::Up,
+            Some(_) => ProbeStatus::Down,
+        },
+        latency_ms: start.elapsed().as_millis() as u64,
+        error,
+    }
+}
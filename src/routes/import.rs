@@ -0,0 +1,195 @@
+use super::{baseline::flag_regressions, report::queue_run_finished, AppState};
+use crate::{
+    domain::{
+        auth::TonsailUser,
+        deletion::soft_delete_run,
+        import::{read_jtl, read_k6, ImportFormat, ImportQuery, ImportSink, ImportStats},
+        usage::{check_run_limits, record_usage, UsageDelta},
+    },
+    prisma::{organization, project, test, test_run, RunStatus},
+    util::{
+        app_error::AppError,
+        audit::{record_audit, AuditEntry, RequestMeta},
+        nano_id::generate_id,
+        validation::ValidatedQuery,
+    },
+};
+use axum::{
+    extract::{BodyStream, Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use futures::TryStreamExt;
+use prisma_client_rust::chrono::{DateTime, FixedOffset, Utc};
+use serde_json::json;
+use std::io;
+use tokio::io::BufReader;
+use tokio_util::io::StreamReader;
+use tracing::instrument;
+
+#[utoipa::path(
+    post, path = "/v1/tests/{test_id}/imports", tag = "runs",
+    security(("session" = [])),
+    params(("test_id" = String, Path, description = "Test the run is added to"), ImportQuery),
+    request_body(
+        content = String,
+        description = "k6 `--out json` output, or a JMeter JTL file in CSV or XML",
+        content_type = "application/octet-stream"
+    ),
+    responses(
+        (status = 200, description = "The finished run", body = crate::domain::schemas::TestRun),
+        (
+            status = 400,
+            description = "Unreadable file, or metrics the organization's policy rejects",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 402,
+            description = "More virtual users or a longer duration than the plan allows",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such test",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Importing test run", skip_all)]
+pub async fn import_run(
+    Path(test_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+    ValidatedQuery(query): ValidatedQuery<ImportQuery>,
+    body: BodyStream,
+) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound("No such test exists".to_string());
+    let org = state
+        .db_client
+        .organization()
+        .find_first(vec![
+            organization::id::equals(actor.organization_id().to_string()),
+            organization::projects::some(vec![project::tests::some(vec![
+                test::id::equals(test_id.clone()),
+                test::deleted_at::equals(None),
+            ])]),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(not_found)?;
+    let limits = state.plans.limits(org.plan);
+
+    let run = state
+        .db_client
+        .test_run()
+        .create(generate_id(), test::id::equals(test_id), vec![])
+        .exec()
+        .await?;
+
+    let reader = BufReader::new(StreamReader::new(
+        body.map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+    ));
+    let mut sink = ImportSink::new(&state.db_client, &state.pg_client, &org, &run.id);
+    let read = match query.format {
+        ImportFormat::K6 => read_k6(reader, &mut sink).await,
+        ImportFormat::Jtl => read_jtl(reader, &mut sink).await,
+    };
+    let stats = match read {
+        Ok(()) => sink.finish().await,
+        Err(e) => Err(e),
+    };
+    // The same plan limits apply as to runs started here
+    let stats = stats.and_then(|stats| match stats.samples {
+        0 => Err(AppError::BadRequest(
+            "the file holds no samples".to_string(),
+        )),
+        _ => check_run_limits(&limits, stats.vus(), Some(stats.duration_secs() as u32))
+            .map(|()| stats),
+    });
+    let stats = match stats {
+        Ok(stats) => stats,
+        // The purge job removes whatever samples were already written
+        Err(e) => {
+            soft_delete_run(&state.db_client, run.id.clone(), Utc::now().into()).await?;
+            return Err(e);
+        }
+    };
+
+    state
+        .server_metrics
+        .add_ingested_samples(stats.samples as u64);
+    let mut data = finish_imported_run(&state, &org.id, &run.id, &stats).await?;
+    flag_regressions(&state, &meta, &org.id, &mut data).await;
+    queue_run_finished(&state, &org.id, &data).await;
+    let entry = AuditEntry::new("run.imported", &org.id, "run", &data.id).after(json!({
+        "format": query.format,
+        "samples": data.samples,
+    }));
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(Json(data).into_response())
+}
+
+/// Marks the run as finished over the time its samples span, and meters it.
+///
+/// Fails with a conflict if the run left `NotStarted` in the meantime, so it is metered once.
+async fn finish_imported_run(
+    state: &AppState,
+    org_id: &str,
+    run_id: &str,
+    stats: &ImportStats,
+) -> Result<test_run::Data, AppError> {
+    let started_at: Option<DateTime<FixedOffset>> = stats.first.map(Into::into);
+    let finished_at: Option<DateTime<FixedOffset>> = stats.last.map(Into::into);
+    let updates = vec![
+        test_run::status::set(RunStatus::Finished),
+        test_run::started_at::set(started_at),
+        test_run::finished_at::set(finished_at),
+        test_run::duration_secs::set(Some(stats.duration_secs() as i32)),
+        test_run::vus::set(stats.vus() as i32),
+        test_run::samples::set(stats.samples),
+    ];
+    // The load was generated elsewhere, so only the run and its samples count
+    let delta = UsageDelta {
+        runs: 1,
+        ingested_samples: stats.samples,
+        ..Default::default()
+    };
+
+    let org_id = org_id.to_string();
+    let run_id = run_id.to_string();
+    let data = state
+        .db_client
+        ._transaction()
+        .run(|client| async move {
+            let moved = client
+                .test_run()
+                .update_many(
+                    vec![
+                        test_run::id::equals(run_id.clone()),
+                        test_run::status::equals(RunStatus::NotStarted),
+                        test_run::deleted_at::equals(None),
+                    ],
+                    updates,
+                )
+                .exec()
+                .await?;
+            if moved != 1 {
+                return Err(AppError::Conflict(
+                    "The run changed status in the meantime".to_string(),
+                ));
+            }
+            record_usage(&client, &org_id, delta).await?;
+            client
+                .test_run()
+                .find_unique(test_run::id::equals(run_id))
+                .exec()
+                .await?
+                .ok_or_else(|| AppError::NotFound("No such run exists".to_string()))
+        })
+        .await?;
+    Ok(data)
+}
@@ -0,0 +1,134 @@
+use super::AppState;
+use crate::util::app_error::AppError;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use fred::{interfaces::ClientLike, types::ClientState};
+use http::{header, HeaderMap};
+use prisma_client_rust::{raw, QueryError};
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{instrument, warn};
+
+#[derive(Deserialize)]
+struct MysqlConnection {
+    command: String,
+}
+
+/// Idle and active connections of the database user, as MySQL counts them.
+///
+/// Prisma keeps its pool inside the query engine, which does not expose statistics, so these
+/// include the connections of every replica, and the one counting them.
+async fn mysql_connections(state: &AppState) -> Result<(usize, usize), QueryError> {
+    let connections = state
+        .db_client
+        ._query_raw::<MysqlConnection>(raw!(
+            "SELECT COMMAND AS command FROM information_schema.PROCESSLIST \
+             WHERE USER = SUBSTRING_INDEX(CURRENT_USER(), '@', 1) AND DB = DATABASE()"
+        ))
+        .exec()
+        .await?;
+    let idle = connections.iter().filter(|c| c.command == "Sleep").count();
+    Ok((idle, connections.len() - idle))
+}
+
+/// Lets only the holder of `telemetry.metrics_token` scrape, the route is hidden without one.
+fn check_scraper(state: &AppState, headers: &HeaderMap) -> Result<(), AppError> {
+    let Some(token) = &state.metrics_token else {
+        return Err(AppError::NotFound("No such route".to_string()));
+    };
+    let sent = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Comparing digests takes the same time however much of the token matches
+    match Sha256::digest(sent.as_bytes()) == Sha256::digest(token.expose_secret().as_bytes()) {
+        true => Ok(()),
+        false => Err(AppError::UnAuthorized(
+            "A valid metrics token is required".to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/internal/metrics", tag = "internal",
+    security(("metrics_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Server metrics in the Prometheus text format",
+            body = String,
+            content_type = "text/plain"
+        ),
+        (
+            status = 401,
+            description = "Missing or wrong metrics token",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No metrics token is configured",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Scraping server metrics", skip_all)]
+pub async fn get_server_metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    check_scraper(&state, &headers)?;
+    let metrics = &state.server_metrics;
+
+    for (pool, pg_client) in [
+        ("questdb", &state.pg_client),
+        ("questdb_exports", &state.export_pg_client),
+    ] {
+        let idle = pg_client.num_idle();
+        metrics.set_pool_connections(pool, "idle", idle);
+        metrics.set_pool_connections(
+            pool,
+            "active",
+            (pg_client.size() as usize).saturating_sub(idle),
+        );
+    }
+
+    let connected = state
+        .rds_client
+        .clients()
+        .iter()
+        .filter(|c| c.state() == ClientState::Connected)
+        .count();
+    metrics.set_pool_connections("redis", "connected", connected);
+    metrics.set_pool_connections(
+        "redis",
+        "disconnected",
+        state.rds_client.size().saturating_sub(connected),
+    );
+
+    // A failing database or store must not hide the rest of the metrics
+    match mysql_connections(&state).await {
+        Ok((idle, active)) => {
+            metrics.set_pool_connections("mysql", "idle", idle);
+            metrics.set_pool_connections("mysql", "active", active);
+        }
+        Err(e) => warn!(error = %e, "Could not count MySQL connections"),
+    }
+
+    let store = state.session_store();
+    match store.count().await {
+        Ok(sessions) => metrics.set_active_sessions(sessions),
+        Err(e) => warn!(error = %e, "Could not count sessions"),
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.encode()?,
+    )
+        .into_response())
+}
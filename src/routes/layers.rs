@@ -1,36 +1,179 @@
-use super::AppState;
+use super::{AppState, V1_PREFIX};
 use crate::{
+    configuration::{CorsSettings, SameSitePolicy},
     domain::auth::{TonsailUser, TonsailUserStore},
-    util::redis_session_store::RedisSessionStore,
+    prisma::{user, Plan, PrismaClient},
+    util::{
+        app_error::AppError,
+        audit::client_ip,
+        rate_limit::RouteGroup,
+        redis_session_store::{SESSION_AUTH_ID_KEY, SESSION_USER_ID_KEY},
+        secrets::SigningKeys,
+        server_metrics::{ServerMetrics, UNMATCHED_ROUTE},
+        tracing::set_remote_parent,
+    },
 };
-use axum::{body::BoxBody, Router};
+use axum::{body::BoxBody, extract::MatchedPath, middleware::Next, response::IntoResponse, Router};
 use axum_login::{
-    axum_sessions::{PersistencePolicy, SameSite, SessionLayer},
+    axum_sessions::{PersistencePolicy, SameSite, SessionHandle, SessionLayer},
     AuthLayer,
 };
-use http::{Method, Request, Response};
+use http::{header, HeaderName, HeaderValue, Method, Request, Response};
 use hyper::Body;
-use std::time::Duration;
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
 use tower::ServiceBuilder;
 use tower_http::{
-    cors::CorsLayer,
+    cors::{AllowOrigin, CorsLayer},
     request_id::MakeRequestUuid,
-    trace::{DefaultMakeSpan, DefaultOnFailure, TraceLayer},
+    trace::{DefaultMakeSpan, DefaultOnFailure, MakeSpan, TraceLayer},
     ServiceBuilderExt,
 };
-use tracing::{Level, Span};
-
-pub fn add_cors_layer(router: Router<AppState>) -> Router<AppState> {
-    let origins = [
-        "https://tonsail.dev".parse().unwrap(),
-        "https://app.tonsail.dev".parse().unwrap(),
-        "http://localhost:5173".parse().unwrap(),
-    ];
+use tracing::{warn, Level, Span};
+
+/// `Settings::validate` rejects invalid methods and headers before the router is built.
+pub fn add_cors_layer(router: Router<AppState>, settings: &CorsSettings) -> Router<AppState> {
+    let patterns = settings.allowed_origins.clone();
+    let methods: Vec<Method> = settings
+        .allowed_methods
+        .iter()
+        .filter_map(|m| Method::from_bytes(m.to_uppercase().as_bytes()).ok())
+        .collect();
+    let headers: Vec<HeaderName> = settings
+        .allowed_headers
+        .iter()
+        .filter_map(|h| HeaderName::from_bytes(h.as_bytes()).ok())
+        .collect();
+
     router.layer(
         CorsLayer::new()
             .allow_credentials(true)
-            .allow_methods([Method::GET, Method::POST, Method::PUT])
-            .allow_origin(origins),
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .max_age(Duration::from_secs(settings.max_age_secs))
+            .allow_origin(AllowOrigin::predicate(move |origin, _| {
+                origin
+                    .to_str()
+                    .map_or(false, |o| patterns.iter().any(|p| origin_matches(p, o)))
+            })),
+    )
+}
+
+/// `https://*.tonsail.dev` matches any subdomain, but not `https://tonsail.dev` itself.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    match pattern.split_once("*.") {
+        Some((scheme, domain)) => origin
+            .strip_prefix(scheme)
+            .and_then(|host| host.strip_suffix(domain))
+            .map_or(false, |sub| {
+                sub.ends_with('.') && sub.len() > 1 && !sub[..sub.len() - 1].contains('/')
+            }),
+        None => pattern == origin,
+    }
+}
+
+/// Adds `Deprecation`, `Sunset` and a `Link` to the successor under `prefix` to every response.
+///
+/// `Settings::validate` checks that `sunset` is an HTTP date.
+pub fn add_deprecation_layer(
+    router: Router<AppState>,
+    prefix: &'static str,
+    sunset: &str,
+) -> Router<AppState> {
+    let sunset = HeaderValue::from_str(sunset).ok();
+    router.layer(axum::middleware::from_fn(
+        move |request: Request<Body>, next: Next<Body>| {
+            let sunset = sunset.clone();
+            async move {
+                let successor = format!(
+                    "<{prefix}{}>; rel=\"successor-version\"",
+                    request.uri().path()
+                );
+                let mut response = next.run(request).await;
+                let headers = response.headers_mut();
+                headers.insert("deprecation", HeaderValue::from_static("true"));
+                if let Some(sunset) = sunset {
+                    headers.insert("sunset", sunset);
+                }
+                if let Ok(link) = HeaderValue::from_str(&successor) {
+                    headers.insert(header::LINK, link);
+                }
+                response
+            }
+        },
+    ))
+}
+
+/// Takes a token per request of a limited route group, answering 429 once the bucket is empty.
+///
+/// Must sit inside the auth layer, it keys logged in users by their organization and plan.
+pub fn add_rate_limit_layer(router: Router<AppState>, state: AppState) -> Router<AppState> {
+    let limiter = state.rate_limiter;
+    if !limiter.enabled() {
+        return router;
+    }
+    let trusted_proxies = state.trusted_proxies;
+
+    router.layer(axum::middleware::from_fn(
+        move |request: Request<Body>, next: Next<Body>| {
+            let limiter = limiter.clone();
+            let trusted_proxies = trusted_proxies.clone();
+            async move {
+                let route = request
+                    .extensions()
+                    .get::<MatchedPath>()
+                    .map(|p| p.as_str().to_string())
+                    .unwrap_or_default();
+                let route = route.strip_prefix(V1_PREFIX).unwrap_or(&route);
+                let Some(group) = RouteGroup::of(request.method(), route) else {
+                    return next.run(request).await;
+                };
+
+                let (client, plan) = rate_limit_client(&request, &trusted_proxies);
+                let decision = match limiter.check(&client, group, plan).await {
+                    Ok(decision) => decision,
+                    Err(e) => {
+                        // Redis being down must not take the API with it
+                        warn!(error = %e, "Rate limiter unavailable, letting the request through");
+                        return next.run(request).await;
+                    }
+                };
+
+                let mut response = match decision.allowed {
+                    true => next.run(request).await,
+                    false => AppError::TooManyRequests(format!(
+                        "Retry in {} seconds",
+                        decision.retry_after_secs
+                    ))
+                    .into_response(),
+                };
+                let headers = response.headers_mut();
+                headers.insert("ratelimit-limit", decision.limit.into());
+                headers.insert("ratelimit-remaining", decision.remaining.into());
+                headers.insert("ratelimit-reset", decision.reset_secs.into());
+                if !decision.allowed {
+                    headers.insert(header::RETRY_AFTER, decision.retry_after_secs.into());
+                }
+                response
+            }
+        },
+    ))
+}
+
+/// Organization of the session user, then client address.
+///
+/// Bearer tokens are not verified here, keying on them would hand out a bucket per made-up token.
+fn rate_limit_client(request: &Request<Body>, trusted_proxies: &[IpAddr]) -> (String, Plan) {
+    if let Some(user) = request.extensions().get::<TonsailUser>() {
+        return (format!("org:{}", user.organization_id()), user.plan());
+    }
+
+    let ip = client_ip(request.headers(), request.extensions(), trusted_proxies);
+    (
+        format!("ip:{}", ip.as_deref().unwrap_or("unknown")),
+        Plan::Free,
     )
 }
 
@@ -38,30 +181,168 @@ type TonsailAuthLayer = AuthLayer<TonsailUserStore, TonsailUser>;
 pub fn add_auth_layer(router: Router<AppState>, state: AppState) -> Router<AppState> {
     let user_store = TonsailUserStore::new(state.db_client.clone());
 
-    let session_store =
-        RedisSessionStore::from_pool(state.rds_client, Some("tonsail-session/".into()));
+    let session_store = state.session_store();
+    let settings = &state.session;
 
-    let auth_layer: TonsailAuthLayer = AuthLayer::new(user_store, &state.secret);
+    let keys = state.signing_keys.clone();
+    let cookie_name = settings.cookie_name.clone();
 
-    router.layer(auth_layer).layer(
-        SessionLayer::new(session_store, &state.secret)
-            .with_persistence_policy(PersistencePolicy::ExistingOnly)
-            .with_cookie_name("api_sid")
-            .with_same_site_policy(SameSite::Strict),
-    )
+    let auth_layer: TonsailAuthLayer = AuthLayer::new(user_store, keys.current());
+    let rebind_keys = keys.clone();
+    let db_client = state.db_client.clone();
+
+    let mut session_layer = SessionLayer::new(session_store, keys.current())
+        .with_persistence_policy(PersistencePolicy::ExistingOnly)
+        .with_cookie_name(settings.cookie_name.clone())
+        .with_secure(settings.secure)
+        .with_same_site_policy(match settings.same_site {
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::Lax => SameSite::Lax,
+            SameSitePolicy::None => SameSite::None,
+        })
+        .with_session_ttl(Some(Duration::from_secs(settings.ttl_secs)));
+    if let Some(domain) = &settings.cookie_domain {
+        session_layer = session_layer.with_cookie_domain(domain.clone());
+    }
+
+    router
+        .layer(auth_layer)
+        .layer(axum::middleware::from_fn(
+            move |request: Request<Body>, next: Next<Body>| {
+                let keys = rebind_keys.clone();
+                let db_client = db_client.clone();
+                async move {
+                    rebind_login(&request, &keys, &db_client).await;
+                    next.run(request).await
+                }
+            },
+        ))
+        .layer(session_layer)
+        .layer(axum::middleware::from_fn(
+            move |mut request: Request<Body>, next: Next<Body>| {
+                resign_session_cookie(&mut request, &keys, &cookie_name);
+                next.run(request)
+            },
+        ))
 }
 
-pub fn add_trace_layer(router: Router<AppState>) -> Router<AppState> {
+/// Session key of the fingerprint of the signing key that last checked the login
+const SESSION_KEY_FINGERPRINT: &str = "_key";
+
+/// Binds a login made under a previous key to the current one, before axum-login checks it.
+///
+/// The session remembers which key checked it last, so the user is loaded once per rotation.
+async fn rebind_login(request: &Request<Body>, keys: &SigningKeys, db_client: &PrismaClient) {
+    let Some(handle) = request.extensions().get::<SessionHandle>() else {
+        return;
+    };
+    let (user_id, auth_id) = {
+        let session = handle.read().await;
+        if session.get::<String>(SESSION_KEY_FINGERPRINT).as_deref() == Some(keys.fingerprint()) {
+            return;
+        }
+        match (
+            session.get::<String>(SESSION_USER_ID_KEY),
+            session.get::<String>(SESSION_AUTH_ID_KEY),
+        ) {
+            (Some(user_id), Some(auth_id)) => (user_id, auth_id),
+            _ => return,
+        }
+    };
+
+    let user = db_client
+        .user()
+        .find_first(vec![
+            user::id::equals(user_id),
+            user::deleted_at::equals(None),
+        ])
+        .exec()
+        .await;
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Could not load the user of a session: {e}");
+            return;
+        }
+    };
+
+    // A login no key bound is left for axum-login to reject.
+    let Some(auth_id) = keys.current_auth_id(&auth_id, user.password.as_bytes()) else {
+        return;
+    };
+    let mut session = handle.write().await;
+    let bound = session
+        .insert(SESSION_AUTH_ID_KEY, auth_id)
+        .and_then(|_| session.insert(SESSION_KEY_FINGERPRINT, keys.fingerprint()));
+    if let Err(e) = bound {
+        warn!("Could not bind a login to the current key: {e}");
+    }
+}
+
+/// Swaps a session cookie signed with a previous key for one the session layer accepts.
+fn resign_session_cookie(request: &mut Request<Body>, keys: &SigningKeys, cookie_name: &str) {
+    let Some(cookies) = request
+        .headers()
+        .get(header::COOKIE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return;
+    };
+
+    let mut changed = false;
+    let cookies: Vec<String> = cookies
+        .split(';')
+        .map(|pair| match pair.trim().split_once('=') {
+            Some((name, value)) if name == cookie_name => match keys.resign(name, value) {
+                Some(value) => {
+                    changed = true;
+                    format!("{name}={value}")
+                }
+                None => pair.trim().to_string(),
+            },
+            _ => pair.trim().to_string(),
+        })
+        .collect();
+
+    if changed {
+        if let Ok(value) = HeaderValue::from_str(&cookies.join("; ")) {
+            request.headers_mut().insert(header::COOKIE, value);
+        }
+    }
+}
+
+pub fn add_trace_layer(router: Router<AppState>, metrics: ServerMetrics) -> Router<AppState> {
     router.layer(
         ServiceBuilder::new()
             .set_x_request_id(MakeRequestUuid)
+            .layer(axum::middleware::from_fn(
+                move |request: Request<Body>, next: Next<Body>| {
+                    let metrics = metrics.clone();
+                    async move {
+                        let method = request.method().clone();
+                        let route = request
+                            .extensions()
+                            .get::<MatchedPath>()
+                            .map_or(UNMATCHED_ROUTE.to_string(), |p| p.as_str().to_string());
+                        let start = Instant::now();
+                        let response = next.run(request).await;
+                        let status = response.status().as_u16();
+                        metrics.observe_request(&method, &route, status, start.elapsed());
+                        response
+                    }
+                },
+            ))
             .layer(
                 TraceLayer::new_for_http()
-                    .make_span_with(
-                        DefaultMakeSpan::new()
+                    .make_span_with(|request: &Request<Body>| {
+                        let span = DefaultMakeSpan::new()
                             .level(Level::INFO)
-                            .include_headers(true),
-                    )
+                            .include_headers(true)
+                            .make_span(request);
+                        set_remote_parent(&span, request.headers());
+                        span
+                    })
                     .on_request(|_request: &Request<Body>, _span: &Span| {
                         tracing::info!("Started request")
                     })
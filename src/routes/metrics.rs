@@ -1,15 +1,30 @@
 use super::AppState;
-use crate::util::app_error::AppError;
+use crate::{
+    domain::{
+        auth::TonsailUser,
+        catalog::{visible_to, CatalogForm},
+    },
+    prisma::{metrics_catalog, test_run, UserRole},
+    questdb::{is_deleted, rollup::Resolution, Metrics},
+    util::{
+        app_error::AppError,
+        audit::{record_audit, AuditEntry, RequestMeta},
+        validation::ValidatedForm,
+    },
+};
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
-use prisma_client_rust::chrono::NaiveDateTime;
-use sea_query::{ColumnRef, Expr, Iden, PostgresQueryBuilder, Query as SeaQuery};
+use http::StatusCode;
+use prisma_client_rust::chrono::{Duration, NaiveDateTime};
+use sea_query::{Alias, ColumnRef, Expr, PostgresQueryBuilder, Query as SeaQuery};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::FromRow;
-use tracing::instrument;
+use tracing::{info_span, instrument, Instrument};
+use utoipa::{IntoParams, ToSchema};
 
 #[derive(Debug, Serialize, FromRow)]
 pub struct HttpMetric {
@@ -24,40 +39,34 @@ pub struct HttpMetric {
     value: f32,
 }
 
-#[derive(Debug, Serialize)]
-struct JSONMetric {
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JSONMetric {
     name: String,
     run_id: String,
+    resolution: Resolution,
     values: Vec<TimeMetric>,
 }
 
-#[derive(Debug, Serialize)]
-struct TimeMetric {
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TimeMetric {
     ts: NaiveDateTime,
     value: f32,
 }
 
-#[derive(Iden)]
-enum Metrics {
-    Table,
-    Name,
-    #[iden = "runID"]
-    RunID,
-    Scenario,
-    Url,
-    Method,
-    Status,
-}
-
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct MetricQuery {
-    #[serde(rename(deserialize = "runID"))]
+    #[serde(rename = "runID")]
     run_id: String,
     name: String,
     scenario: Option<String>,
     url: Option<String>,
     method: Option<String>,
     status: Option<String>,
+    /// Raw samples while the run still has them, the minute rollup past retention or for runs
+    /// over 6 hours, and the hour rollup for runs over 2 days
+    #[param(value_type = Option<crate::questdb::rollup::Resolution>)]
+    resolution: Option<Resolution>,
     #[serde(default = "default_limit")]
     limit: u64,
 }
@@ -66,26 +75,303 @@ fn default_limit() -> u64 {
     10
 }
 
+#[utoipa::path(
+    get, path = "/v1/metrics/catalog", tag = "metrics",
+    security(("session" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Built-in metrics and the custom ones of the organization",
+            body = [crate::domain::schemas::MetricsCatalog]
+        )
+    )
+)]
 #[instrument(name = "Getting metrics", skip_all)]
-pub async fn get_metrics_catalog(State(state): State<AppState>) -> Result<Response, AppError> {
+pub async fn get_metrics_catalog(
+    State(state): State<AppState>,
+    Extension(user): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
     let catalog = state
         .db_client
         .metrics_catalog()
-        .find_many(vec![])
+        .find_many(visible_to(user.organization_id()))
         .exec()
         .await?;
 
     Ok(Json(catalog).into_response())
 }
 
+#[utoipa::path(
+    post, path = "/v1/metrics/catalog", tag = "metrics",
+    security(("session" = [])),
+    request_body(
+        content = crate::domain::catalog::CatalogForm,
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The custom metric",
+            body = crate::domain::schemas::MetricsCatalog
+        ),
+        (
+            status = 403,
+            description = "Only admins manage the catalog",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "The catalog already has a metric with that name",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Creating catalog entry", skip_all)]
+pub async fn create_catalog_entry(
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+    ValidatedForm(form): ValidatedForm<CatalogForm>,
+) -> Result<Response, AppError> {
+    let org_id = catalog_admin_org(&actor)?;
+    check_name_is_free(&state, org_id, &form.value, None).await?;
+
+    let data = state
+        .db_client
+        .metrics_catalog()
+        .create(
+            form.label,
+            form.value,
+            form.group,
+            form.unit,
+            form.description,
+            vec![metrics_catalog::organization_id::set(Some(
+                org_id.to_string(),
+            ))],
+        )
+        .exec()
+        .await?;
+
+    let entry = AuditEntry::new("catalog.created", org_id, "metric", data.id.to_string())
+        .after(json!({ "value": data.value, "unit": data.unit }));
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(Json(data).into_response())
+}
+
+#[utoipa::path(
+    put, path = "/v1/metrics/catalog/{entry_id}", tag = "metrics",
+    security(("session" = [])),
+    params(("entry_id" = i32, Path, description = "Catalog entry id")),
+    request_body(
+        content = crate::domain::catalog::CatalogForm,
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The updated custom metric",
+            body = crate::domain::schemas::MetricsCatalog
+        ),
+        (
+            status = 404,
+            description = "No such custom metric in the organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "The catalog already has a metric with that name",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Updating catalog entry", skip_all)]
+pub async fn update_catalog_entry(
+    Path(entry_id): Path<i32>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+    ValidatedForm(form): ValidatedForm<CatalogForm>,
+) -> Result<Response, AppError> {
+    let org_id = catalog_admin_org(&actor)?;
+    let before = custom_entry(&state, org_id, entry_id).await?;
+    check_name_is_free(&state, org_id, &form.value, Some(entry_id)).await?;
+
+    let data = state
+        .db_client
+        .metrics_catalog()
+        .update(
+            metrics_catalog::id::equals(entry_id),
+            vec![
+                metrics_catalog::label::set(form.label),
+                metrics_catalog::value::set(form.value),
+                metrics_catalog::group::set(form.group),
+                metrics_catalog::unit::set(form.unit),
+                metrics_catalog::description::set(form.description),
+            ],
+        )
+        .exec()
+        .await?;
+
+    let entry = AuditEntry::new("catalog.updated", org_id, "metric", entry_id.to_string())
+        .before(json!({ "value": before.value, "unit": before.unit }))
+        .after(json!({ "value": data.value, "unit": data.unit }));
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(Json(data).into_response())
+}
+
+#[utoipa::path(
+    delete, path = "/v1/metrics/catalog/{entry_id}", tag = "metrics",
+    security(("session" = [])),
+    params(("entry_id" = i32, Path, description = "Catalog entry id")),
+    responses(
+        (status = 204, description = "The custom metric is deleted, its samples are kept"),
+        (
+            status = 404,
+            description = "No such custom metric in the organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Deleting catalog entry", skip_all)]
+pub async fn delete_catalog_entry(
+    Path(entry_id): Path<i32>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    let org_id = catalog_admin_org(&actor)?;
+    let before = custom_entry(&state, org_id, entry_id).await?;
+    state
+        .db_client
+        .metrics_catalog()
+        .delete(metrics_catalog::id::equals(entry_id))
+        .exec()
+        .await?;
+
+    let entry = AuditEntry::new("catalog.deleted", org_id, "metric", entry_id.to_string())
+        .before(json!({ "value": before.value, "unit": before.unit }));
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Built-in entries are managed with `tonsail-server seed`, admins only manage their own.
+fn catalog_admin_org(actor: &TonsailUser) -> Result<&str, AppError> {
+    match actor.role() {
+        UserRole::Member => Err(AppError::RequireAdmin("the metrics catalog".to_string())),
+        _ => Ok(actor.organization_id()),
+    }
+}
+
+async fn custom_entry(
+    state: &AppState,
+    org_id: &str,
+    entry_id: i32,
+) -> Result<metrics_catalog::Data, AppError> {
+    state
+        .db_client
+        .metrics_catalog()
+        .find_first(vec![
+            metrics_catalog::id::equals(entry_id),
+            metrics_catalog::organization_id::equals(Some(org_id.to_string())),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such custom metric exists".to_string()))
+}
+
+/// A custom metric may neither shadow a built-in one nor repeat another of the organization.
+async fn check_name_is_free(
+    state: &AppState,
+    org_id: &str,
+    value: &str,
+    except: Option<i32>,
+) -> Result<(), AppError> {
+    let mut filter = visible_to(org_id);
+    filter.push(metrics_catalog::value::equals(value.to_string()));
+    let taken = state
+        .db_client
+        .metrics_catalog()
+        .find_many(filter)
+        .exec()
+        .await?
+        .into_iter()
+        .any(|e| Some(e.id) != except);
+
+    match taken {
+        true => Err(AppError::Conflict(format!(
+            "the catalog already has a {value} metric"
+        ))),
+        false => Ok(()),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/v1/metrics", tag = "metrics",
+    security(("session" = [])),
+    params(MetricQuery),
+    responses(
+        (status = 200, description = "Time series of a run metric", body = JSONMetric),
+        (
+            status = 404,
+            description = "The run is deleted",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 500,
+            description = "Metrics store failure",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Getting metrics", skip_all)]
 pub async fn get_metrics(
     State(state): State<AppState>,
     Query(params): Query<MetricQuery>,
 ) -> Result<Response, AppError> {
-    let sql = SeaQuery::select()
-        .columns([ColumnRef::Asterisk])
-        .from(Metrics::Table)
+    let not_found = || AppError::NotFound("No such run exists".to_string());
+    let run = state
+        .db_client
+        .test_run()
+        .find_unique(test_run::id::equals(params.run_id.clone()))
+        .exec()
+        .await?;
+    if matches!(&run, Some(run) if run.deleted_at.is_some()) {
+        return Err(not_found());
+    }
+    let resolution = params
+        .resolution
+        .unwrap_or_else(|| default_resolution(run.as_ref()));
+    if is_deleted(&state.pg_client, &params.run_id, resolution).await? {
+        return Err(not_found());
+    }
+
+    let mut select = SeaQuery::select();
+    match resolution {
+        Resolution::Raw => select.columns([ColumnRef::Asterisk]),
+        _ => select
+            .columns([
+                Metrics::Name,
+                Metrics::RunID,
+                Metrics::Scenario,
+                Metrics::Url,
+                Metrics::Method,
+                Metrics::Status,
+                Metrics::Ts,
+            ])
+            .expr_as(
+                Expr::cust("cast(value_sum / value_count AS float)"),
+                Alias::new("value"),
+            ),
+    };
+    let sql = select
+        .from(Alias::new(resolution.table()))
         .and_where(Expr::col(Metrics::RunID).eq(params.run_id.clone()))
         .and_where(Expr::col(Metrics::Name).eq(params.name.clone()))
         .conditions(
@@ -119,7 +405,10 @@ pub async fn get_metrics(
         .limit(params.limit)
         .to_string(PostgresQueryBuilder);
 
-    let metrics: Vec<HttpMetric> = sqlx::query_as(&sql).fetch_all(&state.pg_client).await?;
+    let metrics: Vec<HttpMetric> = sqlx::query_as(&sql)
+        .fetch_all(&state.pg_client)
+        .instrument(info_span!("Querying QuestDB", db.statement = %sql))
+        .await?;
     let values: Vec<TimeMetric> = metrics
         .iter()
         .map(|m| TimeMetric {
@@ -130,7 +419,28 @@ pub async fn get_metrics(
     let res = JSONMetric {
         name: params.name,
         run_id: params.run_id,
+        resolution,
         values,
     };
     Ok(Json(res).into_response())
 }
+
+/// The finest resolution that keeps a chart of the whole run readable, once it is rolled up.
+///
+/// Raw samples are gone once the run is past retention, its rollups remain.
+fn default_resolution(run: Option<&test_run::Data>) -> Resolution {
+    let Some(run) = run.filter(|r| r.rolled_up_at.is_some()) else {
+        return Resolution::Raw;
+    };
+    let span = match (run.started_at, run.finished_at) {
+        (Some(started), Some(finished)) => finished - started,
+        _ => Duration::zero(),
+    };
+    if span > Duration::days(2) {
+        Resolution::Hour
+    } else if span > Duration::hours(6) || run.raw_purged_at.is_some() {
+        Resolution::Minute
+    } else {
+        Resolution::Raw
+    }
+}
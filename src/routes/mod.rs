@@ -1,56 +1,167 @@
+use self::audit::get_audit_log;
 use self::auth::{check_me, login, logout, register_new_user};
-use self::layers::{add_auth_layer, add_cors_layer, add_trace_layer};
-use self::metrics::{get_metrics, get_metrics_catalog};
-use self::organizations::{get_organizations, update_organization};
-use self::project::{create_project, get_project, update_project};
-use self::test_run::{create_test_run, get_test_run};
-use self::tests::{create_test, get_test};
-use self::user::{get_user, update_password, update_user};
+use self::baseline::{clear_baseline, set_baseline};
+use self::export::{create_user_export, download_export, export_run, get_user_export};
+use self::import::import_run;
+use self::internal::get_server_metrics;
+use self::layers::{
+    add_auth_layer, add_cors_layer, add_deprecation_layer, add_rate_limit_layer, add_trace_layer,
+};
+use self::metrics::{
+    create_catalog_entry, delete_catalog_entry, get_metrics, get_metrics_catalog,
+    update_catalog_entry,
+};
+use self::openapi::{ApiDoc, DOCS_PATH, SPEC_PATH};
+use self::organizations::{
+    delete_organization, get_organization_projects, get_organization_usage, get_organizations,
+    update_organization,
+};
+use self::project::{
+    create_project, delete_project, get_project, get_project_tests, update_project,
+};
+use self::report::get_run_report;
+use self::share::{
+    create_share_link, get_share_links, get_shared_metric, get_shared_run, revoke_share_link,
+};
+use self::sso::{get_sso_provider, sso_callback, sso_login, update_sso_provider};
+use self::test_run::{
+    create_test_run, delete_test_run, get_test_run, ingest_samples, report_checks,
+    update_test_run_status,
+};
+use self::tests::{create_test, delete_test, get_test, get_test_runs};
+use self::user::{delete_user, get_user, update_password, update_user};
+use crate::configuration::CorsSettings;
 use crate::domain::auth::TonsailUser;
 use crate::AppState;
-use axum::routing::{get, post, put};
+use axum::routing::{delete, get, post, put};
 use axum::Router;
 use axum_login::RequireAuthorizationLayer;
-use health_check::health_check;
+use health_check::{health_check, health_live, health_ready};
 use organizations::get_organization;
+use tower_http::compression::CompressionLayer;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 
+pub mod audit;
 pub mod auth;
+pub mod baseline;
+pub mod export;
 pub mod health_check;
+pub mod import;
+pub mod internal;
 pub mod layers;
 pub mod metrics;
+pub mod openapi;
 pub mod organizations;
 pub mod project;
+pub mod report;
+pub mod share;
+pub mod sso;
 pub mod test_run;
 pub mod tests;
 pub mod user;
 
-pub fn create_router(state: AppState) -> Router {
+pub const V1_PREFIX: &str = "/v1";
+
+/// `sunset` is the HTTP-date after which the unversioned routes may be removed.
+pub fn create_router(state: AppState, sunset: &str, cors: &CorsSettings) -> Router {
+    let v1 = v1_routes();
     let mut app = Router::new()
+        .nest(V1_PREFIX, v1.clone())
+        // Clients from before versioning still call the bare paths
+        .merge(add_deprecation_layer(v1, V1_PREFIX, sunset))
+        .route("/health_check", get(health_check))
+        .route("/health/live", get(health_live))
+        .route("/health/ready", get(health_ready))
+        .route("/internal/metrics", get(get_server_metrics))
+        .merge(SwaggerUi::new(DOCS_PATH).url(SPEC_PATH, ApiDoc::openapi()));
+    app = add_rate_limit_layer(app, state.clone());
+    app = add_cors_layer(app, cors);
+    app = add_auth_layer(app, state.clone());
+    app = add_trace_layer(app, state.server_metrics.clone());
+    app.with_state(state)
+}
+
+/// A later version nests its own route table, reusing the handlers that did not change.
+fn v1_routes() -> Router<AppState> {
+    Router::new()
         .route("/me", get(check_me))
         .route("/logout", post(logout))
         .route("/metrics", get(get_metrics))
-        .route("/metrics/catalog", get(get_metrics_catalog))
-        .route("/users/:user_id", get(get_user).put(update_user))
+        .route(
+            "/metrics/catalog",
+            get(get_metrics_catalog).post(create_catalog_entry),
+        )
+        .route(
+            "/metrics/catalog/:entry_id",
+            put(update_catalog_entry).delete(delete_catalog_entry),
+        )
+        .route(
+            "/users/:user_id",
+            get(get_user).put(update_user).delete(delete_user),
+        )
         .route("/users/:user_id/password", put(update_password))
-        .route("/runs/:run_id", get(get_test_run).post(create_test_run))
+        .route("/users/:user_id/exports", post(create_user_export))
+        .route("/users/:user_id/exports/:export_id", get(get_user_export))
+        .route(
+            "/runs/:run_id",
+            get(get_test_run)
+                .post(create_test_run)
+                .delete(delete_test_run),
+        )
+        .route("/runs/:run_id/status", put(update_test_run_status))
+        .route("/runs/:run_id/samples", post(ingest_samples))
+        .route("/runs/:run_id/checks", post(report_checks))
+        .route(
+            "/runs/:run_id/export",
+            get(export_run).layer(CompressionLayer::new()),
+        )
+        .route("/runs/:run_id/report", get(get_run_report))
+        .route(
+            "/runs/:run_id/shares",
+            get(get_share_links).post(create_share_link),
+        )
+        .route("/runs/:run_id/shares/:share_id", delete(revoke_share_link))
         .route("/tests", post(create_test))
-        .route("/tests/:test_id", get(get_test))
+        .route("/tests/:test_id", get(get_test).delete(delete_test))
+        .route("/tests/:test_id/runs", get(get_test_runs))
+        .route("/tests/:test_id/imports", post(import_run))
+        .route(
+            "/tests/:test_id/baseline",
+            put(set_baseline).delete(clear_baseline),
+        )
         .route("/projects", post(create_project))
         .route(
             "/projects/:project_id",
-            get(get_project).put(update_project),
+            get(get_project).put(update_project).delete(delete_project),
         )
+        .route("/projects/:project_id/tests", get(get_project_tests))
         .route("/organizations", get(get_organizations))
         .route(
             "/organizations/:organization_id",
-            get(get_organization).put(update_organization),
+            get(get_organization)
+                .put(update_organization)
+                .delete(delete_organization),
+        )
+        .route(
+            "/organizations/:organization_id/projects",
+            get(get_organization_projects),
+        )
+        .route(
+            "/organizations/:organization_id/usage",
+            get(get_organization_usage),
+        )
+        .route("/organizations/:organization_id/audit", get(get_audit_log))
+        .route(
+            "/organizations/:organization_id/sso",
+            get(get_sso_provider).put(update_sso_provider),
         )
         .route_layer(RequireAuthorizationLayer::<TonsailUser>::login())
         .route("/login", post(login))
         .route("/register", post(register_new_user))
-        .route("/health_check", get(health_check));
-    app = add_cors_layer(app);
-    app = add_auth_layer(app, state.clone());
-    app = add_trace_layer(app);
-    app.with_state(state)
+        .route("/exports/:token", get(download_export))
+        .route("/shared/:token", get(get_shared_run))
+        .route("/shared/:token/metrics", get(get_shared_metric))
+        .route("/sso/login", get(sso_login))
+        .route("/sso/callback", get(sso_callback))
 }
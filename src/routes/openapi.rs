@@ -0,0 +1,162 @@
+use super::{
+    audit, auth, baseline, export, health_check, import, internal, metrics, organizations, project,
+    report, share, sso, test_run, tests, user,
+};
+use crate::domain::{self, schemas};
+use crate::util::{app_error::ErrorMessage, pagination};
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+
+pub const SPEC_PATH: &str = "/openapi.json";
+pub const DOCS_PATH: &str = "/docs";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Tonsail API"),
+    paths(
+        auth::check_me,
+        auth::logout,
+        auth::login,
+        auth::register_new_user,
+        metrics::get_metrics,
+        metrics::get_metrics_catalog,
+        metrics::create_catalog_entry,
+        metrics::update_catalog_entry,
+        metrics::delete_catalog_entry,
+        user::get_user,
+        user::update_user,
+        user::delete_user,
+        user::update_password,
+        export::create_user_export,
+        export::get_user_export,
+        export::export_run,
+        export::download_export,
+        test_run::get_test_run,
+        test_run::create_test_run,
+        test_run::delete_test_run,
+        test_run::update_test_run_status,
+        test_run::ingest_samples,
+        test_run::report_checks,
+        import::import_run,
+        baseline::set_baseline,
+        baseline::clear_baseline,
+        report::get_run_report,
+        share::create_share_link,
+        share::get_share_links,
+        share::revoke_share_link,
+        share::get_shared_run,
+        share::get_shared_metric,
+        tests::create_test,
+        tests::get_test,
+        tests::delete_test,
+        tests::get_test_runs,
+        project::create_project,
+        project::get_project,
+        project::update_project,
+        project::delete_project,
+        project::get_project_tests,
+        organizations::get_organizations,
+        organizations::get_organization,
+        organizations::update_organization,
+        organizations::delete_organization,
+        organizations::get_organization_projects,
+        organizations::get_organization_usage,
+        audit::get_audit_log,
+        sso::get_sso_provider,
+        sso::update_sso_provider,
+        sso::sso_login,
+        sso::sso_callback,
+        health_check::health_check,
+        health_check::health_live,
+        health_check::health_ready,
+        internal::get_server_metrics,
+    ),
+    components(schemas(
+        ErrorMessage,
+        schemas::UserRole,
+        schemas::Plan,
+        schemas::MetricPolicy,
+        schemas::RunStatus,
+        schemas::ExportStatus,
+        schemas::CheckKind,
+        schemas::Organization,
+        schemas::Project,
+        schemas::User,
+        schemas::Test,
+        schemas::TestRun,
+        schemas::Regression,
+        schemas::RunCheck,
+        schemas::MetricsCatalog,
+        schemas::OidcProvider,
+        schemas::AuditLog,
+        pagination::OrganizationPage,
+        pagination::ProjectPage,
+        pagination::TestPage,
+        pagination::TestRunPage,
+        pagination::AuditLogPage,
+        domain::auth::AuthLoginForm,
+        domain::auth::AuthRegisterForm,
+        domain::user::UserUpdateForm,
+        domain::user::UserPasswordForm,
+        domain::organization::OrgUpdateForm,
+        domain::catalog::CatalogForm,
+        domain::oidc::OidcProviderForm,
+        domain::export::ExportResponse,
+        crate::questdb::export::ExportFormat,
+        domain::import::ImportFormat,
+        domain::regression::BaselineForm,
+        domain::report::ReportFormat,
+        domain::report::CheckOutcome,
+        domain::share::ShareForm,
+        domain::share::ShareLinkResponse,
+        domain::share::SharedRun,
+        domain::share::SharedSeries,
+        domain::share::SharedPoint,
+        crate::questdb::summary::MetricSummary,
+        domain::usage::UsageReport,
+        crate::configuration::PlanLimits,
+        metrics::JSONMetric,
+        metrics::TimeMetric,
+        crate::questdb::rollup::Resolution,
+        crate::questdb::Sample,
+        health_check::Readiness,
+        health_check::DependencyHealth,
+        health_check::ProbeStatus,
+    )),
+    modifiers(&SessionCookie),
+    tags(
+        (name = "auth", description = "Session login and registration"),
+        (name = "users"),
+        (name = "organizations"),
+        (name = "projects"),
+        (name = "tests"),
+        (name = "runs"),
+        (name = "metrics", description = "Load test results"),
+        (name = "exports", description = "Personal data and run sample exports"),
+        (name = "shares", description = "Read-only run links for people without an account"),
+        (name = "sso", description = "OpenID Connect single sign-on"),
+        (name = "audit"),
+        (name = "health", description = "Kubernetes probes"),
+        (name = "internal", description = "Operations of the server itself"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Authenticated routes expect the cookie set by `/login`, scrapers a bearer token.
+struct SessionCookie;
+
+impl Modify for SessionCookie {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("api_sid"))),
+        );
+        components.add_security_scheme(
+            "metrics_token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
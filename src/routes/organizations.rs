@@ -1,29 +1,146 @@
 use super::AppState;
 use crate::{
-    domain::organization::OrgUpdateForm,
-    prisma::organization,
-    util::{app_error::AppError, validation::ValidatedForm},
+    domain::{
+        auth::TonsailUser,
+        deletion::soft_delete_organization,
+        organization::OrgUpdateForm,
+        usage::{
+            billing_period, retained_samples, retention_days, UsageQuery, UsageReport, SAMPLE_BYTES,
+        },
+    },
+    prisma::{organization, project, usage_period, user, UserRole},
+    util::{
+        app_error::AppError,
+        audit::{record_audit, AuditEntry, RequestMeta},
+        pagination::{decode_cursor, NameSort, NamedListQuery, Page},
+        validation::{ValidatedForm, ValidatedQuery},
+    },
 };
 use axum::{
     extract::{Path, State},
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use http::StatusCode;
+use prisma_client_rust::chrono::Utc;
+use serde_json::json;
 use tracing::instrument;
 
+#[utoipa::path(
+    get, path = "/v1/organizations", tag = "organizations",
+    security(("session" = [])),
+    params(NamedListQuery),
+    responses(
+        (
+            status = 200,
+            description = "One page of organizations",
+            body = crate::util::pagination::OrganizationPage
+        ),
+        (
+            status = 400,
+            description = "Invalid query",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Fetching all organizations", skip_all)]
-pub async fn get_organizations(State(state): State<AppState>) -> Result<Response, AppError> {
-    let data = state
+pub async fn get_organizations(
+    State(state): State<AppState>,
+    ValidatedQuery(query): ValidatedQuery<NamedListQuery>,
+) -> Result<Response, AppError> {
+    let mut filters = vec![organization::deleted_at::equals(None)];
+    if let Some(name) = query.name {
+        filters.push(organization::name::contains(name));
+    }
+
+    let mut find = state
         .db_client
         .organization()
-        .find_many(vec![])
-        .exec()
-        .await?;
+        .find_many(filters)
+        .order_by(match query.sort {
+            NameSort::CreatedAt => organization::created_at::order(query.order.into()),
+            NameSort::UpdatedAt => organization::updated_at::order(query.order.into()),
+            NameSort::Name => organization::name::order(query.order.into()),
+        })
+        .order_by(organization::id::order(query.order.into()))
+        .take(query.limit + 1);
+    if let Some(cursor) = query.cursor {
+        find = find
+            .cursor(organization::id::equals(decode_cursor(&cursor)?))
+            .skip(1);
+    }
+    let items = find.exec().await?;
 
-    Ok(Json(data).into_response())
+    Ok(Json(Page::new(items, query.limit, |o| &o.id)).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/v1/organizations/{organization_id}/projects", tag = "organizations",
+    security(("session" = [])),
+    params(("organization_id" = String, Path, description = "Organization id"), NamedListQuery),
+    responses(
+        (
+            status = 200,
+            description = "One page of projects",
+            body = crate::util::pagination::ProjectPage
+        ),
+        (
+            status = 400,
+            description = "Invalid query",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Fetching organization projects", skip_all)]
+pub async fn get_organization_projects(
+    Path(org_id): Path<String>,
+    State(state): State<AppState>,
+    ValidatedQuery(query): ValidatedQuery<NamedListQuery>,
+) -> Result<Response, AppError> {
+    let mut filters = vec![
+        project::organization_id::equals(org_id),
+        project::deleted_at::equals(None),
+    ];
+    if let Some(name) = query.name {
+        filters.push(project::name::contains(name));
+    }
+
+    let mut find = state
+        .db_client
+        .project()
+        .find_many(filters)
+        .order_by(match query.sort {
+            NameSort::CreatedAt => project::created_at::order(query.order.into()),
+            NameSort::UpdatedAt => project::updated_at::order(query.order.into()),
+            NameSort::Name => project::name::order(query.order.into()),
+        })
+        .order_by(project::id::order(query.order.into()))
+        .take(query.limit + 1);
+    if let Some(cursor) = query.cursor {
+        find = find
+            .cursor(project::id::equals(decode_cursor(&cursor)?))
+            .skip(1);
+    }
+    let items = find.exec().await?;
+
+    Ok(Json(Page::new(items, query.limit, |p| &p.id)).into_response())
 }
 
+#[utoipa::path(
+    get, path = "/v1/organizations/{organization_id}", tag = "organizations",
+    security(("session" = [])),
+    params(("organization_id" = String, Path, description = "Organization id")),
+    responses(
+        (
+            status = 200,
+            description = "Organization with its users and projects",
+            body = crate::domain::schemas::Organization
+        ),
+        (status = 204, description = "No such organization")
+    )
+)]
 #[instrument(name = "Fetching organization", skip_all)]
 pub async fn get_organization(
     Path(org_id): Path<String>,
@@ -32,9 +149,16 @@ pub async fn get_organization(
     let resp = state
         .db_client
         .organization()
-        .find_first(vec![organization::id::equals(org_id)])
-        .with(organization::users::fetch(vec![]))
-        .with(organization::projects::fetch(vec![]))
+        .find_first(vec![
+            organization::id::equals(org_id),
+            organization::deleted_at::equals(None),
+        ])
+        .with(organization::users::fetch(vec![user::deleted_at::equals(
+            None,
+        )]))
+        .with(organization::projects::fetch(vec![
+            project::deleted_at::equals(None),
+        ]))
         .exec()
         .await?;
 
@@ -44,21 +168,209 @@ pub async fn get_organization(
     }
 }
 
+#[utoipa::path(
+    get, path = "/v1/organizations/{organization_id}/usage", tag = "organizations",
+    security(("session" = [])),
+    params(("organization_id" = String, Path, description = "Organization id"), UsageQuery),
+    responses(
+        (
+            status = 200,
+            description = "Usage of the billing period and the plan limits",
+            body = crate::domain::usage::UsageReport
+        ),
+        (
+            status = 403,
+            description = "Requires an owner or admin of the organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Fetching organization usage", skip_all)]
+pub async fn get_organization_usage(
+    Path(org_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(user): Extension<TonsailUser>,
+    ValidatedQuery(query): ValidatedQuery<UsageQuery>,
+) -> Result<Response, AppError> {
+    if user.organization_id() != org_id || user.role() == UserRole::Member {
+        return Err(AppError::RequireAdmin("the usage".to_string()));
+    }
+
+    let org = state
+        .db_client
+        .organization()
+        .find_first(vec![
+            organization::id::equals(org_id.clone()),
+            organization::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such organization exists".to_string()))?;
+    let (period_start, period_end) = billing_period(query.period.as_deref(), Utc::now());
+    let usage = state
+        .db_client
+        .usage_period()
+        .find_unique(usage_period::organization_id_period_start(
+            org_id.clone(),
+            period_start,
+        ))
+        .exec()
+        .await?;
+    let current = billing_period(None, Utc::now()).0 == period_start;
+    let retained_bytes = match current {
+        true => retained_samples(&state.db_client, &org_id).await? * SAMPLE_BYTES,
+        false => usage.as_ref().map_or(0, |u| u.retained_bytes),
+    };
+
+    Ok(Json(UsageReport {
+        organization_id: org_id,
+        plan: org.plan,
+        period_start,
+        period_end,
+        vu_hours: usage.as_ref().map_or(0.0, |u| u.vu_seconds as f64 / 3600.0),
+        runs: usage.as_ref().map_or(0, |u| u.runs),
+        ingested_samples: usage.as_ref().map_or(0, |u| u.ingested_samples),
+        retained_bytes,
+        retention_days: retention_days(&state.plans, &org),
+        limits: state.plans.limits(org.plan),
+    })
+    .into_response())
+}
+
+#[utoipa::path(
+    put, path = "/v1/organizations/{organization_id}", tag = "organizations",
+    security(("session" = [])),
+    params(("organization_id" = String, Path, description = "Organization id")),
+    request_body(
+        content = crate::domain::organization::OrgUpdateForm,
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Updated organization",
+            body = crate::domain::schemas::Organization
+        ),
+        (
+            status = 403,
+            description = "Requires an owner or admin of the organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Updating organization", skip_all)]
 pub async fn update_organization(
     Path(org_id): Path<String>,
     State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
     ValidatedForm(org): ValidatedForm<OrgUpdateForm>,
 ) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound("No such organization exists".to_string());
+    if actor.organization_id() != org_id {
+        return Err(not_found());
+    }
+    if actor.role() == UserRole::Member {
+        return Err(AppError::RequireAdmin(
+            "updating the organization".to_string(),
+        ));
+    }
+
+    let before = state
+        .db_client
+        .organization()
+        .find_first(vec![
+            organization::id::equals(org_id.clone()),
+            organization::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(not_found)?;
+
+    let mut params = vec![organization::name::set(org.name)];
+    if let Some(days) = org.retention_days {
+        let limit = state.plans.limits(before.plan).retention_days;
+        if days > limit {
+            return Err(AppError::QuotaExceeded(format!(
+                "the plan keeps raw samples for at most {limit} days"
+            )));
+        }
+        params.push(organization::retention_days::set(Some(days as i32)));
+    }
+    if let Some(policy) = org.unknown_metrics {
+        params.push(organization::unknown_metrics::set(policy));
+    }
+
     let data = state
         .db_client
         .organization()
-        .update(
-            organization::id::equals(org_id),
-            vec![organization::name::set(org.name)],
-        )
+        .update(organization::id::equals(org_id), params)
         .exec()
         .await?;
 
+    let entry = AuditEntry::new("organization.updated", &data.id, "organization", &data.id)
+        .before(json!({
+            "name": before.name,
+            "retentionDays": before.retention_days,
+            "unknownMetrics": before.unknown_metrics,
+        }))
+        .after(json!({
+            "name": data.name,
+            "retentionDays": data.retention_days,
+            "unknownMetrics": data.unknown_metrics,
+        }));
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+
     Ok(Json(data).into_response())
 }
+
+#[utoipa::path(
+    delete, path = "/v1/organizations/{organization_id}", tag = "organizations",
+    security(("session" = [])),
+    params(("organization_id" = String, Path, description = "Organization id")),
+    responses(
+        (status = 204, description = "Organization and everything in it is deleted"),
+        (
+            status = 404,
+            description = "No such organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Deleting organization", skip_all)]
+pub async fn delete_organization(
+    Path(org_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound("No such organization exists".to_string());
+    if actor.organization_id() != org_id || actor.role() != UserRole::Owner {
+        return Err(not_found());
+    }
+
+    let id = org_id.clone();
+    let count = state
+        .db_client
+        ._transaction()
+        .run(|client| async move { soft_delete_organization(&client, id, Utc::now().into()).await })
+        .await?;
+
+    match count {
+        0 => Err(not_found()),
+        _ => {
+            let entry = AuditEntry::new("organization.deleted", &org_id, "organization", &org_id);
+            record_audit(&state, Some(actor.id()), &meta, entry).await;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+    }
+}
@@ -1,32 +1,54 @@
 use super::AppState;
 use crate::{
+    domain::{auth::TonsailUser, deletion::soft_delete_project},
     prisma::{organization, project, test},
-    util::nano_id::generate_id,
+    util::{
+        app_error::AppError,
+        audit::{record_audit, AuditEntry, RequestMeta},
+        nano_id::generate_id,
+        pagination::{decode_cursor, NameSort, NamedListQuery, Page},
+        validation::ValidatedQuery,
+    },
 };
 use axum::{
     extract::{Path, State},
     response::{IntoResponse, Response},
-    Form, Json,
+    Extension, Form, Json,
 };
 use http::{HeaderMap, StatusCode};
+use prisma_client_rust::chrono::Utc;
 use serde::Deserialize;
+use serde_json::json;
 use tracing::instrument;
+use utoipa::ToSchema;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateForm {
     name: String,
     organization_id: String,
 }
 
+#[utoipa::path(
+    post, path = "/v1/projects", tag = "projects",
+    security(("session" = [])),
+    request_body(content = inline(CreateForm), content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The new project", body = crate::domain::schemas::Project),
+        (
+            status = 404,
+            description = "No such organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Creating new project", skip_all)]
 pub async fn create_project(
-    headers: HeaderMap,
+    meta: RequestMeta,
     State(state): State<AppState>,
+    Extension(actor): Extension<TonsailUser>,
     Form(project): Form<CreateForm>,
 ) -> Response {
-    // This can not fail as this is set in middleware
-    let _request_id = headers.get("x-request-id").unwrap();
-
     let resp = state
         .db_client
         .project()
@@ -40,11 +62,35 @@ pub async fn create_project(
         .await;
 
     match resp {
-        Ok(data) => Json(data).into_response(),
+        Ok(data) => {
+            let entry = AuditEntry::new(
+                "project.created",
+                &data.organization_id,
+                "project",
+                &data.id,
+            )
+            .after(json!({ "name": data.name }));
+            record_audit(&state, Some(actor.id()), &meta, entry).await;
+            Json(data).into_response()
+        }
         Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
     }
 }
 
+#[utoipa::path(
+    get, path = "/v1/projects/{project_id}", tag = "projects",
+    security(("session" = [])),
+    params(("project_id" = String, Path, description = "Project id")),
+    responses(
+        (status = 200, description = "The project", body = crate::domain::schemas::Project),
+        (
+            status = 404,
+            description = "No such project",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Fetching project", skip_all)]
 pub async fn get_project(
     Path(project_id): Path<String>,
@@ -57,8 +103,10 @@ pub async fn get_project(
     let resp = state
         .db_client
         .project()
-        .find_first(vec![project::id::equals(project_id)])
-        .with(project::tests::fetch(vec![]).with(test::runs::fetch(vec![])))
+        .find_first(vec![
+            project::id::equals(project_id),
+            project::deleted_at::equals(None),
+        ])
         .exec()
         .await
         .unwrap();
@@ -69,20 +117,97 @@ pub async fn get_project(
     }
 }
 
-#[derive(Deserialize)]
+#[utoipa::path(
+    get, path = "/v1/projects/{project_id}/tests", tag = "projects",
+    security(("session" = [])),
+    params(("project_id" = String, Path, description = "Project id"), NamedListQuery),
+    responses(
+        (status = 200, description = "One page of tests", body = crate::util::pagination::TestPage),
+        (
+            status = 400,
+            description = "Invalid query",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Fetching project tests", skip_all)]
+pub async fn get_project_tests(
+    Path(project_id): Path<String>,
+    State(state): State<AppState>,
+    ValidatedQuery(query): ValidatedQuery<NamedListQuery>,
+) -> Result<Response, AppError> {
+    let mut filters = vec![
+        test::project_id::equals(project_id),
+        test::deleted_at::equals(None),
+    ];
+    if let Some(name) = query.name {
+        filters.push(test::name::contains(name));
+    }
+
+    let mut find = state
+        .db_client
+        .test()
+        .find_many(filters)
+        .order_by(match query.sort {
+            NameSort::CreatedAt => test::created_at::order(query.order.into()),
+            NameSort::UpdatedAt => test::updated_at::order(query.order.into()),
+            NameSort::Name => test::name::order(query.order.into()),
+        })
+        .order_by(test::id::order(query.order.into()))
+        .take(query.limit + 1);
+    if let Some(cursor) = query.cursor {
+        find = find
+            .cursor(test::id::equals(decode_cursor(&cursor)?))
+            .skip(1);
+    }
+    let items = find.exec().await?;
+
+    Ok(Json(Page::new(items, query.limit, |t| &t.id)).into_response())
+}
+
+#[derive(Deserialize, ToSchema)]
 pub struct UpdateForm {
     name: String,
 }
 
+#[utoipa::path(
+    put, path = "/v1/projects/{project_id}", tag = "projects",
+    security(("session" = [])),
+    params(("project_id" = String, Path, description = "Project id")),
+    request_body(content = inline(UpdateForm), content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Updated project", body = crate::domain::schemas::Project),
+        (
+            status = 404,
+            description = "No such project",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Updating project", skip_all)]
 pub async fn update_project(
     Path(project_id): Path<String>,
-    headers: HeaderMap,
+    meta: RequestMeta,
     State(state): State<AppState>,
+    Extension(actor): Extension<TonsailUser>,
     Form(project): Form<UpdateForm>,
 ) -> Response {
-    // This can not fail as this is set in middleware
-    let _request_id = headers.get("x-request-id").unwrap();
+    let before = state
+        .db_client
+        .project()
+        .find_first(vec![
+            project::id::equals(project_id.clone()),
+            project::deleted_at::equals(None),
+        ])
+        .exec()
+        .await;
+    let before = match before {
+        Ok(Some(data)) => data,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No such project exists").into_response(),
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
 
     let resp = state
         .db_client
@@ -95,7 +220,68 @@ pub async fn update_project(
         .await;
 
     match resp {
-        Ok(data) => Json(data).into_response(),
+        Ok(data) => {
+            let entry = AuditEntry::new(
+                "project.updated",
+                &data.organization_id,
+                "project",
+                &data.id,
+            )
+            .before(json!({ "name": before.name }))
+            .after(json!({ "name": data.name }));
+            record_audit(&state, Some(actor.id()), &meta, entry).await;
+            Json(data).into_response()
+        }
         Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
     }
 }
+
+#[utoipa::path(
+    delete, path = "/v1/projects/{project_id}", tag = "projects",
+    security(("session" = [])),
+    params(("project_id" = String, Path, description = "Project id")),
+    responses(
+        (status = 204, description = "Project and its tests are deleted"),
+        (
+            status = 404,
+            description = "No such project",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Deleting project", skip_all)]
+pub async fn delete_project(
+    Path(project_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    let data = state
+        .db_client
+        .project()
+        .find_first(vec![
+            project::id::equals(project_id),
+            project::organization_id::equals(actor.organization_id().to_string()),
+            project::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such project exists".to_string()))?;
+
+    let id = data.id.clone();
+    state
+        .db_client
+        ._transaction()
+        .run(|client| async move { soft_delete_project(&client, id, Utc::now().into()).await })
+        .await?;
+
+    let entry = AuditEntry::new(
+        "project.deleted",
+        &data.organization_id,
+        "project",
+        &data.id,
+    );
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
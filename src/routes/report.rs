@@ -0,0 +1,94 @@
+use super::AppState;
+use crate::{
+    domain::{
+        auth::TonsailUser,
+        organization::find_organization_run,
+        report::{ReportQuery, RunReport},
+        webhook::{queue_event, RunFinished, WebhookEvent},
+    },
+    prisma::{run_check, test_run, RunStatus},
+    util::{app_error::AppError, validation::ValidatedQuery},
+};
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use http::header;
+use tracing::{instrument, warn};
+
+/// Queues `run.finished` with the reports of the run to attach, a failure is only logged.
+pub async fn queue_run_finished(state: &AppState, org_id: &str, run: &test_run::Data) {
+    let event = WebhookEvent::RunFinished(RunFinished::of(run));
+    if let Err(e) = queue_event(&state.db_client, org_id, event).await {
+        warn!(error = %e, run_id = %run.id, "Could not queue webhook event");
+    }
+}
+
+#[utoipa::path(
+    get, path = "/v1/runs/{run_id}/report", tag = "runs",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id"), ReportQuery),
+    responses(
+        (
+            status = 200,
+            description = "The report as an attachment",
+            content(("application/xml" = String), ("text/html" = String))
+        ),
+        (
+            status = 400,
+            description = "Unknown report format",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "The run is not over yet",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Generating run report", skip_all)]
+pub async fn get_run_report(
+    Path(run_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(user): Extension<TonsailUser>,
+    ValidatedQuery(query): ValidatedQuery<ReportQuery>,
+) -> Result<Response, AppError> {
+    let mut run = find_organization_run(&state.db_client, user.organization_id(), &run_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such run exists".to_string()))?;
+    if !matches!(run.status, RunStatus::Finished | RunStatus::Aborted) {
+        return Err(AppError::Conflict(
+            "Reports are generated once the run is over".to_string(),
+        ));
+    }
+
+    let test_name = run.test.take().map(|t| t.name).unwrap_or_default();
+    let checks = state
+        .db_client
+        .run_check()
+        .find_many(vec![run_check::run_id::equals(run_id.clone())])
+        .exec()
+        .await?;
+    let report = RunReport::load(&state.pg_client, run, test_name, checks).await?;
+    let format = query.format;
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", format.filename(&run_id)),
+            ),
+        ],
+        report.render(format),
+    )
+        .into_response())
+}
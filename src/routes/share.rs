@@ -0,0 +1,325 @@
+use super::AppState;
+use crate::{
+    domain::{
+        auth::TonsailUser,
+        organization::{find_organization_run, organization_of_run},
+        report::{chart_bucket_secs, report_resolution},
+        share::{
+            check_lockout, hash_share_token, record_failed_attempt, ShareForm, ShareLinkResponse,
+            SharedRun, SharedSeries, SharedSeriesQuery, SHARE_PASSWORD_HEADER,
+        },
+    },
+    prisma::{share_link, test_run},
+    questdb::summary::{metric_series, metric_summaries, series_bucket_secs},
+    util::{
+        app_error::AppError,
+        audit::{record_audit, AuditEntry, RequestMeta},
+        hash::{check_hash, hash_password},
+        nano_id::{generate_id, generate_token},
+        validation::{ValidatedForm, ValidatedQuery},
+    },
+};
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use http::{HeaderMap, StatusCode};
+use prisma_client_rust::{
+    chrono::{self, Utc},
+    or,
+};
+use serde_json::json;
+use tracing::instrument;
+
+#[utoipa::path(
+    post, path = "/v1/runs/{run_id}/shares", tag = "shares",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id")),
+    request_body(content = ShareForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (
+            status = 200,
+            description = "The new link, its URL is only returned now",
+            body = ShareLinkResponse
+        ),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Creating share link", skip_all)]
+pub async fn create_share_link(
+    Path(run_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(user): Extension<TonsailUser>,
+    ValidatedForm(form): ValidatedForm<ShareForm>,
+) -> Result<Response, AppError> {
+    let run = find_organization_run(&state.db_client, user.organization_id(), &run_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such run exists".to_string()))?;
+
+    let token = generate_token();
+    let expires_at = form
+        .expires_in_hours
+        .map(|h| (Utc::now() + chrono::Duration::hours(h.into())).into());
+    let link = state
+        .db_client
+        .share_link()
+        .create(
+            generate_id(),
+            hash_share_token(&token),
+            user.id().to_string(),
+            test_run::id::equals(run.id),
+            vec![
+                share_link::expires_at::set(expires_at),
+                share_link::password::set(form.password.map(|p| hash_password(p.as_bytes()))),
+            ],
+        )
+        .exec()
+        .await?;
+
+    let entry = AuditEntry::new(
+        "share_link.created",
+        user.organization_id(),
+        "share_link",
+        &link.id,
+    )
+    .after(json!({
+        "runId": link.run_id,
+        "expiresAt": link.expires_at,
+        "hasPassword": link.password.is_some(),
+    }));
+    record_audit(&state, Some(user.id()), &meta, entry).await;
+    Ok(Json(ShareLinkResponse::created(link, &token)).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/v1/runs/{run_id}/shares", tag = "shares",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id")),
+    responses(
+        (status = 200, description = "Every link of the run", body = [ShareLinkResponse]),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Listing share links", skip_all)]
+pub async fn get_share_links(
+    Path(run_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(user): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    find_organization_run(&state.db_client, user.organization_id(), &run_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such run exists".to_string()))?;
+
+    let links = state
+        .db_client
+        .share_link()
+        .find_many(vec![share_link::run_id::equals(run_id)])
+        .exec()
+        .await?;
+    let links: Vec<_> = links.into_iter().map(ShareLinkResponse::from).collect();
+    Ok(Json(links).into_response())
+}
+
+#[utoipa::path(
+    delete, path = "/v1/runs/{run_id}/shares/{share_id}", tag = "shares",
+    security(("session" = [])),
+    params(
+        ("run_id" = String, Path, description = "Run id"),
+        ("share_id" = String, Path, description = "Share link id")
+    ),
+    responses(
+        (status = 204, description = "The link no longer works"),
+        (
+            status = 404,
+            description = "No such link",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Revoking share link", skip_all)]
+pub async fn revoke_share_link(
+    Path((run_id, share_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(user): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound("No such share link exists".to_string());
+    find_organization_run(&state.db_client, user.organization_id(), &run_id)
+        .await?
+        .ok_or_else(not_found)?;
+
+    let count = state
+        .db_client
+        .share_link()
+        .update_many(
+            vec![
+                share_link::id::equals(share_id.clone()),
+                share_link::run_id::equals(run_id),
+                share_link::revoked_at::equals(None),
+            ],
+            vec![share_link::revoked_at::set(Some(Utc::now().into()))],
+        )
+        .exec()
+        .await?;
+    if count == 0 {
+        return Err(not_found());
+    }
+
+    let entry = AuditEntry::new(
+        "share_link.revoked",
+        user.organization_id(),
+        "share_link",
+        share_id,
+    );
+    record_audit(&state, Some(user.id()), &meta, entry).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Resolves a live link to its run, checking the password and logging the access.
+async fn open_share_link(
+    state: &AppState,
+    token: &str,
+    headers: &HeaderMap,
+    meta: &RequestMeta,
+) -> Result<test_run::Data, AppError> {
+    let not_found = || AppError::NotFound("Share link is invalid or expired".to_string());
+    let link = state
+        .db_client
+        .share_link()
+        .find_first(vec![
+            share_link::token_hash::equals(hash_share_token(token)),
+            share_link::revoked_at::equals(None),
+            or![
+                share_link::expires_at::equals(None),
+                share_link::expires_at::gt(Utc::now().into())
+            ],
+            share_link::run::is(vec![test_run::deleted_at::equals(None)]),
+        ])
+        .with(share_link::run::fetch().with(test_run::test::fetch()))
+        .exec()
+        .await?
+        .ok_or_else(not_found)?;
+
+    let org_id = organization_of_run(&state.db_client, &link.run_id)
+        .await?
+        .ok_or_else(not_found)?;
+    if let Some(hash) = &link.password {
+        check_lockout(&state.rds_client, &link.id).await?;
+        let password = headers
+            .get(SHARE_PASSWORD_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if let Err(e) = check_hash(password.as_bytes(), hash) {
+            record_failed_attempt(&state.rds_client, &link.id).await?;
+            let entry = AuditEntry::new("share_link.denied", org_id, "share_link", &link.id);
+            record_audit(state, None, meta, entry).await;
+            return Err(e);
+        }
+    }
+
+    let entry = AuditEntry::new("share_link.accessed", org_id, "share_link", &link.id);
+    record_audit(state, None, meta, entry).await;
+
+    link.run.map(|r| *r).ok_or_else(not_found)
+}
+
+#[utoipa::path(
+    get, path = "/v1/shared/{token}", tag = "shares",
+    params(("token" = String, Path, description = "Token of the share link")),
+    responses(
+        (status = 200, description = "Summary of the shared run", body = SharedRun),
+        (
+            status = 401,
+            description = "Missing or wrong password",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "Invalid, revoked or expired link",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 429,
+            description = "The link is locked after too many wrong passwords",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Viewing shared run", skip_all)]
+pub async fn get_shared_run(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    meta: RequestMeta,
+) -> Result<Response, AppError> {
+    let mut run = open_share_link(&state, &token, &headers, &meta).await?;
+    let metrics = metric_summaries(&state.pg_client, &run.id, report_resolution(&run)).await?;
+    let test_name = run.test.take().map(|t| t.name).unwrap_or_default();
+    Ok(Json(SharedRun::new(run, test_name, metrics)).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/v1/shared/{token}/metrics", tag = "shares",
+    params(
+        ("token" = String, Path, description = "Token of the share link"),
+        SharedSeriesQuery
+    ),
+    responses(
+        (status = 200, description = "One metric of the shared run over time", body = SharedSeries),
+        (
+            status = 401,
+            description = "Missing or wrong password",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "Invalid, revoked or expired link",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 429,
+            description = "The link is locked after too many wrong passwords",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Viewing shared metric", skip_all)]
+pub async fn get_shared_metric(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    meta: RequestMeta,
+    ValidatedQuery(query): ValidatedQuery<SharedSeriesQuery>,
+) -> Result<Response, AppError> {
+    let run = open_share_link(&state, &token, &headers, &meta).await?;
+    let resolution = report_resolution(&run);
+    let bucket_secs = series_bucket_secs(resolution, chart_bucket_secs(&run));
+    let points = metric_series(
+        &state.pg_client,
+        &run.id,
+        Some(&query.name),
+        resolution,
+        bucket_secs,
+    )
+    .await?;
+    Ok(Json(SharedSeries::new(query.name, bucket_secs, points)).into_response())
+}
@@ -0,0 +1,425 @@
+use super::AppState;
+use crate::{
+    domain::{
+        auth::{AuthContext, TonsailUser},
+        oidc::{
+            email_domain, role_rank, OidcProviderForm, PendingSsoLogin, SsoCallbackQuery,
+            SsoLoginQuery,
+        },
+    },
+    prisma::{oidc_provider, organization, user, UserRole},
+    util::{
+        app_error::AppError,
+        audit::{record_audit, AuditEntry, RequestMeta},
+        hash::hash_password,
+        nano_id::{generate_id, generate_token},
+        oidc::{authorization_url, discover, exchange_code, validate_id_token, IdTokenClaims},
+        validation::{ValidatedForm, ValidatedQuery},
+    },
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect, Response},
+    Extension, Json,
+};
+use cookie::{time::Duration, Cookie, SameSite};
+use fred::prelude::*;
+use http::{header, HeaderMap};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+
+const SSO_STATE_PREFIX: &str = "tonsail-sso/";
+const SSO_STATE_TTL_SECS: i64 = 600;
+/// Holds the secret of the browser a pending login was started in.
+const SSO_BROWSER_COOKIE: &str = "tonsail_sso";
+
+fn browser_hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Sets, or with an empty value clears, the cookie binding a login to the browser.
+///
+/// It must be `Lax`, as the callback is a top-level navigation from the identity provider.
+fn browser_cookie(state: &AppState, value: &str, max_age_secs: i64) -> String {
+    Cookie::build(SSO_BROWSER_COOKIE, value.to_string())
+        .path("/")
+        .http_only(true)
+        .secure(state.session.secure)
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(max_age_secs))
+        .finish()
+        .to_string()
+}
+
+fn browser_secret(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == SSO_BROWSER_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Only Owners and Admins of the organization may see or change its SSO provider.
+fn require_sso_admin(actor: &TonsailUser, org_id: &str) -> Result<(), AppError> {
+    if actor.organization_id() != org_id || actor.role() == UserRole::Member {
+        return Err(AppError::NotFound("SSO provider".to_string()));
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get, path = "/v1/sso/login", tag = "sso",
+    params(SsoLoginQuery),
+    responses(
+        (status = 303, description = "Redirect to the identity provider"),
+        (
+            status = 404,
+            description = "No SSO provider with a verified domain for the email",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Starting SSO login", skip_all)]
+pub async fn sso_login(
+    State(state): State<AppState>,
+    ValidatedQuery(query): ValidatedQuery<SsoLoginQuery>,
+) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound(format!("SSO provider for {}", query.email));
+    let domain = email_domain(&query.email).ok_or_else(not_found)?;
+
+    let provider = state
+        .db_client
+        .oidc_provider()
+        .find_first(vec![
+            oidc_provider::email_domain::equals(domain),
+            oidc_provider::enabled::equals(true),
+        ])
+        .exec()
+        .await?
+        .filter(|p| p.domain_verified_at.is_some())
+        .ok_or_else(not_found)?;
+
+    let metadata = discover(&state.http_client, &provider.issuer).await?;
+    let csrf_state = generate_token();
+    let browser_secret = generate_token();
+    let pending = PendingSsoLogin {
+        provider_id: provider.id,
+        browser_hash: browser_hash(&browser_secret),
+        nonce: generate_token(),
+        code_verifier: generate_token(),
+    };
+    let url = authorization_url(
+        &metadata,
+        &provider.client_id,
+        &provider.redirect_uri,
+        &csrf_state,
+        &pending.nonce,
+        &pending.code_verifier,
+    )?;
+
+    state
+        .rds_client
+        .set::<(), _, _>(
+            format!("{SSO_STATE_PREFIX}{csrf_state}"),
+            serde_json::to_string(&pending)?,
+            Some(Expiration::EX(SSO_STATE_TTL_SECS)),
+            None,
+            false,
+        )
+        .await?;
+
+    let cookie = browser_cookie(&state, &browser_secret, SSO_STATE_TTL_SECS);
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(url.as_str())).into_response())
+}
+
+#[utoipa::path(
+    get, path = "/v1/sso/callback", tag = "sso",
+    params(SsoCallbackQuery),
+    responses(
+        (
+            status = 200,
+            description = "Logged in, sets the session cookie",
+            body = crate::domain::schemas::User
+        ),
+        (
+            status = 401,
+            description = "SSO login failed, or was started in another browser",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Finishing SSO login", skip_all)]
+pub async fn sso_callback(
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    mut auth: AuthContext,
+    headers: HeaderMap,
+    Query(query): Query<SsoCallbackQuery>,
+) -> Result<Response, AppError> {
+    // The state is single use, so it is taken atomically before anything else can fail
+    let key = format!("{SSO_STATE_PREFIX}{}", query.state);
+    let pending = state.rds_client.getdel::<Option<String>, _>(&key).await?;
+    let pending: PendingSsoLogin = match pending {
+        Some(p) => serde_json::from_str(&p)?,
+        None => {
+            return Err(AppError::UnAuthorized(
+                "SSO login is expired or unknown".to_string(),
+            ))
+        }
+    };
+    // Stops a login started by someone else from being finished in this browser
+    if browser_secret(&headers).map(browser_hash).as_ref() != Some(&pending.browser_hash) {
+        return Err(AppError::UnAuthorized(
+            "SSO login was started in another browser".to_string(),
+        ));
+    }
+
+    let provider = state
+        .db_client
+        .oidc_provider()
+        .find_unique(oidc_provider::id::equals(pending.provider_id))
+        .exec()
+        .await?
+        .filter(|p| p.enabled && p.domain_verified_at.is_some())
+        .ok_or_else(|| AppError::UnAuthorized("SSO provider is disabled".to_string()))?;
+
+    let metadata = discover(&state.http_client, &provider.issuer).await?;
+    let id_token = exchange_code(
+        &state.http_client,
+        &metadata,
+        &provider.client_id,
+        provider.client_secret.as_deref(),
+        &provider.redirect_uri,
+        &query.code,
+        &pending.code_verifier,
+    )
+    .await?;
+    let claims = validate_id_token(
+        &state.http_client,
+        &metadata,
+        &provider.issuer,
+        &provider.client_id,
+        &id_token,
+        &pending.nonce,
+    )
+    .await?;
+
+    // Identity providers that do not vouch for the address could hand out any account
+    if claims.email_verified != Some(true)
+        || email_domain(&claims.email).as_deref() != Some(provider.email_domain.as_str())
+    {
+        return Err(AppError::UnAuthorized(
+            "Email is not allowed for this SSO provider".to_string(),
+        ));
+    }
+
+    let user = TonsailUser::from(provision_user(&state, &provider, claims).await?);
+    match auth.login(&user).await {
+        Ok(_) => {
+            let entry =
+                AuditEntry::new("user.sso_login", user.organization_id(), "user", user.id());
+            record_audit(&state, Some(user.id()), &meta, entry).await;
+            let cookie = browser_cookie(&state, "", 0);
+            Ok(([(header::SET_COOKIE, cookie)], Json(user)).into_response())
+        }
+        Err(_) => Err(AppError::UnAuthorized("Unable to login".to_string())),
+    }
+}
+
+/// Finds the user behind the ID token, creating it in the provider's organization on first login.
+///
+/// Whoever configured the provider controls the identities it vouches for, so it never logs in
+/// as an account with a higher role than theirs.
+#[instrument(name = "Provisioning SSO user", skip_all)]
+async fn provision_user(
+    state: &AppState,
+    provider: &oidc_provider::Data,
+    claims: IdTokenClaims,
+) -> Result<user::Data, AppError> {
+    let existing = state
+        .db_client
+        .user()
+        .find_unique(user::email::equals(claims.email.clone()))
+        .exec()
+        .await?;
+    // Providers set up by an operator have no configuring user and no cap
+    let cap = match &provider.configured_by {
+        Some(id) => state
+            .db_client
+            .user()
+            .find_first(vec![
+                user::id::equals(id.clone()),
+                user::organization_id::equals(provider.organization_id.clone()),
+                user::deleted_at::equals(None),
+            ])
+            .exec()
+            .await?
+            .map_or(UserRole::Member, |u| u.role),
+        None => UserRole::Owner,
+    };
+
+    match existing {
+        Some(u) if u.deleted_at.is_some() => {
+            Err(AppError::UnAuthorized("User is deleted".to_string()))
+        }
+        Some(u) if u.organization_id != provider.organization_id => Err(AppError::UnAuthorized(
+            "User belongs to another organization".to_string(),
+        )),
+        Some(u) if role_rank(u.role) > role_rank(cap) => Err(AppError::UnAuthorized(
+            "User has a higher role than the SSO provider may log in as".to_string(),
+        )),
+        Some(u) => Ok(u),
+        None => Ok(state
+            .db_client
+            .user()
+            .create(
+                generate_id(),
+                claims.email.clone(),
+                // SSO users never log in with a password, so they get an unguessable one
+                hash_password(generate_token().as_bytes()),
+                claims.name.unwrap_or(claims.email),
+                organization::id::equals(provider.organization_id.clone()),
+                vec![],
+            )
+            .exec()
+            .await?),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/v1/organizations/{organization_id}/sso", tag = "sso",
+    security(("session" = [])),
+    params(("organization_id" = String, Path, description = "Organization id")),
+    responses(
+        (
+            status = 200,
+            description = "SSO provider of the organization",
+            body = crate::domain::schemas::OidcProvider
+        ),
+        (
+            status = 404,
+            description = "No SSO provider, or the caller is not an admin of the organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Fetching SSO provider", skip_all)]
+pub async fn get_sso_provider(
+    Path(org_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(actor): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    require_sso_admin(&actor, &org_id)?;
+    let provider = state
+        .db_client
+        .oidc_provider()
+        .find_unique(oidc_provider::organization_id::equals(org_id))
+        .exec()
+        .await?;
+
+    match provider {
+        Some(mut data) => {
+            data.client_secret = None;
+            Ok(Json(data).into_response())
+        }
+        None => Err(AppError::NotFound("SSO provider".to_string())),
+    }
+}
+
+#[utoipa::path(
+    put, path = "/v1/organizations/{organization_id}/sso", tag = "sso",
+    security(("session" = [])),
+    params(("organization_id" = String, Path, description = "Organization id")),
+    request_body(content = OidcProviderForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (
+            status = 200,
+            description = "Configured SSO provider",
+            body = crate::domain::schemas::OidcProvider
+        ),
+        (
+            status = 400,
+            description = "Invalid form",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "The caller is not an admin of the organization",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Configuring SSO provider", skip_all)]
+pub async fn update_sso_provider(
+    Path(org_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+    ValidatedForm(form): ValidatedForm<OidcProviderForm>,
+) -> Result<Response, AppError> {
+    require_sso_admin(&actor, &org_id)?;
+    let email_domain = form.email_domain.to_lowercase();
+    let enabled = form.enabled.unwrap_or(true);
+    // A new domain has to be verified again
+    let verified_at = state
+        .db_client
+        .oidc_provider()
+        .find_unique(oidc_provider::organization_id::equals(org_id.clone()))
+        .exec()
+        .await?
+        .filter(|p| p.email_domain == email_domain)
+        .and_then(|p| p.domain_verified_at);
+    let configured_by = Some(actor.id().to_string());
+
+    let mut data = state
+        .db_client
+        .oidc_provider()
+        .upsert(
+            oidc_provider::organization_id::equals(org_id.clone()),
+            oidc_provider::create(
+                generate_id(),
+                form.issuer.clone(),
+                form.client_id.clone(),
+                form.redirect_uri.clone(),
+                email_domain.clone(),
+                organization::id::equals(org_id),
+                vec![
+                    oidc_provider::client_secret::set(form.client_secret.clone()),
+                    oidc_provider::enabled::set(enabled),
+                    oidc_provider::configured_by::set(configured_by.clone()),
+                ],
+            ),
+            vec![
+                oidc_provider::issuer::set(form.issuer),
+                oidc_provider::client_id::set(form.client_id),
+                oidc_provider::client_secret::set(form.client_secret),
+                oidc_provider::redirect_uri::set(form.redirect_uri),
+                oidc_provider::email_domain::set(email_domain),
+                oidc_provider::domain_verified_at::set(verified_at),
+                oidc_provider::enabled::set(enabled),
+                oidc_provider::configured_by::set(configured_by),
+            ],
+        )
+        .exec()
+        .await?;
+
+    data.client_secret = None;
+    let entry = AuditEntry::new(
+        "organization.sso_updated",
+        &data.organization_id,
+        "oidc_provider",
+        &data.id,
+    )
+    .after(serde_json::to_value(&data)?);
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+
+    Ok(Json(data).into_response())
+}
@@ -1,43 +1,476 @@
 use axum::extract::Path;
 use axum::response::{IntoResponse, Response};
-use axum::Json;
 use axum::{extract::State, Form};
+use axum::{Extension, Json};
 use http::{HeaderMap, StatusCode};
 use serde::Deserialize;
 use tracing::instrument;
+use utoipa::ToSchema;
 
-use crate::prisma::{test, test_run};
+use crate::domain::auth::TonsailUser;
+use crate::domain::catalog::{screen_unknown_metrics, unknown_metrics, validate_metric_name};
+use crate::domain::deletion::soft_delete_run;
+use crate::domain::organization::{
+    find_organization_run, organization_of_run, organization_of_test,
+};
+use crate::domain::report::{CheckOutcome, MAX_CHECK_NAME_LENGTH};
+use crate::domain::usage::{
+    check_concurrent_runs, check_run_limits, plan_of, record_usage, UsageDelta,
+};
+use crate::prisma::{organization, run_check, test, test_run, RunStatus};
+use crate::questdb::{insert_samples, run_sample_count, Sample};
+use crate::util::app_error::AppError;
+use crate::util::audit::{record_audit, AuditEntry, RequestMeta};
 use crate::util::nano_id::generate_id;
+use prisma_client_rust::chrono::{DateTime, Duration, FixedOffset, Utc};
+use prisma_client_rust::QueryError;
+use serde_json::json;
 
+use super::baseline::flag_regressions;
+use super::report::queue_run_finished;
 use super::AppState;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateForm {
     test_id: String,
+    #[serde(default = "default_vus")]
+    vus: u32,
+    /// Planned duration, checked against the plan's maximum
+    duration_secs: Option<u32>,
 }
 
+fn default_vus() -> u32 {
+    1
+}
+
+/// Most samples one ingestion request may carry.
+const MAX_SAMPLES_PER_BATCH: usize = 5000;
+
+#[derive(Deserialize, ToSchema)]
+pub struct SampleBatch {
+    samples: Vec<Sample>,
+}
+
+/// Most thresholds and checks one request may carry.
+const MAX_CHECKS_PER_BATCH: usize = 1000;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CheckBatch {
+    checks: Vec<CheckOutcome>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct StatusForm {
+    #[schema(value_type = crate::domain::schemas::RunStatus)]
+    status: RunStatus,
+}
+
+#[utoipa::path(
+    post, path = "/v1/runs/{run_id}", tag = "runs",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Ignored, a new id is generated")),
+    request_body(content = inline(CreateForm), content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The new run", body = crate::domain::schemas::TestRun),
+        (
+            status = 402,
+            description = "More virtual users or a longer duration than the plan allows",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such test",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Creating new test run", skip_all)]
 pub async fn create_test_run(
-    headers: HeaderMap,
+    meta: RequestMeta,
     State(state): State<AppState>,
+    Extension(actor): Extension<TonsailUser>,
     Form(test_run): Form<CreateForm>,
-) -> Response {
-    // This can not fail as this is set in middleware
-    let _request_id = headers.get("x-request-id").unwrap();
+) -> Result<Response, AppError> {
+    let org_id = organization_of_test(&state.db_client, &test_run.test_id)
+        .await?
+        .filter(|org_id| org_id == actor.organization_id())
+        .ok_or_else(|| AppError::NotFound("No such test exists".to_string()))?;
+    let limits = state
+        .plans
+        .limits(plan_of(&state.db_client, &org_id).await?);
+    check_run_limits(&limits, test_run.vus, test_run.duration_secs)?;
 
-    let resp = state
+    let usage_org = org_id.clone();
+    let data = state
         .db_client
-        .test_run()
-        .create(generate_id(), test::id::equals(test_run.test_id), vec![])
+        ._transaction()
+        .run(|client| async move {
+            let data = client
+                .test_run()
+                .create(
+                    generate_id(),
+                    test::id::equals(test_run.test_id),
+                    vec![
+                        test_run::vus::set(test_run.vus as i32),
+                        test_run::duration_secs::set(test_run.duration_secs.map(|d| d as i32)),
+                    ],
+                )
+                .exec()
+                .await?;
+            let delta = UsageDelta {
+                runs: 1,
+                ..Default::default()
+            };
+            record_usage(&client, &usage_org, delta).await?;
+            Ok::<_, QueryError>(data)
+        })
+        .await?;
+
+    let entry = AuditEntry::new("run.created", org_id, "run", &data.id);
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(Json(data).into_response())
+}
+
+#[utoipa::path(
+    put, path = "/v1/runs/{run_id}/status", tag = "runs",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id")),
+    request_body(content = inline(StatusForm), content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (
+            status = 200,
+            description = "The updated run, aborted at the plan's maximum duration \
+                if it ran longer",
+            body = crate::domain::schemas::TestRun
+        ),
+        (
+            status = 402,
+            description = "The organization already runs as many tests as its plan allows",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "The run can not move to that status, or changed status in the meantime",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Updating test run status", skip_all)]
+pub async fn update_test_run_status(
+    Path(run_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+    Form(form): Form<StatusForm>,
+) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound("No such run exists".to_string());
+    let org_id = actor.organization_id().to_string();
+    let run = find_organization_run(&state.db_client, &org_id, &run_id)
+        .await?
+        .ok_or_else(not_found)?;
+    let limits = state
+        .plans
+        .limits(plan_of(&state.db_client, &org_id).await?);
+
+    let now: DateTime<FixedOffset> = Utc::now().into();
+    let (updates, delta) = match (run.status, form.status) {
+        (RunStatus::NotStarted, RunStatus::Started) => {
+            let updates = vec![
+                test_run::status::set(RunStatus::Started),
+                test_run::started_at::set(Some(now)),
+            ];
+            (updates, None)
+        }
+        (RunStatus::NotStarted, RunStatus::Aborted) => {
+            let updates = vec![
+                test_run::status::set(RunStatus::Aborted),
+                test_run::finished_at::set(Some(now)),
+            ];
+            (updates, None)
+        }
+        (RunStatus::Started, to @ (RunStatus::Finished | RunStatus::Aborted)) => {
+            let samples = run_sample_count(&state.pg_client, &run_id).await?;
+            let started_at = run.started_at.unwrap_or(now);
+            let elapsed = (now - started_at).num_seconds().max(0);
+            // A run that outlived the plan is cut off at its maximum duration
+            let max_duration = i64::from(limits.max_run_duration_secs);
+            let (to, elapsed, finished_at) = match elapsed > max_duration {
+                true => (
+                    RunStatus::Aborted,
+                    max_duration,
+                    started_at + Duration::seconds(max_duration),
+                ),
+                false => (to, elapsed, now),
+            };
+            let updates = vec![
+                test_run::status::set(to),
+                test_run::finished_at::set(Some(finished_at)),
+                test_run::samples::set(samples),
+            ];
+            let delta = UsageDelta {
+                vu_seconds: i64::from(run.vus) * elapsed,
+                ingested_samples: samples,
+                ..Default::default()
+            };
+            (updates, Some(delta))
+        }
+        (from, to) => {
+            return Err(AppError::Conflict(format!(
+                "A {} run can not become {}",
+                json!(from),
+                json!(to)
+            )))
+        }
+    };
+
+    let (from, to) = (run.status, form.status);
+    let usage_org = org_id.clone();
+    let mut data = state
+        .db_client
+        ._transaction()
+        .run(|client| async move {
+            if to == RunStatus::Started {
+                check_concurrent_runs(&client, &usage_org, &limits).await?;
+            }
+            // Only the request that moves the run away from `from` applies its usage
+            let moved = client
+                .test_run()
+                .update_many(
+                    vec![
+                        test_run::id::equals(run_id.clone()),
+                        test_run::status::equals(from),
+                    ],
+                    updates,
+                )
+                .exec()
+                .await?;
+            if moved != 1 {
+                return Err(AppError::Conflict(
+                    "The run changed status in the meantime".to_string(),
+                ));
+            }
+            if let Some(delta) = delta {
+                record_usage(&client, &usage_org, delta).await?;
+            }
+            client
+                .test_run()
+                .find_unique(test_run::id::equals(run_id))
+                .exec()
+                .await?
+                .ok_or_else(|| AppError::NotFound("No such run exists".to_string()))
+        })
+        .await?;
+    if data.status == RunStatus::Finished {
+        flag_regressions(&state, &meta, &org_id, &mut data).await;
+    }
+    if matches!(data.status, RunStatus::Finished | RunStatus::Aborted) {
+        queue_run_finished(&state, &org_id, &data).await;
+    }
+
+    let entry = AuditEntry::new("run.status_changed", org_id, "run", &data.id)
+        .before(json!({ "status": run.status }))
+        .after(json!({ "status": data.status }));
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(Json(data).into_response())
+}
+
+#[utoipa::path(
+    post, path = "/v1/runs/{run_id}/samples", tag = "runs",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id")),
+    request_body(content = inline(SampleBatch), content_type = "application/json"),
+    responses(
+        (status = 204, description = "The samples are stored"),
+        (
+            status = 400,
+            description = "Invalid metric names, too many samples, \
+                or metrics the organization's policy rejects",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "The run is not started",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Ingesting run samples", skip_all)]
+pub async fn ingest_samples(
+    Path(run_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(actor): Extension<TonsailUser>,
+    Json(batch): Json<SampleBatch>,
+) -> Result<Response, AppError> {
+    if batch.samples.len() > MAX_SAMPLES_PER_BATCH {
+        return Err(AppError::BadRequest(format!(
+            "a request carries at most {MAX_SAMPLES_PER_BATCH} samples"
+        )));
+    }
+    if let Some(sample) = batch
+        .samples
+        .iter()
+        .find(|s| validate_metric_name(&s.name).is_err())
+    {
+        return Err(AppError::BadRequest(format!(
+            "{} is not a valid metric name",
+            sample.name
+        )));
+    }
+
+    let not_found = || AppError::NotFound("No such run exists".to_string());
+    let org_id = actor.organization_id().to_string();
+    let run = find_organization_run(&state.db_client, &org_id, &run_id)
+        .await?
+        .ok_or_else(not_found)?;
+    if run.status != RunStatus::Started {
+        return Err(AppError::Conflict(
+            "Samples are only taken while the run is started".to_string(),
+        ));
+    }
+    let org = state
+        .db_client
+        .organization()
+        .find_unique(organization::id::equals(org_id.clone()))
         .exec()
-        .await;
+        .await?
+        .ok_or_else(not_found)?;
 
-    match resp {
-        Ok(data) => Json(data).into_response(),
-        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    let names = batch.samples.iter().map(|s| s.name.as_str());
+    let unknown = unknown_metrics(&state.db_client, &org_id, names).await?;
+    let uncatalogued = screen_unknown_metrics(org.unknown_metrics, unknown)?;
+    insert_samples(&state.pg_client, &run_id, &batch.samples, &uncatalogued).await?;
+    state
+        .server_metrics
+        .add_ingested_samples(batch.samples.len() as u64);
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[utoipa::path(
+    post, path = "/v1/runs/{run_id}/checks", tag = "runs",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id")),
+    request_body(content = inline(CheckBatch), content_type = "application/json"),
+    responses(
+        (status = 204, description = "The outcomes are stored, replacing earlier ones"),
+        (
+            status = 400,
+            description = "Too many outcomes, or names that are empty or too long",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "The run has not started yet",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Reporting run checks", skip_all)]
+pub async fn report_checks(
+    Path(run_id): Path<String>,
+    State(state): State<AppState>,
+    Extension(actor): Extension<TonsailUser>,
+    Json(batch): Json<CheckBatch>,
+) -> Result<Response, AppError> {
+    if batch.checks.len() > MAX_CHECKS_PER_BATCH {
+        return Err(AppError::BadRequest(format!(
+            "a request carries at most {MAX_CHECKS_PER_BATCH} thresholds and checks"
+        )));
     }
+    if batch
+        .checks
+        .iter()
+        .any(|c| c.name.is_empty() || c.name.chars().count() > MAX_CHECK_NAME_LENGTH)
+    {
+        return Err(AppError::BadRequest(format!(
+            "threshold and check names are 1 to {MAX_CHECK_NAME_LENGTH} characters long"
+        )));
+    }
+
+    let run = find_organization_run(&state.db_client, actor.organization_id(), &run_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such run exists".to_string()))?;
+    // The load generator knows whether its thresholds held once it stopped
+    if run.status == RunStatus::NotStarted {
+        return Err(AppError::Conflict(
+            "Checks are only reported once the run is started".to_string(),
+        ));
+    }
+
+    state
+        .db_client
+        ._transaction()
+        .run(|client| async move {
+            for check in batch.checks {
+                let counts = || {
+                    vec![
+                        run_check::passes::set(check.passes as i64),
+                        run_check::fails::set(check.fails as i64),
+                    ]
+                };
+                client
+                    .run_check()
+                    .upsert(
+                        run_check::run_id_kind_name(run_id.clone(), check.kind, check.name.clone()),
+                        run_check::create(
+                            generate_id(),
+                            check.kind,
+                            check.name,
+                            test_run::id::equals(run_id.clone()),
+                            counts(),
+                        ),
+                        counts(),
+                    )
+                    .exec()
+                    .await?;
+            }
+            Ok::<_, QueryError>(())
+        })
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
 }
 
+#[utoipa::path(
+    get, path = "/v1/runs/{run_id}", tag = "runs",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id")),
+    responses(
+        (status = 200, description = "The run", body = crate::domain::schemas::TestRun),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Fetching test run", skip_all)]
 pub async fn get_test_run(
     Path(run_id): Path<String>,
@@ -50,7 +483,12 @@ pub async fn get_test_run(
     let resp = state
         .db_client
         .test_run()
-        .find_first(vec![test_run::id::equals(run_id)])
+        .find_first(vec![
+            test_run::id::equals(run_id),
+            test_run::deleted_at::equals(None),
+        ])
+        .with(test_run::regressions::fetch(vec![]))
+        .with(test_run::checks::fetch(vec![]))
         .exec()
         .await
         .unwrap();
@@ -60,3 +498,40 @@ pub async fn get_test_run(
         None => (StatusCode::NOT_FOUND, "No such run exists").into_response(),
     }
 }
+
+#[utoipa::path(
+    delete, path = "/v1/runs/{run_id}", tag = "runs",
+    security(("session" = [])),
+    params(("run_id" = String, Path, description = "Run id")),
+    responses(
+        (status = 204, description = "Run is deleted"),
+        (
+            status = 404,
+            description = "No such run",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Deleting test run", skip_all)]
+pub async fn delete_test_run(
+    Path(run_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound("No such run exists".to_string());
+    let org_id = organization_of_run(&state.db_client, &run_id)
+        .await?
+        .filter(|org_id| org_id == actor.organization_id())
+        .ok_or_else(not_found)?;
+
+    let count = soft_delete_run(&state.db_client, run_id.clone(), Utc::now().into()).await?;
+    if count == 0 {
+        return Err(not_found());
+    }
+
+    let entry = AuditEntry::new("run.deleted", org_id, "run", run_id);
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
@@ -1,30 +1,68 @@
 use axum::extract::Path;
 use axum::response::{IntoResponse, Response};
-use axum::Json;
 use axum::{extract::State, Form};
+use axum::{Extension, Json};
 use http::{HeaderMap, StatusCode};
 use serde::Deserialize;
+use serde_json::json;
 use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
-use crate::prisma::{project, test};
+use crate::domain::auth::TonsailUser;
+use crate::domain::deletion::soft_delete_test;
+use crate::domain::organization::organization_of_test;
+use crate::prisma::{project, test, test_run, RunStatus};
+use crate::util::app_error::AppError;
+use crate::util::audit::{record_audit, AuditEntry, RequestMeta};
 use crate::util::nano_id::generate_id;
+use crate::util::pagination::{decode_cursor, default_limit, Page, SortOrder, MAX_LIMIT};
+use crate::util::validation::ValidatedQuery;
+use prisma_client_rust::chrono::{DateTime, FixedOffset, Utc};
 
 use super::AppState;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateForm {
     name: String,
     project_id: String,
 }
 
+#[utoipa::path(
+    post, path = "/v1/tests", tag = "tests",
+    security(("session" = [])),
+    request_body(content = inline(CreateForm), content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The new test", body = crate::domain::schemas::Test),
+        (
+            status = 404,
+            description = "No such project",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Creating new test", skip_all)]
 pub async fn create_test(
-    headers: HeaderMap,
+    meta: RequestMeta,
     State(state): State<AppState>,
+    Extension(actor): Extension<TonsailUser>,
     Form(test): Form<CreateForm>,
 ) -> Response {
-    // This can not fail as this is set in middleware
-    let _request_id = headers.get("x-request-id").unwrap();
+    let project = state
+        .db_client
+        .project()
+        .find_first(vec![
+            project::id::equals(test.project_id.clone()),
+            project::deleted_at::equals(None),
+        ])
+        .exec()
+        .await;
+    let project = match project {
+        Ok(Some(data)) => data,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No such project exists").into_response(),
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
 
     let resp = state
         .db_client
@@ -39,11 +77,30 @@ pub async fn create_test(
         .await;
 
     match resp {
-        Ok(data) => Json(data).into_response(),
+        Ok(data) => {
+            let entry = AuditEntry::new("test.created", &project.organization_id, "test", &data.id)
+                .after(json!({ "name": data.name, "projectId": data.project_id }));
+            record_audit(&state, Some(actor.id()), &meta, entry).await;
+            Json(data).into_response()
+        }
         Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
     }
 }
 
+#[utoipa::path(
+    get, path = "/v1/tests/{test_id}", tag = "tests",
+    security(("session" = [])),
+    params(("test_id" = String, Path, description = "Test id")),
+    responses(
+        (status = 200, description = "The test", body = crate::domain::schemas::Test),
+        (
+            status = 404,
+            description = "No such test",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Fetching test", skip_all)]
 pub async fn get_test(
     Path(test_id): Path<String>,
@@ -56,8 +113,10 @@ pub async fn get_test(
     let resp = state
         .db_client
         .test()
-        .find_first(vec![test::id::equals(test_id)])
-        .with(test::runs::fetch(vec![]))
+        .find_first(vec![
+            test::id::equals(test_id),
+            test::deleted_at::equals(None),
+        ])
         .exec()
         .await
         .unwrap();
@@ -67,3 +126,130 @@ pub async fn get_test(
         None => (StatusCode::NOT_FOUND, "No such test exists").into_response(),
     }
 }
+
+#[utoipa::path(
+    delete, path = "/v1/tests/{test_id}", tag = "tests",
+    security(("session" = [])),
+    params(("test_id" = String, Path, description = "Test id")),
+    responses(
+        (status = 204, description = "Test and its runs are deleted"),
+        (
+            status = 404,
+            description = "No such test",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Deleting test", skip_all)]
+pub async fn delete_test(
+    Path(test_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound("No such test exists".to_string());
+    let org_id = organization_of_test(&state.db_client, &test_id)
+        .await?
+        .filter(|org_id| org_id == actor.organization_id())
+        .ok_or_else(not_found)?;
+
+    let id = test_id.clone();
+    let count = state
+        .db_client
+        ._transaction()
+        .run(|client| async move { soft_delete_test(&client, id, Utc::now().into()).await })
+        .await?;
+    if count == 0 {
+        return Err(not_found());
+    }
+
+    let entry = AuditEntry::new("test.deleted", org_id, "test", test_id);
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RunSort {
+    #[default]
+    CreatedAt,
+    Status,
+}
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RunListQuery {
+    #[param(value_type = Option<crate::domain::schemas::RunStatus>)]
+    status: Option<RunStatus>,
+    from: Option<DateTime<FixedOffset>>,
+    to: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    #[param(inline)]
+    sort: RunSort,
+    #[serde(default)]
+    #[param(inline)]
+    order: SortOrder,
+    #[validate(range(min = 1, max = "MAX_LIMIT"))]
+    #[serde(default = "default_limit")]
+    limit: i64,
+    cursor: Option<String>,
+}
+
+#[utoipa::path(
+    get, path = "/v1/tests/{test_id}/runs", tag = "tests",
+    security(("session" = [])),
+    params(("test_id" = String, Path, description = "Test id"), RunListQuery),
+    responses(
+        (
+            status = 200,
+            description = "One page of runs",
+            body = crate::util::pagination::TestRunPage
+        ),
+        (
+            status = 400,
+            description = "Invalid query",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Fetching test runs", skip_all)]
+pub async fn get_test_runs(
+    Path(test_id): Path<String>,
+    State(state): State<AppState>,
+    ValidatedQuery(query): ValidatedQuery<RunListQuery>,
+) -> Result<Response, AppError> {
+    let mut filters = vec![
+        test_run::test_id::equals(test_id),
+        test_run::deleted_at::equals(None),
+    ];
+    if let Some(status) = query.status {
+        filters.push(test_run::status::equals(status));
+    }
+    if let Some(from) = query.from {
+        filters.push(test_run::created_at::gte(from));
+    }
+    if let Some(to) = query.to {
+        filters.push(test_run::created_at::lt(to));
+    }
+
+    let mut find = state
+        .db_client
+        .test_run()
+        .find_many(filters)
+        .order_by(match query.sort {
+            RunSort::CreatedAt => test_run::created_at::order(query.order.into()),
+            RunSort::Status => test_run::status::order(query.order.into()),
+        })
+        .order_by(test_run::id::order(query.order.into()))
+        .take(query.limit + 1);
+    if let Some(cursor) = query.cursor {
+        find = find
+            .cursor(test_run::id::equals(decode_cursor(&cursor)?))
+            .skip(1);
+    }
+    let items = find.exec().await?;
+
+    Ok(Json(Page::new(items, query.limit, |r| &r.id)).into_response())
+}
@@ -2,11 +2,13 @@ use super::AppState;
 use crate::{
     domain::{
         auth::{AuthContext, TonsailUser},
+        organization::lock_organization,
         user::{UserPasswordForm, UserUpdateForm},
     },
-    prisma::user,
+    prisma::{user, UserRole},
     util::{
         app_error::AppError,
+        audit::{record_audit, AuditEntry, RequestMeta},
         hash::{check_hash, hash_password},
         validation::ValidatedForm,
     },
@@ -16,8 +18,23 @@ use axum::{
     response::{IntoResponse, Response},
     Extension, Json,
 };
+use http::StatusCode;
+use prisma_client_rust::chrono::Utc;
+use serde_json::json;
 use tracing::instrument;
 
+#[utoipa::path(
+    get, path = "/v1/users/{user_id}", tag = "users",
+    security(("session" = [])),
+    params(("user_id" = String, Path, description = "User id")),
+    responses(
+        (
+            status = 200,
+            description = "The user, null when it does not exist",
+            body = Option<crate::domain::schemas::User>
+        )
+    )
+)]
 #[instrument(name = "Fetching user", skip_all)]
 pub async fn get_user(
     Path(user_id): Path<String>,
@@ -26,19 +43,50 @@ pub async fn get_user(
     let data = state
         .db_client
         .user()
-        .find_first(vec![user::id::equals(user_id)])
+        .find_first(vec![
+            user::id::equals(user_id),
+            user::deleted_at::equals(None),
+        ])
         .exec()
         .await?;
 
     Ok(Json(data).into_response())
 }
 
+#[utoipa::path(
+    put, path = "/v1/users/{user_id}", tag = "users",
+    security(("session" = [])),
+    params(("user_id" = String, Path, description = "User id")),
+    request_body(content = UserUpdateForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Updated user", body = crate::domain::schemas::User),
+        (
+            status = 404,
+            description = "No such user",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Updating user", skip_all)]
 pub async fn update_user(
     Path(user_id): Path<String>,
     State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
     ValidatedForm(user): ValidatedForm<UserUpdateForm>,
 ) -> Result<Response, AppError> {
+    let before = state
+        .db_client
+        .user()
+        .find_first(vec![
+            user::id::equals(user_id.clone()),
+            user::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such user exists".to_string()))?;
+
     let mut params = vec![];
     if user.name.is_some() {
         params.push(user::name::set(user.name.unwrap()));
@@ -55,13 +103,38 @@ pub async fn update_user(
         .exec()
         .await?;
 
+    let action = match before.email != data.email {
+        true => "user.email_changed",
+        false => "user.updated",
+    };
+    let entry = AuditEntry::new(action, &data.organization_id, "user", &data.id)
+        .before(json!({ "name": before.name, "email": before.email }))
+        .after(json!({ "name": data.name, "email": data.email }));
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+
     Ok(Json(data).into_response())
 }
 
+#[utoipa::path(
+    put, path = "/v1/users/{user_id}/password", tag = "users",
+    security(("session" = [])),
+    params(("user_id" = String, Path, description = "User id")),
+    request_body(content = UserPasswordForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Updated user", body = crate::domain::schemas::User),
+        (
+            status = 401,
+            description = "Old password is wrong",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
 #[instrument(name = "Updating password", skip_all)]
 pub async fn update_password(
     Path(user_id): Path<String>,
     State(state): State<AppState>,
+    meta: RequestMeta,
     mut auth: AuthContext,
     Extension(user): Extension<TonsailUser>,
     ValidatedForm(password): ValidatedForm<UserPasswordForm>,
@@ -76,6 +149,14 @@ pub async fn update_password(
         .exec()
         .await?;
 
+    let entry = AuditEntry::new(
+        "user.password_changed",
+        &data.organization_id,
+        "user",
+        &data.id,
+    );
+    record_audit(&state, Some(user.id()), &meta, entry).await;
+
     let user = TonsailUser::from(data.clone());
     match auth.login(&user).await {
         Ok(_) => Ok(Json(data).into_response()),
@@ -84,3 +165,103 @@ pub async fn update_password(
         )),
     }
 }
+
+#[utoipa::path(
+    delete, path = "/v1/users/{user_id}", tag = "users",
+    security(("session" = [])),
+    params(("user_id" = String, Path, description = "User id")),
+    responses(
+        (status = 204, description = "User is deleted and logged out"),
+        (
+            status = 403,
+            description = "Only admins remove other users",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 404,
+            description = "No such user",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        ),
+        (
+            status = 409,
+            description = "User is the last owner",
+            body = crate::util::app_error::ErrorMessage,
+            content_type = "text/plain"
+        )
+    )
+)]
+#[instrument(name = "Deleting user", skip_all)]
+pub async fn delete_user(
+    Path(user_id): Path<String>,
+    State(state): State<AppState>,
+    meta: RequestMeta,
+    Extension(actor): Extension<TonsailUser>,
+) -> Result<Response, AppError> {
+    let data = state
+        .db_client
+        .user()
+        .find_first(vec![
+            user::id::equals(user_id),
+            user::organization_id::equals(actor.organization_id().to_string()),
+            user::deleted_at::equals(None),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such user exists".to_string()))?;
+
+    // Anyone may leave, only admins remove others and only owners remove owners
+    let allowed = data.id == actor.id()
+        || match data.role {
+            UserRole::Owner => actor.role() == UserRole::Owner,
+            _ => actor.role() != UserRole::Member,
+        };
+    if !allowed {
+        return Err(AppError::RequireAdmin("removing other users".to_string()));
+    }
+
+    // Concurrent removals of owners wait on the organization, so one of them always stays
+    let (org_id, user_id, role) = (data.organization_id.clone(), data.id.clone(), data.role);
+    state
+        .db_client
+        ._transaction()
+        .run(|client| async move {
+            if role == UserRole::Owner {
+                lock_organization(&client, &org_id).await?;
+                let owners = client
+                    .user()
+                    .count(vec![
+                        user::organization_id::equals(org_id),
+                        user::role::equals(UserRole::Owner),
+                        user::deleted_at::equals(None),
+                    ])
+                    .exec()
+                    .await?;
+                if owners <= 1 {
+                    return Err(AppError::Conflict(
+                        "Can not remove the last owner of an organization".to_string(),
+                    ));
+                }
+            }
+
+            let removed = client
+                .user()
+                .update_many(
+                    vec![user::id::equals(user_id), user::deleted_at::equals(None)],
+                    vec![user::deleted_at::set(Some(Utc::now().into()))],
+                )
+                .exec()
+                .await?;
+            match removed {
+                1 => Ok(()),
+                _ => Err(AppError::NotFound("No such user exists".to_string())),
+            }
+        })
+        .await?;
+
+    let entry = AuditEntry::new("user.deleted", &data.organization_id, "user", &data.id);
+    record_audit(&state, Some(actor.id()), &meta, entry).await;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
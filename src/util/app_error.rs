@@ -1,9 +1,13 @@
 use axum::body;
 use axum::extract::rejection::{FormRejection, QueryRejection};
 use axum::response::{IntoResponse, Response};
+use fred::prelude::RedisError;
 use http::StatusCode;
 use prisma_client_rust::QueryError;
 use thiserror::Error;
+use utoipa::ToSchema;
+
+use super::oidc::OidcError;
 
 /// Our app's top level error type.
 #[derive(Debug, Error)]
@@ -17,6 +21,18 @@ pub enum AppError {
     #[error("Require Admin privileges for {0}")]
     RequireAdmin(String),
 
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
     #[error(transparent)]
     DatabaseError(#[from] QueryError),
 
@@ -26,6 +42,18 @@ pub enum AppError {
     #[error(transparent)]
     PostgresError(#[from] sqlx::Error),
 
+    #[error(transparent)]
+    RedisError(#[from] RedisError),
+
+    #[error(transparent)]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    OidcError(#[from] OidcError),
+
+    #[error(transparent)]
+    MetricsError(#[from] prometheus::Error),
+
     #[error(transparent)]
     ValidationError(#[from] validator::ValidationErrors),
 
@@ -36,13 +64,23 @@ pub enum AppError {
     AxumQueryRejection(#[from] QueryRejection),
 }
 
+/// Body of every error response, the plain text description of an [`AppError`].
+#[derive(ToSchema)]
+#[schema(example = "Not found: No such project exists")]
+pub struct ErrorMessage(pub String);
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = match self {
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::UnAuthorized(_) => StatusCode::UNAUTHORIZED,
             AppError::RequireAdmin(_) => StatusCode::FORBIDDEN,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::QuotaExceeded(_) => StatusCode::PAYMENT_REQUIRED,
             AppError::DatabaseError(_) => StatusCode::BAD_REQUEST,
+            AppError::OidcError(_) => StatusCode::UNAUTHORIZED,
             AppError::ValidationError(_) => StatusCode::BAD_REQUEST,
             AppError::AxumFormRejection(_) => StatusCode::BAD_REQUEST,
             AppError::AxumQueryRejection(_) => StatusCode::BAD_REQUEST,
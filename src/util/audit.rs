@@ -0,0 +1,154 @@
+use super::nano_id::generate_id;
+use crate::{prisma::audit_log, AppState};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+};
+use http::{request::Parts, Extensions, HeaderMap};
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+use tracing::error;
+
+/// Who sent the request, as far as the audit log is concerned.
+#[derive(Debug, Clone, Default)]
+pub struct RequestMeta {
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for RequestMeta {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+
+        Ok(Self {
+            ip: client_ip(&parts.headers, &parts.extensions, &state.trusted_proxies),
+            request_id: header("x-request-id"),
+        })
+    }
+}
+
+/// Peer address, or the one a trusted proxy put in `X-Forwarded-For`.
+///
+/// Clients may prepend anything to the header, so the client is the right-most
+/// address that is not one of the trusted proxies.
+pub fn client_ip(
+    headers: &HeaderMap,
+    extensions: &Extensions,
+    trusted_proxies: &[IpAddr],
+) -> Option<String> {
+    let ConnectInfo(peer) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    let peer = peer.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer.to_string());
+    }
+
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+    }
+    Some(client.to_string())
+}
+
+#[derive(Debug)]
+pub struct AuditEntry {
+    action: &'static str,
+    organization_id: String,
+    target_type: &'static str,
+    target_id: String,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        action: &'static str,
+        organization_id: impl Into<String>,
+        target_type: &'static str,
+        target_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            action,
+            organization_id: organization_id.into(),
+            target_type,
+            target_id: target_id.into(),
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn before(mut self, value: Value) -> Self {
+        self.before = Some(value);
+        self
+    }
+
+    pub fn after(mut self, value: Value) -> Self {
+        self.after = Some(value);
+        self
+    }
+}
+
+/// Appends an entry to the audit log.
+///
+/// The audited change has already happened at this point, so a failed write
+/// is logged instead of failing the request.
+pub async fn record_audit(
+    state: &AppState,
+    actor_id: Option<&str>,
+    meta: &RequestMeta,
+    entry: AuditEntry,
+) {
+    let resp = state
+        .db_client
+        .audit_log()
+        .create(
+            generate_id(),
+            entry.organization_id,
+            entry.action.to_string(),
+            entry.target_type.to_string(),
+            entry.target_id,
+            vec![
+                audit_log::actor_id::set(actor_id.map(str::to_string)),
+                audit_log::before::set(entry.before.map(|v| v.to_string())),
+                audit_log::after::set(entry.after.map(|v| v.to_string())),
+                audit_log::ip::set(meta.ip.clone()),
+                audit_log::request_id::set(meta.request_id.clone()),
+            ],
+        )
+        .exec()
+        .await;
+
+    if let Err(e) = resp {
+        error!(
+            error = e.to_string(),
+            action = entry.action,
+            "Could not write audit log"
+        );
+    }
+}
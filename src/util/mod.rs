@@ -1,6 +1,14 @@
 pub mod app_error;
+pub mod audit;
 pub mod hash;
 pub mod nano_id;
+pub mod oidc;
+pub mod pagination;
+pub mod rate_limit;
 pub mod redis_session_store;
+pub mod secrets;
+pub mod server_metrics;
+pub mod shutdown;
+pub mod startup_error;
 pub mod tracing;
 pub mod validation;
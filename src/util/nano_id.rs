@@ -1,4 +1,6 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use nanoid::nanoid;
+use rand::Rng;
 
 pub fn generate_id() -> String {
     let alphabet: [char; 36] = [
@@ -8,3 +10,8 @@ pub fn generate_id() -> String {
 
     nanoid!(12, &alphabet)
 }
+
+/// Returns an unguessable URL safe token with 256 bits of entropy.
+pub fn generate_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>())
+}
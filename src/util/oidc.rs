@@ -0,0 +1,185 @@
+use super::tracing::inject_trace_context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::instrument;
+use url::Url;
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("Could not reach the identity provider: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("Invalid identity provider URL: {0}")]
+    Url(#[from] url::ParseError),
+
+    #[error("Invalid ID token: {0}")]
+    Token(#[from] jsonwebtoken::errors::Error),
+
+    #[error("Identity provider did not return an ID token")]
+    MissingIdToken,
+
+    #[error("No signing key found for kid {0:?}")]
+    UnknownKey(Option<String>),
+
+    #[error("ID token nonce does not match")]
+    NonceMismatch,
+
+    #[error("Signing key {0:?} is not an RSA or EC key")]
+    UnsupportedKey(Option<String>),
+}
+
+/// Subset of the OpenID Provider Metadata we rely on.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: String,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// Derives the S256 PKCE code challenge from a code verifier.
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+#[instrument(name = "Fetching OIDC discovery document", skip(http))]
+pub async fn discover(http: &reqwest::Client, issuer: &str) -> Result<ProviderMetadata, OidcError> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    );
+    Ok(inject_trace_context(http.get(url))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+pub fn authorization_url(
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    redirect_uri: &str,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+) -> Result<Url, OidcError> {
+    let mut url = Url::parse(&metadata.authorization_endpoint)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("scope", "openid email profile")
+        .append_pair("client_id", client_id)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("state", state)
+        .append_pair("nonce", nonce)
+        .append_pair("code_challenge", &pkce_challenge(code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    Ok(url)
+}
+
+#[instrument(name = "Exchanging OIDC authorization code", skip_all)]
+pub async fn exchange_code(
+    http: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    client_secret: Option<&str>,
+    redirect_uri: &str,
+    code: &str,
+    code_verifier: &str,
+) -> Result<String, OidcError> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", client_id),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(secret) = client_secret {
+        form.push(("client_secret", secret));
+    }
+
+    let resp: TokenResponse = inject_trace_context(http.post(&metadata.token_endpoint))
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    resp.id_token.ok_or(OidcError::MissingIdToken)
+}
+
+/// Algorithms a key may sign with, never the one the token claims for itself.
+fn key_algorithms(jwk: &Jwk) -> Result<Vec<Algorithm>, OidcError> {
+    let algorithms = match (&jwk.common.algorithm, &jwk.algorithm) {
+        (Some(alg @ (Algorithm::RS256 | Algorithm::ES256)), _) => vec![*alg],
+        (Some(_), _) => vec![],
+        (None, AlgorithmParameters::RSA(_)) => vec![Algorithm::RS256],
+        (None, AlgorithmParameters::EllipticCurve(_)) => vec![Algorithm::ES256],
+        (None, _) => vec![],
+    };
+    if algorithms.is_empty() {
+        return Err(OidcError::UnsupportedKey(jwk.common.key_id.clone()));
+    }
+    Ok(algorithms)
+}
+
+/// Checks the signature, audience, issuer and nonce of an ID token.
+///
+/// `issuer` is the one configured for the provider, not the discovered one.
+#[instrument(name = "Validating OIDC ID token", skip_all)]
+pub async fn validate_id_token(
+    http: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    issuer: &str,
+    client_id: &str,
+    id_token: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, OidcError> {
+    let header = decode_header(id_token)?;
+    let jwks: JwkSet = inject_trace_context(http.get(&metadata.jwks_uri))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or_else(|| OidcError::UnknownKey(header.kid.clone()))?;
+
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.algorithms = key_algorithms(jwk)?;
+    validation.set_audience(&[client_id]);
+    validation.set_issuer(&[issuer]);
+
+    let claims =
+        decode::<IdTokenClaims>(id_token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+    match &claims.nonce {
+        Some(n) if n == nonce => Ok(claims),
+        _ => Err(OidcError::NonceMismatch),
+    }
+}
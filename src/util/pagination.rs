@@ -0,0 +1,98 @@
+use super::app_error::AppError;
+use crate::domain::schemas;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use prisma_client_rust::Direction;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 200;
+
+pub fn default_limit() -> i64 {
+    DEFAULT_LIMIT
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl From<SortOrder> for Direction {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Asc => Direction::Asc,
+            SortOrder::Desc => Direction::Desc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NameSort {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Name,
+}
+
+/// Query of the list endpoints for resources that have a name.
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NamedListQuery {
+    pub name: Option<String>,
+    #[serde(default)]
+    #[param(inline)]
+    pub sort: NameSort,
+    #[serde(default)]
+    #[param(inline)]
+    pub order: SortOrder,
+    #[validate(range(min = 1, max = "MAX_LIMIT"))]
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    pub cursor: Option<String>,
+}
+
+/// One page of a list, `nextCursor` is absent on the last page.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[aliases(
+    OrganizationPage = Page<schemas::Organization>,
+    ProjectPage = Page<schemas::Project>,
+    TestPage = Page<schemas::Test>,
+    TestRunPage = Page<schemas::TestRun>,
+    AuditLogPage = Page<schemas::AuditLog>,
+)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Expects up to `limit + 1` items, the extra one only tells that there is a next page.
+    pub fn new(mut items: Vec<T>, limit: i64, id_of: impl Fn(&T) -> &str) -> Self {
+        let next_cursor = match items.len() as i64 > limit {
+            true => {
+                items.truncate(limit as usize);
+                items.last().map(|i| encode_cursor(id_of(i)))
+            }
+            false => None,
+        };
+        Self { items, next_cursor }
+    }
+}
+
+pub fn encode_cursor(id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(id)
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<String, AppError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| AppError::BadRequest("Invalid cursor".to_string()))
+}
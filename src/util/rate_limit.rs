@@ -0,0 +1,167 @@
+use crate::{
+    configuration::{BucketSettings, RateLimitSettings},
+    prisma::Plan,
+};
+use fred::{pool::RedisPool, prelude::*};
+use http::Method;
+
+const KEY_PREFIX: &str = "tonsail-ratelimit/";
+
+// Refills the bucket for the time since the last request, then takes one token.
+// Uses the Redis clock, so every server instance agrees on the elapsed time.
+const TOKEN_BUCKET: &str = r#"
+local capacity = tonumber(ARGV[1])
+local per_ms = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1]) or capacity
+local ts = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * per_ms)
+
+local allowed = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+  allowed = 1
+end
+
+local full_in = math.ceil((capacity - tokens) / per_ms)
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], full_in + 1000)
+
+local retry_in = 0
+if allowed == 0 then
+  retry_in = math.ceil((1 - tokens) / per_ms)
+end
+return {allowed, math.floor(tokens), full_in, retry_in}
+"#;
+
+/// Routes that share a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    Auth,
+    Query,
+    Runs,
+    Ingestion,
+}
+
+impl RouteGroup {
+    /// Group of a matched route without its version prefix, `None` if it is not limited.
+    pub fn of(method: &Method, route: &str) -> Option<Self> {
+        match (method, route) {
+            (_, "/login" | "/register" | "/sso/login" | "/sso/callback") => Some(Self::Auth),
+            (
+                &Method::GET,
+                "/metrics"
+                | "/metrics/catalog"
+                | "/runs/:run_id/export"
+                | "/runs/:run_id/report"
+                | "/shared/:token"
+                | "/shared/:token/metrics",
+            ) => Some(Self::Query),
+            (&Method::POST, "/runs/:run_id") => Some(Self::Runs),
+            (&Method::POST, "/runs/:run_id/samples" | "/tests/:test_id/imports") => {
+                Some(Self::Ingestion)
+            }
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::Query => "query",
+            Self::Runs => "runs",
+            Self::Ingestion => "ingestion",
+        }
+    }
+}
+
+/// Outcome of taking a token, rendered as the `RateLimit-*` headers.
+#[derive(Debug)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until the next request is allowed, zero when this one was
+    pub retry_after_secs: u64,
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    pool: RedisPool,
+    settings: RateLimitSettings,
+}
+
+impl RateLimiter {
+    pub fn new(pool: RedisPool, settings: RateLimitSettings) -> Self {
+        Self { pool, settings }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    /// Takes a token from the bucket of `client` for `group`.
+    ///
+    /// `client` is e.g. `org:<id>` or `ip:<address>`.
+    pub async fn check(
+        &self,
+        client: &str,
+        group: RouteGroup,
+        plan: Plan,
+    ) -> Result<RateLimitDecision, RedisError> {
+        let bucket = self.bucket(group, plan);
+        let per_ms = f64::from(bucket.refill_per_minute) / 60_000.0;
+        let key = format!("{KEY_PREFIX}{}/{client}", group.as_str());
+
+        let reply: Vec<i64> = self
+            .pool
+            .eval(
+                TOKEN_BUCKET,
+                key,
+                vec![bucket.capacity.to_string(), per_ms.to_string()],
+            )
+            .await?;
+        let [allowed, remaining, full_in_ms, retry_in_ms] = reply[..] else {
+            return Err(RedisError::new(
+                RedisErrorKind::Parse,
+                "Unexpected token bucket reply",
+            ));
+        };
+
+        Ok(RateLimitDecision {
+            allowed: allowed == 1,
+            limit: bucket.capacity,
+            remaining: remaining.max(0) as u32,
+            reset_secs: ms_to_secs(full_in_ms),
+            retry_after_secs: ms_to_secs(retry_in_ms),
+        })
+    }
+
+    fn bucket(&self, group: RouteGroup, plan: Plan) -> BucketSettings {
+        let base = match group {
+            RouteGroup::Auth => self.settings.auth,
+            RouteGroup::Query => self.settings.query,
+            RouteGroup::Runs => self.settings.runs,
+            RouteGroup::Ingestion => self.settings.ingestion,
+        };
+        let multipliers = self.settings.plan_multipliers;
+        let multiplier = match plan {
+            Plan::Free => multipliers.free,
+            Plan::Team => multipliers.team,
+            Plan::Enterprise => multipliers.enterprise,
+        };
+        BucketSettings {
+            capacity: base.capacity.saturating_mul(multiplier),
+            refill_per_minute: base.refill_per_minute.saturating_mul(multiplier),
+        }
+    }
+}
+
+fn ms_to_secs(ms: i64) -> u64 {
+    (ms.max(0) as u64 + 999) / 1000
+}
@@ -8,11 +8,20 @@ use fred::{
     types::{RedisKey, ScanType},
 };
 use futures::stream::StreamExt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Session key under which axum-login keeps the logged in user's id
+pub(crate) const SESSION_USER_ID_KEY: &str = "_user_id";
+/// Session key of the binding between the login and the user's password hash
+pub(crate) const SESSION_AUTH_ID_KEY: &str = "_auth_id";
+/// Sorted set of the session keys scored by their expiry, so counting needs no `SCAN`
+const INDEX_KEY: &str = "_index";
 
 #[derive(Clone)]
 pub struct RedisSessionStore {
     pool: RedisPool,
     prefix: Option<String>,
+    idle_timeout: Option<Duration>,
 }
 
 impl std::fmt::Debug for RedisSessionStore {
@@ -23,14 +32,90 @@ impl std::fmt::Debug for RedisSessionStore {
 
 impl RedisSessionStore {
     pub fn from_pool(pool: RedisPool, prefix: Option<String>) -> Self {
-        Self { pool, prefix }
+        Self {
+            pool,
+            prefix,
+            idle_timeout: None,
+        }
+    }
+
+    /// Sessions that are not used for `timeout` expire before their absolute TTL.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Seconds until the key expires, the sooner of the idle and absolute deadlines.
+    fn key_ttl(&self, session: &Session) -> Option<i64> {
+        let ttl = match (self.idle_timeout, session.expires_in()) {
+            (Some(idle), Some(absolute)) => Some(idle.min(absolute)),
+            (idle, absolute) => idle.or(absolute),
+        };
+        ttl.map(|d| d.as_secs().max(1) as i64)
     }
 
+    /// Sessions that have not expired, sessions stored before the index existed
+    /// are only counted once they are used again.
     pub async fn count(&self) -> Result<usize> {
-        match self.prefix {
-            None => Ok(self.pool.dbsize().await?),
-            Some(_) => Ok(self.ids().await?.map_or(0, |v| v.len())),
+        let index = self.prefix_key(INDEX_KEY);
+        self.pool
+            .zremrangebyscore::<i64, _, _, _>(&index, 0.0, now_secs())
+            .await?;
+        Ok(self.pool.zcard(&index).await?)
+    }
+
+    /// Records when the session key expires, never when it has no TTL.
+    async fn index(&self, key: &str, ttl: Option<i64>) -> Result {
+        let expires_at = ttl.map_or(f64::INFINITY, |ttl| now_secs() + ttl as f64);
+        self.pool
+            .zadd::<i64, _, _>(
+                self.prefix_key(INDEX_KEY),
+                None,
+                None,
+                false,
+                false,
+                (expires_at, key),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the stored sessions that are logged in as the given user.
+    pub async fn user_sessions(&self, user_id: &str) -> Result<Vec<(RedisKey, Session)>> {
+        let mut sessions = Vec::new();
+        for key in self.ids().await?.unwrap_or_default() {
+            let session = self
+                .pool
+                .get::<Option<String>, _>(key.clone())
+                .await?
+                .map(|v| serde_json::from_str::<Session>(&v))
+                .transpose()?;
+
+            if let Some(session) = session {
+                if session.get::<String>(SESSION_USER_ID_KEY).as_deref() == Some(user_id) {
+                    sessions.push((key, session));
+                }
+            }
+        }
+        Ok(sessions)
+    }
+
+    pub async fn destroy_user_sessions(&self, user_id: &str) -> Result<usize> {
+        let keys: Vec<RedisKey> = self
+            .user_sessions(user_id)
+            .await?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+
+        let count = keys.len();
+        if count > 0 {
+            self.pool.del::<i64, _>(keys.clone()).await?;
+            self.pool
+                .zrem::<i64, _, _>(self.prefix_key(INDEX_KEY), keys)
+                .await?;
         }
+        Ok(count)
     }
 
     async fn ids(&self) -> Result<Option<Vec<RedisKey>>> {
@@ -59,38 +144,60 @@ impl RedisSessionStore {
 #[async_trait]
 impl SessionStore for RedisSessionStore {
     async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
-        let id = Session::id_from_cookie_value(&cookie_value)?;
-        Ok(self
+        let id = self.prefix_key(&Session::id_from_cookie_value(&cookie_value)?);
+        let session: Option<Session> = self
             .pool
-            .get::<Option<String>, String>(self.prefix_key(&id))
+            .get::<Option<String>, _>(id.clone())
             .await?
             .map(|v| serde_json::from_str(&v))
-            .transpose()?)
+            .transpose()?;
+
+        // Every request pushes the idle deadline back
+        if let Some(ttl) = session.as_ref().and_then(|s| self.key_ttl(s)) {
+            self.pool.expire::<i64, _>(id.clone(), ttl).await?;
+            self.index(&id, Some(ttl)).await?;
+        }
+        Ok(session)
     }
 
     async fn store_session(&self, session: Session) -> Result<Option<String>> {
         let id = self.prefix_key(session.id());
         let string = serde_json::to_string(&session)?;
-        let expiration = session
-            .expires_in()
-            .map(|d| Expiration::EX(d.as_secs() as i64));
+        let ttl = self.key_ttl(&session);
 
-        self.pool.set(id, string, expiration, None, false).await?;
+        self.pool
+            .set(id.clone(), string, ttl.map(Expiration::EX), None, false)
+            .await?;
+        self.index(&id, ttl).await?;
 
         Ok(session.into_cookie_value())
     }
 
     async fn destroy_session(&self, session: Session) -> Result {
-        Ok(self.pool.del(self.prefix_key(session.id())).await?)
+        let id = self.prefix_key(session.id());
+        self.pool
+            .zrem::<i64, _, _>(self.prefix_key(INDEX_KEY), id.clone())
+            .await?;
+        Ok(self.pool.del(id).await?)
     }
 
     async fn clear_store(&self) -> Result {
         match self.prefix {
             None => Ok(self.pool.flushall(false).await?),
-            Some(_) => match self.ids().await? {
-                None => Ok(()),
-                Some(ids) => Ok(self.pool.del(ids).await?),
-            },
+            Some(_) => {
+                self.pool.del::<i64, _>(self.prefix_key(INDEX_KEY)).await?;
+                match self.ids().await? {
+                    None => Ok(()),
+                    Some(ids) => Ok(self.pool.del(ids).await?),
+                }
+            }
         }
     }
 }
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
@@ -0,0 +1,168 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use cookie::{Cookie, CookieJar, Key};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use serde::{de, Deserialize, Deserializer};
+use sha2::{Digest, Sha256, Sha512};
+use std::sync::Arc;
+
+/// Key material that is only shown as `[REDACTED]` when printed.
+///
+/// Accepts `base64:<data>`, `hex:<data>`, or the legacy comma separated byte list.
+#[derive(Clone)]
+pub struct SecretKey(Secret<Vec<u8>>);
+
+impl SecretKey {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(Secret::new(bytes))
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let bytes = if let Some(data) = value.strip_prefix("base64:") {
+            STANDARD
+                .decode(data)
+                .map_err(|e| format!("invalid base64 secret: {e}"))?
+        } else if let Some(data) = value.strip_prefix("hex:") {
+            hex::decode(data).map_err(|e| format!("invalid hex secret: {e}"))?
+        } else {
+            value
+                .split(',')
+                .map(|b| b.trim().parse::<u8>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("invalid byte list secret: {e}"))?
+        };
+        Ok(Self::new(bytes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.expose_secret().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        self.0.expose_secret()
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey([REDACTED])")
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Text(String),
+            Bytes(Vec<u8>),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Text(value) => SecretKey::parse(&value).map_err(de::Error::custom),
+            Raw::Bytes(bytes) => Ok(SecretKey::new(bytes)),
+        }
+    }
+}
+
+/// Deserializes keys from a list, or from a string of keys separated by `;`.
+///
+/// A `;` never appears in a key, unlike the `,` of the legacy byte lists.
+pub fn deserialize_secret_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<SecretKey>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Text(String),
+        Keys(Vec<SecretKey>),
+    }
+
+    match Raw::deserialize(deserializer)? {
+        Raw::Text(value) => value
+            .split(';')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(|key| SecretKey::parse(key).map_err(de::Error::custom))
+            .collect(),
+        Raw::Keys(keys) => Ok(keys),
+    }
+}
+
+/// Session cookie signing keys, the newest one first.
+///
+/// The newest key signs every cookie and binds every login, the older ones only verify
+/// what was signed or bound before a rotation.
+#[derive(Clone)]
+pub struct SigningKeys {
+    keys: Arc<Vec<Key>>,
+    current: SecretKey,
+    previous: Arc<Vec<SecretKey>>,
+    fingerprint: String,
+}
+
+impl SigningKeys {
+    /// Every key must be at least 64 bytes long, see `MIN_SECRET_LENGTH`.
+    pub fn new(current: &SecretKey, previous: &[SecretKey]) -> Self {
+        let keys = std::iter::once(current)
+            .chain(previous)
+            .map(|k| Key::from(k.expose()))
+            .collect();
+        Self {
+            keys: Arc::new(keys),
+            current: current.clone(),
+            previous: Arc::new(previous.to_vec()),
+            fingerprint: hex::encode(&Sha256::digest(current.expose())[..8]),
+        }
+    }
+
+    /// Key of the cookie signatures that the session layer checks, and of the login bindings.
+    pub fn current(&self) -> &[u8] {
+        self.current.expose()
+    }
+
+    /// Names the current key without revealing it.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// The session auth id of a login bound with any key, as the current key binds it.
+    ///
+    /// `None` when no key bound `auth_id` to the password hash, e.g. after a password change.
+    pub fn current_auth_id(&self, auth_id: &str, password_hash: &[u8]) -> Option<String> {
+        let current = session_auth_id(&self.current, password_hash)?;
+        let bound = current == auth_id
+            || self
+                .previous
+                .iter()
+                .any(|k| session_auth_id(k, password_hash).as_deref() == Some(auth_id));
+        bound.then_some(current)
+    }
+
+    /// Re-signs a cookie signed with a previous key, `None` if it needs no change or is forged.
+    pub fn resign(&self, name: &str, value: &str) -> Option<String> {
+        let (current, previous) = self.keys.split_first()?;
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new(name.to_string(), value.to_string()));
+        if jar.signed(current).get(name).is_some() {
+            return None;
+        }
+
+        let verified = previous.iter().find_map(|k| jar.signed(k).get(name))?;
+        let mut resigned = CookieJar::new();
+        resigned.signed_mut(current).add(verified);
+        resigned.get(name).map(|c| c.value().to_string())
+    }
+}
+
+/// What axum-login stores as `_auth_id` to bind a login to the user's password hash.
+fn session_auth_id(key: &SecretKey, password_hash: &[u8]) -> Option<String> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key.expose()).ok()?;
+    mac.update(password_hash);
+    Some(STANDARD.encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,118 @@
+// Metrics about the server itself, as opposed to the load test results behind `/metrics`.
+use http::Method;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::{sync::Arc, time::Duration};
+
+/// Route label of unmatched requests, so scanners can not blow up the label cardinality.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+/// Method label of requests with any other method, for the same reason.
+pub const OTHER_METHOD: &str = "other";
+
+const STANDARD_METHODS: [Method; 9] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::CONNECT,
+    Method::OPTIONS,
+    Method::TRACE,
+    Method::PATCH,
+];
+
+#[derive(Clone)]
+pub struct ServerMetrics {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_latency: HistogramVec,
+    pool_connections: IntGaugeVec,
+    active_sessions: IntGauge,
+    ingested_samples: IntCounter,
+}
+
+impl ServerMetrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("tonsail".into()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by matched route"),
+            &["method", "route", "status"],
+        )?;
+        let http_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by matched route",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("pool_connections", "Connections of the database pools"),
+            &["pool", "state"],
+        )?;
+        let active_sessions = IntGauge::new("active_sessions", "Sessions stored in Redis")?;
+        let ingested_samples = IntCounter::new(
+            "ingested_samples_total",
+            "Samples this server stored in QuestDB, its rate is the ingestion throughput",
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_latency.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(active_sessions.clone()))?;
+        registry.register(Box::new(ingested_samples.clone()))?;
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                registry,
+                http_requests,
+                http_latency,
+                pool_connections,
+                active_sessions,
+                ingested_samples,
+            }),
+        })
+    }
+
+    pub fn observe_request(&self, method: &Method, route: &str, status: u16, latency: Duration) {
+        let method = match STANDARD_METHODS.contains(method) {
+            true => method.as_str(),
+            false => OTHER_METHOD,
+        };
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.inner.http_requests.with_label_values(&labels).inc();
+        self.inner
+            .http_latency
+            .with_label_values(&labels)
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn set_pool_connections(&self, pool: &str, state: &str, connections: usize) {
+        self.inner
+            .pool_connections
+            .with_label_values(&[pool, state])
+            .set(connections as i64);
+    }
+
+    pub fn set_active_sessions(&self, sessions: usize) {
+        self.inner.active_sessions.set(sessions as i64);
+    }
+
+    pub fn add_ingested_samples(&self, samples: u64) {
+        self.inner.ingested_samples.inc_by(samples);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.inner.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
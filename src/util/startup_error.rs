@@ -0,0 +1,77 @@
+use crate::questdb::migrations::MigrationError;
+use fred::prelude::RedisError;
+use opentelemetry::trace::TraceError;
+use prisma_client_rust::NewClientError;
+use thiserror::Error;
+use tracing::subscriber::SetGlobalDefaultError;
+use url::Url;
+
+/// Everything that can stop `Application::build` or the tracing setup, naming the dependency
+/// at fault.
+#[derive(Debug, Error)]
+pub enum StartupError {
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Could not connect to MySQL through Prisma after {attempts} attempts")]
+    Prisma {
+        attempts: usize,
+        #[source]
+        source: NewClientError,
+    },
+
+    #[error("Could not connect to QuestDB at {url} after {attempts} attempts")]
+    QuestDB {
+        url: String,
+        attempts: usize,
+        #[source]
+        source: sqlx::Error,
+    },
+
+    #[error("Could not migrate the QuestDB schema")]
+    QuestDBMigration(#[source] MigrationError),
+
+    #[error("Could not connect to Redis at {url} after {attempts} attempts")]
+    Redis {
+        url: String,
+        attempts: usize,
+        #[source]
+        source: RedisError,
+    },
+
+    #[error("Could not install the OTLP exporter for {endpoint}")]
+    Otlp {
+        endpoint: String,
+        #[source]
+        source: TraceError,
+    },
+
+    #[error("Could not install the tracing subscriber")]
+    Subscriber(#[from] SetGlobalDefaultError),
+
+    #[error("Could not build the HTTP client")]
+    HttpClient(#[source] reqwest::Error),
+
+    #[error("Could not register server metrics")]
+    Metrics(#[from] prometheus::Error),
+
+    #[error("Could not listen on {address}")]
+    Bind {
+        address: String,
+        #[source]
+        source: hyper::Error,
+    },
+}
+
+/// Drops the password so connection URLs can be logged.
+pub fn redact_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut parsed) => {
+            if parsed.password().is_some() {
+                let _ = parsed.set_password(Some("***"));
+            }
+            parsed.to_string()
+        }
+        Err(_) => "<invalid url>".to_string(),
+    }
+}
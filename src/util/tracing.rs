@@ -1,15 +1,107 @@
-use tracing::subscriber::set_global_default;
+use super::startup_error::StartupError;
+use crate::configuration::{LogFormat, TelemetrySettings};
+use opentelemetry::{
+    global,
+    sdk::{propagation::TraceContextPropagator, trace, Resource},
+    KeyValue,
+};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::WithExportConfig;
+use tracing::{subscriber::set_global_default, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Registry};
 
-pub async fn initialize_tracing() {
+/// Installs the global subscriber, exporting to the OTLP collector when one is set.
+///
+/// Spans of the Prisma query engine and of Redis commands are children of the span that
+/// runs them, so they show up under the request in the trace.
+pub async fn initialize_tracing(settings: &TelemetrySettings) -> Result<(), StartupError> {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
-    let formatting_layer = tracing_subscriber::fmt::layer()
-        .compact()
-        .with_file(false)
-        .with_line_number(false);
+    // Only one of the two is set, `Option<Layer>` is a no-op layer when it is `None`
+    let (compact_layer, json_layer) = match settings.log_format {
+        LogFormat::Compact => (
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .compact()
+                    .with_file(false)
+                    .with_line_number(false),
+            ),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false),
+            ),
+        ),
+    };
 
-    let subscriber = Registry::default().with(env_filter).with(formatting_layer);
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let otel_layer = match &settings.otlp_endpoint {
+        Some(endpoint) => {
+            let mut attributes = vec![KeyValue::new("service.name", settings.service_name.clone())];
+            attributes.extend(
+                settings
+                    .resource_attributes
+                    .iter()
+                    .map(|(k, v)| KeyValue::new(k.clone(), v.clone())),
+            );
 
-    set_global_default(subscriber).expect("Tracing subscriber has been already set");
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(
+                    trace::config()
+                        .with_sampler(trace::Sampler::ParentBased(Box::new(
+                            trace::Sampler::TraceIdRatioBased(settings.sampling_ratio),
+                        )))
+                        .with_resource(Resource::new(attributes)),
+                )
+                .install_batch(opentelemetry::runtime::Tokio)
+                .map_err(|source| StartupError::Otlp {
+                    endpoint: endpoint.clone(),
+                    source,
+                })?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    let subscriber = Registry::default()
+        .with(env_filter)
+        .with(otel_layer)
+        .with(compact_layer)
+        .with(json_layer);
+
+    set_global_default(subscriber)?;
+    Ok(())
+}
+
+/// Flushes the spans that are still waiting in the OTLP batch exporter.
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+/// Continues the trace of an inbound W3C `traceparent` header, if there is one.
+pub fn set_remote_parent(span: &Span, headers: &http::HeaderMap) {
+    let parent = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)));
+    span.set_parent(parent);
+}
+
+/// Adds the `traceparent` of the current span to an outgoing request.
+pub fn inject_trace_context(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    let mut headers = http::HeaderMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|p| {
+        p.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    request.headers(headers)
 }
@@ -0,0 +1,48 @@
+use tonsail_server::{
+    admin::{run, Command},
+    configuration::get_configuration,
+    prisma::UserRole,
+    util::nano_id::generate_id,
+    Application,
+};
+
+use crate::util::{login, seed_database};
+
+#[tokio::test]
+async fn created_users_log_in_with_the_reset_password() {
+    let app = Application::build(get_configuration().unwrap())
+        .await
+        .unwrap();
+    seed_database().await;
+
+    let email = format!("{}@tonsail.dev", generate_id().to_lowercase());
+    let command = Command::CreateUser {
+        email: email.clone(),
+        name: "Ada Lovelace".to_string(),
+        org: "orgid1".to_string(),
+        role: UserRole::Admin,
+        password: "Ad@L0velace".to_string(),
+    };
+    run(command, get_configuration().unwrap()).await.unwrap();
+    login(&app.router, &email, "Ad@L0velace").await;
+
+    let command = Command::ResetPassword {
+        email: email.clone(),
+        password: "N3w-Ad@L0velace".to_string(),
+    };
+    run(command, get_configuration().unwrap()).await.unwrap();
+    login(&app.router, &email, "N3w-Ad@L0velace").await;
+}
+
+#[tokio::test]
+async fn rejects_a_weak_password_before_connecting() {
+    let command = Command::CreateUser {
+        email: "weak@tonsail.dev".to_string(),
+        name: "Weak Password".to_string(),
+        org: "orgid1".to_string(),
+        role: UserRole::Member,
+        password: "password".to_string(),
+    };
+
+    assert!(run(command, get_configuration().unwrap()).await.is_err());
+}
@@ -0,0 +1,36 @@
+use http::{Request, StatusCode};
+use hyper::Body;
+use prisma_client_rust::serde_json::{self, Value};
+use tonsail_server::{configuration::get_configuration, Application};
+use tower::ServiceExt;
+
+use crate::util::{login, seed_database};
+
+#[tokio::test]
+async fn login_is_recorded_in_audit_log() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+
+    let response = app
+        .router
+        .oneshot(
+            Request::builder()
+                .header(http::header::COOKIE, cookie)
+                .uri("/v1/organizations/orgid1/audit?action=user.login&actor_id=userid1&limit=1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let page: Value = serde_json::from_slice(&body).unwrap();
+    let items = page["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["action"], "user.login");
+    assert_eq!(items[0]["targetId"], "userid1");
+}
@@ -33,7 +33,7 @@ async fn returns_200_with_user_when_registration_is_succesfull() {
                     http::header::CONTENT_TYPE,
                     "application/x-www-form-urlencoded",
                 )
-                .uri("/register")
+                .uri("/v1/register")
                 .body(body)
                 .unwrap(),
         )
@@ -69,7 +69,7 @@ async fn returns_400_when_user_already_exists() {
                     http::header::CONTENT_TYPE,
                     "application/x-www-form-urlencoded",
                 )
-                .uri("/register")
+                .uri("/v1/register")
                 .body(body)
                 .unwrap(),
         )
@@ -0,0 +1,169 @@
+use http::StatusCode;
+use prisma_client_rust::serde_json::{json, Value};
+use tonsail_server::{
+    configuration::get_configuration,
+    domain::webhook::delivery_body,
+    prisma::{webhook_event, PrismaClient},
+    Application,
+};
+
+use crate::util::{login, seed_database, send, send_json, start_run};
+
+/// Sends 40 response times per URL, alternating between `value - 10` and `value + 10`.
+async fn ingest(app: &Application, cookie: &str, run_id: &str, urls: &[(&str, f64)]) {
+    let samples: Vec<Value> = urls
+        .iter()
+        .flat_map(|(url, value)| {
+            (0..40).map(move |i| {
+                json!({
+                    "name": "http_response_rate",
+                    "scenario": "browse",
+                    "url": url,
+                    "method": "GET",
+                    "status": "200",
+                    "ts": format!("2023-03-15T00:00:{i:02}Z"),
+                    "value": if i % 2 == 0 { value - 10.0 } else { value + 10.0 }
+                })
+            })
+        })
+        .collect();
+    let uri = format!("/v1/runs/{run_id}/samples");
+    let (status, _) = send_json(app, cookie, &uri, &json!({ "samples": samples })).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+async fn finish(app: &Application, cookie: &str, run_id: &str) -> Value {
+    let uri = format!("/v1/runs/{run_id}/status");
+    let (status, run) = send(app, cookie, "PUT", &uri, Some("status=FINISHED")).await;
+    assert_eq!(status, StatusCode::OK);
+    run
+}
+
+#[tokio::test]
+async fn runs_slower_than_the_baseline_are_flagged() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let baseline_id = start_run(&app, &cookie, "Baselined").await;
+    let uri = format!("/v1/runs/{baseline_id}");
+    let (_, run) = send(&app, &cookie, "GET", &uri, None).await;
+    let test_id = run["testId"].as_str().unwrap().to_string();
+    let baseline_uri = format!("/v1/tests/{test_id}/baseline");
+
+    let form = format!("runId={baseline_id}");
+    let (status, _) = send(&app, &cookie, "PUT", &baseline_uri, Some(&form)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let urls = [
+        ("https://shop.test/", 100.0),
+        ("https://shop.test/cart", 100.0),
+    ];
+    ingest(&app, &cookie, &baseline_id, &urls).await;
+    finish(&app, &cookie, &baseline_id).await;
+    let form = format!("runId={baseline_id}&tolerancePct=20");
+    let (status, test) = send(&app, &cookie, "PUT", &baseline_uri, Some(&form)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(test["baselineRunId"], baseline_id.as_str());
+    assert_eq!(test["regressionTolerancePct"], 20.0);
+
+    let form = format!("test_id={test_id}");
+    let (_, run) = send(&app, &cookie, "POST", "/v1/runs/new", Some(&form)).await;
+    let run_id = run["id"].as_str().unwrap().to_string();
+    let uri = format!("/v1/runs/{run_id}/status");
+    send(&app, &cookie, "PUT", &uri, Some("status=STARTED")).await;
+    // Only the home page got slower, the cart stays within the tolerance
+    let urls = [
+        ("https://shop.test/", 300.0),
+        ("https://shop.test/cart", 110.0),
+    ];
+    ingest(&app, &cookie, &run_id, &urls).await;
+    let run = finish(&app, &cookie, &run_id).await;
+
+    let regressions = run["regressions"].as_array().unwrap();
+    assert!(regressions
+        .iter()
+        .all(|r| r["metric"] == "http_response_rate"));
+    let mut urls: Vec<&Value> = regressions.iter().map(|r| &r["url"]).collect();
+    urls.sort_by_key(|url| url.to_string());
+    assert_eq!(urls, [&json!("https://shop.test/"), &Value::Null]);
+    let home = regressions
+        .iter()
+        .find(|r| r["url"] == "https://shop.test/")
+        .unwrap();
+    assert_eq!(home["baselineMean"], 100.0);
+    assert_eq!(home["changePct"], 200.0);
+    assert!(home["pValue"].as_f64().unwrap() < 0.05);
+
+    let uri = format!("/v1/runs/{run_id}");
+    let (_, run) = send(&app, &cookie, "GET", &uri, None).await;
+    assert_eq!(run["regressions"].as_array().unwrap().len(), 2);
+
+    let (status, _) = send(&app, &cookie, "DELETE", &baseline_uri, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let uri = format!("/v1/tests/{test_id}");
+    let (_, test) = send(&app, &cookie, "GET", &uri, None).await;
+    assert_eq!(test["baselineRunId"], Value::Null);
+}
+
+#[tokio::test]
+async fn regressions_queue_a_webhook_event() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let baseline_id = start_run(&app, &cookie, "Hooked").await;
+    ingest(
+        &app,
+        &cookie,
+        &baseline_id,
+        &[("https://shop.test/", 100.0)],
+    )
+    .await;
+    let baseline = finish(&app, &cookie, &baseline_id).await;
+    let test_id = baseline["testId"].as_str().unwrap().to_string();
+    let uri = format!("/v1/tests/{test_id}/baseline");
+    send(
+        &app,
+        &cookie,
+        "PUT",
+        &uri,
+        Some(&format!("runId={baseline_id}")),
+    )
+    .await;
+
+    let form = format!("test_id={test_id}");
+    let (_, run) = send(&app, &cookie, "POST", "/v1/runs/new", Some(&form)).await;
+    let run_id = run["id"].as_str().unwrap().to_string();
+    let uri = format!("/v1/runs/{run_id}/status");
+    send(&app, &cookie, "PUT", &uri, Some("status=STARTED")).await;
+    ingest(&app, &cookie, &run_id, &[("https://shop.test/", 300.0)]).await;
+    finish(&app, &cookie, &run_id).await;
+
+    let client = PrismaClient::_builder().build().await.unwrap();
+    let events = client
+        .webhook_event()
+        .find_many(vec![
+            webhook_event::organization_id::equals("orgid1".to_string()),
+            webhook_event::event::equals("run.regressed".to_string()),
+        ])
+        .exec()
+        .await
+        .unwrap();
+    let body = events
+        .iter()
+        .map(delivery_body)
+        .find(|body| body["data"]["runId"] == run_id.as_str())
+        .unwrap();
+
+    assert_eq!(body["type"], "run.regressed");
+    assert_eq!(body["organizationId"], "orgid1");
+    assert!(body["createdAt"].is_string());
+    assert_eq!(body["data"]["testId"], test_id.as_str());
+    assert_eq!(body["data"]["baselineRunId"], baseline_id.as_str());
+    let regressions = body["data"]["regressions"].as_array().unwrap();
+    assert_eq!(regressions.len(), 2);
+    assert!(regressions.iter().all(|r| r["runId"] == run_id.as_str()));
+}
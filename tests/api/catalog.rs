@@ -0,0 +1,128 @@
+use http::StatusCode;
+use prisma_client_rust::serde_json::json;
+use tonsail_server::{
+    admin::{self, Command},
+    configuration::get_configuration,
+    prisma::UserRole,
+    util::nano_id::generate_id,
+    Application,
+};
+
+use crate::util::{login, register, seed_database, send, send_json};
+
+#[tokio::test]
+async fn admins_manage_custom_metrics() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+
+    let value = format!("checkouts_{}", generate_id());
+    let form = format!("label=Checkouts&value={value}&group=Business&unit=count");
+    let (status, entry) = send(&app, &cookie, "POST", "/v1/metrics/catalog", Some(&form)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(entry["organizationId"], "orgid1");
+
+    let (status, _) = send(&app, &cookie, "POST", "/v1/metrics/catalog", Some(&form)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let builtin = "label=VUs&value=vus&group=Business&unit=count";
+    let (status, _) = send(&app, &cookie, "POST", "/v1/metrics/catalog", Some(builtin)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let invalid = "label=Bad&value=1-bad&group=Business&unit=count";
+    let (status, _) = send(&app, &cookie, "POST", "/v1/metrics/catalog", Some(invalid)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, catalog) = send(&app, &cookie, "GET", "/v1/metrics/catalog", None).await;
+    let values: Vec<_> = catalog
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| &e["value"])
+        .collect();
+    assert!(values.contains(&&json!(value)));
+    assert!(values.contains(&&json!("vus")));
+
+    let uri = format!("/v1/metrics/catalog/{}", entry["id"]);
+    let form = format!("label=Orders&value={value}&group=Business&unit=count");
+    let (status, entry) = send(&app, &cookie, "PUT", &uri, Some(&form)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(entry["label"], "Orders");
+
+    let (status, _) = send(&app, &cookie, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, &cookie, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn ingestion_follows_the_unknown_metrics_policy() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    // A fresh organization, flipping its policy does not affect other tests
+    let (cookie, org_id) = register(&app).await;
+    let form = format!("name=Ingested&organization_id={org_id}");
+    let (_, project) = send(&app, &cookie, "POST", "/v1/projects", Some(&form)).await;
+    let form = format!(
+        "name=Ingested&project_id={}",
+        project["id"].as_str().unwrap()
+    );
+    let (_, test) = send(&app, &cookie, "POST", "/v1/tests", Some(&form)).await;
+    let form = format!("test_id={}", test["id"].as_str().unwrap());
+    let (_, run) = send(&app, &cookie, "POST", "/v1/runs/new", Some(&form)).await;
+    let run_id = run["id"].as_str().unwrap();
+    let samples_uri = format!("/v1/runs/{run_id}/samples");
+
+    let sample = |name: &str| json!({ "samples": [{ "name": name, "ts": "2023-03-15T00:00:00Z", "value": 1.0 }] });
+    let (status, _) = send_json(&app, &cookie, &samples_uri, &sample("vus")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let status_uri = format!("/v1/runs/{run_id}/status");
+    send(&app, &cookie, "PUT", &status_uri, Some("status=STARTED")).await;
+
+    // Only the organization of the run sends it samples
+    seed_database().await;
+    let stranger = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let (status, _) = send_json(&app, &stranger, &samples_uri, &sample("vus")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Nor does anyone outside its owners and admins set its policy
+    let org_uri = format!("/v1/organizations/{org_id}");
+    let form = "name=Ingested&unknownMetrics=REJECT";
+    let (status, _) = send(&app, &stranger, "PUT", &org_uri, Some(form)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let email = format!("{}@tonsail.test", generate_id().to_lowercase());
+    let command = Command::CreateUser {
+        email: email.clone(),
+        name: "Ada Lovelace".to_string(),
+        org: org_id.clone(),
+        role: UserRole::Member,
+        password: "Ad@L0velace".to_string(),
+    };
+    admin::run(command, get_configuration().unwrap())
+        .await
+        .unwrap();
+    let member = login(&app.router, &email, "Ad@L0velace").await;
+    let (status, _) = send(&app, &member, "PUT", &org_uri, Some(form)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send_json(&app, &cookie, &samples_uri, &sample("mystery")).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _) = send(&app, &cookie, "PUT", &org_uri, Some(form)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send_json(&app, &cookie, &samples_uri, &sample("mystery")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send_json(&app, &cookie, &samples_uri, &sample("vus")).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let quoted = json!({ "samples": [
+        { "name": "vus", "url": "https://shop.test/?q=it's", "ts": "2023-03-15T00:00:01Z", "value": 2.0 }
+    ]});
+    let (status, _) = send_json(&app, &cookie, &samples_uri, &quoted).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let form = "name=Ingested&unknownMetrics=TAG";
+    send(&app, &cookie, "PUT", &org_uri, Some(form)).await;
+    let (status, _) = send_json(&app, &cookie, &samples_uri, &sample("mystery")).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,54 @@
+use http::{header, Method, Request, StatusCode};
+use hyper::Body;
+use tonsail_server::{configuration::get_configuration, Application};
+use tower::ServiceExt;
+
+async fn preflight(app: &Application, origin: &str) -> http::Response<axum::body::BoxBody> {
+    app.router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/v1/me")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn allowed_origins_come_from_settings() {
+    let mut config = get_configuration().unwrap();
+    config.cors.allowed_origins = vec![
+        "https://tonsail.dev".into(),
+        "https://*.preview.tonsail.dev".into(),
+    ];
+    let app = Application::build(config).await.unwrap();
+
+    for origin in ["https://tonsail.dev", "https://pr-42.preview.tonsail.dev"] {
+        let response = preflight(&app, origin).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            origin
+        );
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS],
+            "true"
+        );
+    }
+
+    for origin in [
+        "https://evil.dev",
+        "https://preview.tonsail.dev",
+        "http://pr-42.preview.tonsail.dev",
+    ] {
+        let response = preflight(&app, origin).await;
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
@@ -0,0 +1,222 @@
+use http::{header, Request, StatusCode};
+use hyper::Body;
+use prisma_client_rust::serde_json::{self, json, Value};
+use std::time::Duration;
+use tonsail_server::{configuration::get_configuration, util::nano_id::generate_id, Application};
+use tower::ServiceExt;
+
+use crate::util::{login, seed_database, send, send_json, start_run};
+
+#[tokio::test]
+async fn user_can_download_export_without_password_hash() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+
+    let response = app
+        .router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .header(http::header::COOKIE, cookie.clone())
+                .uri("/v1/users/userid1/exports")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let export: Value = serde_json::from_slice(&body).unwrap();
+    let uri = format!(
+        "/v1/users/userid1/exports/{}",
+        export["id"].as_str().unwrap()
+    );
+
+    let mut download_url = None;
+    for _ in 0..50 {
+        let response = app
+            .router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .header(http::header::COOKIE, cookie.clone())
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let export: Value = serde_json::from_slice(&body).unwrap();
+        if let Some(url) = export["downloadUrl"].as_str() {
+            download_url = Some(url.to_string());
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let response = app
+        .router
+        .oneshot(
+            Request::builder()
+                .uri(download_url.expect("Export did not finish in time"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let archive: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(archive["user"]["email"], "graham@bell.com");
+    assert!(archive["user"].get("password").is_none());
+}
+
+/// Starts a run with three samples and finishes it, returning its id.
+async fn finished_run(app: &Application, cookie: &str) -> String {
+    let run_id = start_run(app, cookie, "Exported").await;
+    let samples = json!({ "samples": [
+        { "name": "vus", "ts": "2023-03-15T00:00:00Z", "value": 1.0 },
+        { "name": "vus", "ts": "2023-03-15T00:00:01Z", "value": 2.0 },
+        {
+            "name": "http_request_rate",
+            "scenario": "a, b",
+            "ts": "2023-03-15T00:00:01Z",
+            "value": 3.5
+        }
+    ]});
+    let (status, _) = send_json(app, cookie, &format!("/v1/runs/{run_id}/samples"), &samples).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let uri = format!("/v1/runs/{run_id}/status");
+    send(app, cookie, "PUT", &uri, Some("status=FINISHED")).await;
+    run_id
+}
+
+async fn get(app: &Application, cookie: &str, uri: &str, gzip: bool) -> http::Response<Body> {
+    let mut request = Request::builder().uri(uri).header(header::COOKIE, cookie);
+    if gzip {
+        request = request.header(header::ACCEPT_ENCODING, "gzip");
+    }
+    app.router
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn run_samples_are_streamed_as_csv_or_json_lines() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = finished_run(&app, &cookie).await;
+
+    let response = get(
+        &app,
+        &cookie,
+        &format!("/v1/runs/{run_id}/export?format=csv"),
+        false,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv");
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        format!("attachment; filename=\"run-{run_id}.csv\"").as_str()
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let csv = String::from_utf8(body.to_vec()).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines[0], "name,runID,scenario,url,method,status,ts,value");
+    assert_eq!(lines.len(), 4);
+    assert!(csv.contains("\"a, b\""));
+
+    let uri = format!("/v1/runs/{run_id}/export?format=jsonl&name=vus&to=2023-03-15T00:00:01Z");
+    let response = get(&app, &cookie, &uri, false).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let rows: Vec<Value> = body
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["value"], 1.0);
+
+    let response = get(
+        &app,
+        &cookie,
+        &format!("/v1/runs/{run_id}/export?format=csv"),
+        true,
+    )
+    .await;
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+    let response = get(
+        &app,
+        &cookie,
+        &format!("/v1/runs/{run_id}/export?format=xml"),
+        false,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = get(
+        &app,
+        &cookie,
+        "/v1/runs/nosuchrun000/export?format=csv",
+        false,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn large_run_exports_are_prepared_in_the_background() {
+    let mut config = get_configuration().unwrap();
+    config.exports.max_streamed_rows = 0;
+    config.exports.directory = std::env::temp_dir()
+        .join(format!("tonsail-exports-{}", generate_id()))
+        .to_string_lossy()
+        .into_owned();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = finished_run(&app, &cookie).await;
+
+    let uri = format!("/v1/runs/{run_id}/export?format=parquet");
+    let response = get(&app, &cookie, &uri, false).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let export: Value = serde_json::from_slice(&body).unwrap();
+    let uri = format!(
+        "/v1/users/userid1/exports/{}",
+        export["id"].as_str().unwrap()
+    );
+
+    let mut download_url = None;
+    for _ in 0..50 {
+        let (_, export) = send(&app, &cookie, "GET", &uri, None).await;
+        if let Some(url) = export["downloadUrl"].as_str() {
+            download_url = Some(url.to_string());
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let url = download_url.expect("Export did not finish in time");
+    let response = get(&app, &cookie, &url, false).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/vnd.apache.parquet"
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(body.starts_with(b"PAR1") && body.ends_with(b"PAR1"));
+}
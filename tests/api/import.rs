@@ -0,0 +1,133 @@
+use http::{header, Request, StatusCode};
+use hyper::Body;
+use prisma_client_rust::serde_json::{json, Value};
+use tonsail_server::{configuration::get_configuration, Application};
+use tower::ServiceExt;
+
+use crate::util::{create_test, login, seed_database};
+
+async fn upload(app: &Application, cookie: &str, uri: &str, file: &str) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header(header::COOKIE, cookie)
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .body(Body::from(file.to_string()))
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn k6_json_output_becomes_a_finished_run() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let test_id = create_test(&app, &cookie, "orgid1", "K6Import").await;
+
+    let point = |metric: &str, time: &str, value: f64, tags: Value| {
+        json!({
+            "type": "Point",
+            "metric": metric,
+            "data": { "time": time, "value": value, "tags": tags }
+        })
+    };
+    let tags = json!({
+        "scenario": "default",
+        "url": "https://shop.test/",
+        "method": "GET",
+        "status": "200"
+    });
+    let lines = [
+        json!({ "type": "Metric", "metric": "http_req_duration", "data": { "type": "trend" } }),
+        point(
+            "http_req_duration",
+            "2023-03-15T00:00:00Z",
+            120.5,
+            tags.clone(),
+        ),
+        point("http_req_failed", "2023-03-15T00:00:05Z", 0.0, tags),
+        point("vus", "2023-03-15T00:00:10Z", 7.0, json!({})),
+    ];
+    let file = lines.map(|l| l.to_string()).join("\n");
+
+    let uri = format!("/v1/tests/{test_id}/imports?format=k6");
+    let (status, run) = upload(&app, &cookie, &uri, &file).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run["status"], "FINISHED");
+    assert_eq!(run["samples"], 3);
+    assert_eq!(run["vus"], 7);
+    assert_eq!(run["durationSecs"], 10);
+
+    let (status, _) = upload(&app, &cookie, &uri, "{not json").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The free plan allows 50 virtual users and runs of 15 minutes
+    let crowded = point("vus", "2023-03-15T00:00:10Z", 500.0, json!({})).to_string();
+    let (status, _) = upload(&app, &cookie, &uri, &crowded).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    let long = [
+        point("vus", "2023-03-15T00:00:00Z", 1.0, json!({})),
+        point("vus", "2023-03-15T01:00:00Z", 1.0, json!({})),
+    ]
+    .map(|l| l.to_string())
+    .join("\n");
+    let (status, _) = upload(&app, &cookie, &uri, &long).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+}
+
+#[tokio::test]
+async fn jmeter_results_are_imported_from_csv_and_xml() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let test_id = create_test(&app, &cookie, "orgid1", "JtlImport").await;
+    let uri = format!("/v1/tests/{test_id}/imports?format=jtl");
+
+    let csv = "timeStamp,elapsed,label,responseCode,success,allThreads,URL\n\
+               1678838400000,150,Home,200,true,4,https://shop.test/\n\
+               1678838402000,900,\"Pay, then confirm\",503,false,5,https://shop.test/pay\n";
+    let (status, run) = upload(&app, &cookie, &uri, csv).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run["status"], "FINISHED");
+    assert_eq!(run["samples"], 8);
+    assert_eq!(run["vus"], 5);
+
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testResults version="1.2">
+<httpSample t="210" lt="180" ts="1678838400000" s="true" lb="Home" rc="200" na="2">
+  <method class="java.lang.String">GET</method>
+  <java.net.URL>https://shop.test/</java.net.URL>
+</httpSample>
+</testResults>"#;
+    let (status, run) = upload(&app, &cookie, &uri, xml).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run["samples"], 5);
+
+    // A redirect nests its follow-up requests, which are already part of its time
+    let nested = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testResults version="1.2">
+<httpSample t="300" lt="120" ts="1678838400000" s="true" lb="Login" rc="200" na="2">
+  <httpSample t="100" lt="90" ts="1678838400000" s="true" lb="Login-0" rc="302" na="2"/>
+  <httpSample t="200" lt="30" ts="1678838400100" s="true" lb="Login-1" rc="200" na="2">
+    <java.net.URL>https://shop.test/home</java.net.URL>
+  </httpSample>
+  <java.net.URL>https://shop.test/login</java.net.URL>
+</httpSample>
+</testResults>"#;
+    let (status, run) = upload(&app, &cookie, &uri, nested).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run["samples"], 5);
+
+    let (status, _) = upload(&app, &cookie, &uri, "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let uri = format!("/v1/tests/{test_id}/imports?format=gatling");
+    let (status, _) = upload(&app, &cookie, &uri, csv).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -1,2 +1,23 @@
+mod admin;
+mod audit;
 mod auth;
+mod baseline;
+mod catalog;
+mod cors;
+mod deletion;
+mod export;
+mod import;
+mod openapi;
+mod pagination;
+mod rate_limit;
+mod report;
+mod retention;
+mod secrets;
+mod server_metrics;
+mod share;
+mod sso;
+mod startup;
+mod telemetry;
+mod usage;
 mod util;
+mod versioning;
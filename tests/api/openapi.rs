@@ -0,0 +1,103 @@
+use http::{Method, Request, StatusCode};
+use hyper::Body;
+use std::collections::BTreeSet;
+use tonsail_server::{configuration::get_configuration, routes::openapi::ApiDoc, Application};
+use tower::ServiceExt;
+use utoipa::openapi::PathItemType;
+use utoipa::OpenApi;
+
+/// Paths passed to `.route(..)` in a router source, in OpenAPI notation.
+fn routed_paths(source: &str, prefix: &str) -> BTreeSet<String> {
+    source
+        .split(".route(")
+        .skip(1)
+        .filter_map(|rest| rest.trim_start().strip_prefix('"'))
+        .filter_map(|rest| rest.split('"').next())
+        .map(|path| {
+            format!("{prefix}{path}")
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(param) => format!("{{{param}}}"),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .collect()
+}
+
+fn method_of(item: &PathItemType) -> Method {
+    match item {
+        PathItemType::Get => Method::GET,
+        PathItemType::Post => Method::POST,
+        PathItemType::Put => Method::PUT,
+        PathItemType::Delete => Method::DELETE,
+        PathItemType::Options => Method::OPTIONS,
+        PathItemType::Head => Method::HEAD,
+        PathItemType::Patch => Method::PATCH,
+        PathItemType::Trace => Method::TRACE,
+        PathItemType::Connect => Method::CONNECT,
+    }
+}
+
+#[test]
+fn spec_documents_every_route() {
+    let source = include_str!("../../src/routes/mod.rs");
+    let (root, v1) = source.split_once("fn v1_routes()").unwrap();
+    let mut routed = routed_paths(root, "");
+    routed.extend(routed_paths(v1, "/v1"));
+
+    let documented: BTreeSet<String> = ApiDoc::openapi().paths.paths.into_keys().collect();
+    assert_eq!(documented, routed);
+}
+
+#[tokio::test]
+async fn every_documented_operation_is_routed() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    for (path, item) in ApiDoc::openapi().paths.paths {
+        let uri = path.replace(['{', '}'], "");
+        for operation in item.operations.keys() {
+            let method = method_of(operation);
+            let response = app
+                .router
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(method.clone())
+                        .uri(&uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            // Unmatched routes get axum's empty 404, handlers always explain theirs
+            let unrouted = status == StatusCode::METHOD_NOT_ALLOWED
+                || (status == StatusCode::NOT_FOUND && body.is_empty());
+            assert!(!unrouted, "{method} {path} is documented but not routed");
+        }
+    }
+}
+
+#[tokio::test]
+async fn serves_the_spec() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    let response = app
+        .router
+        .oneshot(
+            Request::builder()
+                .uri("/openapi.json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
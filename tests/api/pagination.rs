@@ -0,0 +1,92 @@
+use http::{Request, StatusCode};
+use hyper::Body;
+use prisma_client_rust::serde_json::{self, Value};
+use tonsail_server::{configuration::get_configuration, Application};
+use tower::ServiceExt;
+
+use crate::util::{login, seed_database};
+
+async fn get_json(app: &Application, cookie: &str, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .header(http::header::COOKIE, cookie)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn project_list_pages_through_cursor() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+
+    for name in ["Paged A", "Paged B", "Paged C"] {
+        let response = app
+            .router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .header(http::header::COOKIE, cookie.clone())
+                    .header(
+                        http::header::CONTENT_TYPE,
+                        "application/x-www-form-urlencoded",
+                    )
+                    .uri("/v1/projects")
+                    .body(Body::from(format!(
+                        "name={}&organization_id=orgid1",
+                        name.replace(' ', "+")
+                    )))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let base = "/v1/organizations/orgid1/projects?name=Paged&sort=name&order=asc&limit=2";
+    let (status, first) = get_json(&app, &cookie, base).await;
+    assert_eq!(status, StatusCode::OK);
+    let names: Vec<_> = first["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].clone())
+        .collect();
+    assert_eq!(names, vec!["Paged A", "Paged B"]);
+
+    let cursor = first["nextCursor"].as_str().unwrap();
+    let (_, second) = get_json(&app, &cookie, &format!("{base}&cursor={cursor}")).await;
+    let names: Vec<_> = second["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].clone())
+        .collect();
+    assert_eq!(names, vec!["Paged C"]);
+    assert!(second["nextCursor"].is_null());
+}
+
+#[tokio::test]
+async fn rejects_out_of_range_limit() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+
+    let (status, _) = get_json(&app, &cookie, "/v1/organizations?limit=1000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
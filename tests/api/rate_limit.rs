@@ -0,0 +1,139 @@
+use axum::extract::ConnectInfo;
+use http::{header, Request, StatusCode};
+use hyper::Body;
+use rand::Rng;
+use std::net::SocketAddr;
+use tonsail_server::{
+    configuration::{get_configuration, Settings},
+    Application,
+};
+use tower::ServiceExt;
+
+const PROXY: &str = "127.0.0.1";
+
+/// Logs in through the trusted proxy, on behalf of `ip`.
+async fn login(app: &Application, ip: &str) -> http::Response<axum::body::BoxBody> {
+    login_from(app, PROXY, ip).await
+}
+
+async fn login_from(
+    app: &Application,
+    peer: &str,
+    forwarded_for: &str,
+) -> http::Response<axum::body::BoxBody> {
+    login_request(app, peer, forwarded_for, Request::builder()).await
+}
+
+async fn login_request(
+    app: &Application,
+    peer: &str,
+    forwarded_for: &str,
+    request: http::request::Builder,
+) -> http::Response<axum::body::BoxBody> {
+    let peer = SocketAddr::new(peer.parse().unwrap(), 40000);
+    app.router
+        .clone()
+        .oneshot(
+            request
+                .method("POST")
+                .uri("/v1/login")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header("x-forwarded-for", forwarded_for)
+                .extension(ConnectInfo(peer))
+                .body(Body::from("email=nobody%40tonsail.dev&password=wrong"))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+fn limited_config() -> Settings {
+    let mut config = get_configuration().unwrap();
+    config.rate_limit.enabled = true;
+    config.rate_limit.auth.capacity = 2;
+    config.rate_limit.auth.refill_per_minute = 1;
+    config.application.trusted_proxies = vec![PROXY.parse().unwrap()];
+    config
+}
+
+/// A fresh address per run, the buckets outlive the test.
+fn random_ip() -> String {
+    let [a, b, c] = rand::thread_rng().gen::<[u8; 3]>();
+    format!("10.{a}.{b}.{c}")
+}
+
+#[tokio::test]
+async fn returns_429_once_the_bucket_is_empty() {
+    let app = Application::build(limited_config()).await.unwrap();
+    let ip = random_ip();
+
+    for remaining in ["1", "0"] {
+        let response = login(&app, &ip).await;
+        assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["ratelimit-limit"], "2");
+        assert_eq!(response.headers()["ratelimit-remaining"], remaining);
+    }
+
+    let response = login(&app, &ip).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+    // Other clients have their own bucket
+    let response = login(&app, &random_ip()).await;
+    assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn forwarded_addresses_are_only_believed_from_trusted_proxies() {
+    let app = Application::build(limited_config()).await.unwrap();
+    let peer = random_ip();
+
+    // Spoofing a new address per request does not reset the bucket of the peer
+    for _ in 0..2 {
+        let response = login_from(&app, &peer, &random_ip()).await;
+        assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+    let response = login_from(&app, &peer, &random_ip()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Behind the proxy, the right-most untrusted hop is the client
+    let client = random_ip();
+    for _ in 0..2 {
+        let forwarded = format!("{}, {client}", random_ip());
+        let response = login(&app, &forwarded).await;
+        assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+    let response = login(&app, &format!("{}, {client}", random_ip())).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn made_up_bearer_tokens_share_the_bucket_of_their_address() {
+    let app = Application::build(limited_config()).await.unwrap();
+    let ip = random_ip();
+
+    for i in 0..3 {
+        let bearer = Request::builder().header(header::AUTHORIZATION, format!("Bearer {i}"));
+        let response = login_request(&app, PROXY, &ip, bearer).await;
+        assert_eq!(response.status() == StatusCode::TOO_MANY_REQUESTS, i == 2);
+    }
+}
+
+#[tokio::test]
+async fn unlimited_routes_have_no_rate_limit_headers() {
+    let mut config = get_configuration().unwrap();
+    config.rate_limit.enabled = true;
+    let app = Application::build(config).await.unwrap();
+
+    let response = app
+        .router
+        .oneshot(
+            Request::builder()
+                .uri("/v1/me")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key("ratelimit-limit"));
+}
@@ -0,0 +1,184 @@
+use http::{header, Request, StatusCode};
+use hyper::Body;
+use prisma_client_rust::serde_json::json;
+use tonsail_server::{
+    configuration::get_configuration,
+    domain::webhook::delivery_body,
+    prisma::{webhook_event, PrismaClient},
+    Application,
+};
+use tower::ServiceExt;
+
+use crate::util::{login, seed_database, send, send_json, start_run};
+
+async fn report(app: &Application, cookie: &str, uri: &str) -> (StatusCode, String, String) {
+    let request = Request::builder()
+        .uri(uri)
+        .header(header::COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|v| v.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (
+        status,
+        content_type,
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn finished_runs_are_reported_as_junit_and_html() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = start_run(&app, &cookie, "Reported").await;
+
+    let junit_uri = format!("/v1/runs/{run_id}/report?format=junit");
+    let (status, _, _) = report(&app, &cookie, &junit_uri).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let sample = |url: &str, status: &str, ts: &str, value: f64| {
+        json!({
+            "name": "http_response_rate",
+            "scenario": "checkout",
+            "url": url,
+            "method": "POST",
+            "status": status,
+            "ts": ts,
+            "value": value
+        })
+    };
+    let samples = json!({ "samples": [
+        sample("https://shop.test/cart", "200", "2023-03-15T00:00:00Z", 120.0),
+        sample("https://shop.test/cart", "200", "2023-03-15T00:00:01Z", 80.0),
+        sample("https://shop.test/pay", "200", "2023-03-15T00:00:00Z", 300.0),
+        sample("https://shop.test/pay", "503", "2023-03-15T00:00:02Z", 900.0)
+    ]});
+    let uri = format!("/v1/runs/{run_id}/samples");
+    let (status, _) = send_json(&app, &cookie, &uri, &samples).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let uri = format!("/v1/runs/{run_id}/status");
+    send(&app, &cookie, "PUT", &uri, Some("status=FINISHED")).await;
+
+    let (status, content_type, xml) = report(&app, &cookie, &junit_uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/xml");
+    assert!(xml.contains("tests=\"3\" failures=\"1\""));
+    let passed = "<testcase classname=\"checkout\" name=\"POST https://shop.test/cart\"/>";
+    assert!(xml.contains(passed));
+    assert!(xml.contains("message=\"1 of 2 samples had error statuses\">503: 1</failure>"));
+    assert!(xml.contains("<property name=\"http_response_rate.max\" value=\"900.000\"/>"));
+
+    let uri = format!("/v1/runs/{run_id}/report?format=html");
+    let (status, content_type, html) = report(&app, &cookie, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type.starts_with("text/html"));
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<svg"));
+    assert!(html.contains("https://shop.test/pay"));
+
+    let uri = format!("/v1/runs/{run_id}/report?format=pdf");
+    let (status, _, _) = report(&app, &cookie, &uri).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn thresholds_and_checks_are_reported_as_testcases() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = start_run(&app, &cookie, "Checked").await;
+    let checks_uri = format!("/v1/runs/{run_id}/checks");
+
+    let checks = json!({ "checks": [
+        { "kind": "CHECK", "name": "status is 200", "passes": 10, "fails": 0 },
+        { "kind": "THRESHOLD", "name": "http_req_duration: p(95)<500", "passes": 1, "fails": 0 }
+    ]});
+    let (status, _) = send_json(&app, &cookie, &checks_uri, &checks).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let uri = format!("/v1/runs/{run_id}/status");
+    send(&app, &cookie, "PUT", &uri, Some("status=FINISHED")).await;
+
+    // The final outcomes replace the earlier ones
+    let checks = json!({ "checks": [
+        { "kind": "CHECK", "name": "status is 200", "passes": 18, "fails": 2 },
+        { "kind": "THRESHOLD", "name": "http_req_duration: p(95)<500", "passes": 0, "fails": 1 },
+        { "kind": "THRESHOLD", "name": "checks: rate>0.5", "passes": 1, "fails": 0 }
+    ]});
+    let (status, _) = send_json(&app, &cookie, &checks_uri, &checks).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (_, run) = send(&app, &cookie, "GET", &format!("/v1/runs/{run_id}"), None).await;
+    assert_eq!(run["checks"].as_array().unwrap().len(), 3);
+
+    let uri = format!("/v1/runs/{run_id}/report?format=junit");
+    let (status, _, xml) = report(&app, &cookie, &uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(xml.contains("tests=\"4\" failures=\"2\""));
+    assert!(xml.contains("<testcase classname=\"thresholds\" name=\"checks: rate&gt;0.5\"/>"));
+    assert!(xml.contains(
+        "<testcase classname=\"thresholds\" name=\"http_req_duration: p(95)&lt;500\">\n      \
+         <failure type=\"threshold\" message=\"The threshold was crossed\"/>"
+    ));
+    assert!(xml.contains("<failure type=\"check\" message=\"2 of 20 checks failed\"/>"));
+
+    let uri = format!("/v1/runs/{run_id}/report?format=html");
+    let (_, _, html) = report(&app, &cookie, &uri).await;
+    assert!(html.contains("<h2>Thresholds and checks</h2>"));
+
+    let checks = json!({ "checks": [{ "kind": "CHECK", "name": "", "passes": 1, "fails": 0 }] });
+    let (status, _) = send_json(&app, &cookie, &checks_uri, &checks).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn finished_runs_queue_their_reports_as_attachments() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = start_run(&app, &cookie, "Attached").await;
+    let uri = format!("/v1/runs/{run_id}/status");
+    send(&app, &cookie, "PUT", &uri, Some("status=ABORTED")).await;
+
+    let client = PrismaClient::_builder().build().await.unwrap();
+    let events = client
+        .webhook_event()
+        .find_many(vec![webhook_event::event::equals(
+            "run.finished".to_string(),
+        )])
+        .exec()
+        .await
+        .unwrap();
+    let body = events
+        .iter()
+        .map(delivery_body)
+        .find(|body| body["data"]["runId"] == run_id.as_str())
+        .unwrap();
+    assert_eq!(body["data"]["status"], "ABORTED");
+
+    let reports = body["data"]["reports"].as_array().unwrap();
+    assert_eq!(reports.len(), 2);
+    for (attachment, content_type) in reports.iter().zip(["application/xml", "text/html"]) {
+        assert!(attachment["filename"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("run-{run_id}-report.")));
+        let (status, served_type, _) =
+            report(&app, &cookie, attachment["path"].as_str().unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(served_type.starts_with(content_type));
+        assert_eq!(attachment["contentType"], served_type.as_str());
+    }
+}
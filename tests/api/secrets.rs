@@ -0,0 +1,127 @@
+use http::{Request, StatusCode};
+use hyper::Body;
+use std::collections::HashMap;
+use tonsail_server::{
+    configuration::{configuration_from, get_configuration, Environment},
+    util::secrets::{SecretKey, SigningKeys},
+    Application,
+};
+use tower::ServiceExt;
+
+use crate::util::{login, seed_database};
+
+async fn me(app: &Application, cookie: &str) -> StatusCode {
+    app.router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/v1/me")
+                .header(http::header::COOKIE, cookie)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+#[test]
+fn parses_base64_hex_and_byte_list_secrets() {
+    let bytes = [1u8, 2, 255];
+    for value in ["base64:AQL/", "hex:0102ff", "1,2,255"] {
+        let key = SecretKey::parse(value).unwrap();
+        assert_eq!(key.expose(), bytes);
+    }
+    assert!(SecretKey::parse("hex:zz").is_err());
+}
+
+#[test]
+fn previous_secrets_from_the_environment_keep_their_byte_lists() {
+    let legacy = vec!["7"; 64].join(",");
+    let mut vars: HashMap<String, String> = std::env::vars().collect();
+    vars.insert(
+        "APP_PREVIOUS_SECRETS".to_string(),
+        format!("{legacy}; hex:{}", "ab".repeat(64)),
+    );
+
+    let config = configuration_from(Environment::Test, vars).unwrap();
+
+    assert_eq!(config.previous_secrets.len(), 2);
+    assert_eq!(config.previous_secrets[0].expose(), [7; 64]);
+    assert_eq!(config.previous_secrets[1].expose(), [0xab; 64]);
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn secrets_are_redacted_when_printed() {
+    let key = SecretKey::parse("hex:0102ff").unwrap();
+    assert_eq!(format!("{key:?}"), "SecretKey([REDACTED])");
+}
+
+#[test]
+fn resigns_cookies_of_previous_keys_only() {
+    let old = SecretKey::new(vec![1; 64]);
+    let new = SecretKey::new(vec![2; 64]);
+    let forged = SecretKey::new(vec![3; 64]);
+
+    let mut jar = cookie::CookieJar::new();
+    jar.signed_mut(&cookie::Key::from(old.expose()))
+        .add(cookie::Cookie::new("api_sid", "session"));
+    let signed_by_old = jar.get("api_sid").unwrap().value().to_string();
+
+    let rotated = SigningKeys::new(&new, &[old]);
+    assert!(rotated.resign("api_sid", &signed_by_old).is_some());
+
+    let unrelated = SigningKeys::new(&new, &[forged]);
+    assert!(unrelated.resign("api_sid", &signed_by_old).is_none());
+}
+
+#[test]
+fn binds_logins_of_previous_keys_to_the_current_one() {
+    let old = SecretKey::new(vec![1; 64]);
+    let new = SecretKey::new(vec![2; 64]);
+    let old_id = auth_id(&[1; 64], b"hash");
+    let new_id = auth_id(&[2; 64], b"hash");
+
+    let rotated = SigningKeys::new(&new, &[old]);
+    assert_eq!(
+        rotated.current_auth_id(&old_id, b"hash"),
+        Some(new_id.clone())
+    );
+    assert_eq!(rotated.current_auth_id(&new_id, b"hash"), Some(new_id));
+    // A password change unbinds the login under every key
+    assert!(rotated.current_auth_id(&old_id, b"changed").is_none());
+
+    let dropped = SigningKeys::new(&new, &[]);
+    assert!(dropped.current_auth_id(&old_id, b"hash").is_none());
+}
+
+/// The `_auth_id` axum-login stores for a login made under `key`, a base64 HMAC-SHA512.
+fn auth_id(key: &[u8], password_hash: &[u8]) -> String {
+    use base64::Engine;
+    use hmac::Mac;
+    let mut mac = hmac::Hmac::<sha2::Sha512>::new_from_slice(key).unwrap();
+    mac.update(password_hash);
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+#[tokio::test]
+async fn sessions_survive_a_key_rotation() {
+    seed_database().await;
+    let config = get_configuration().unwrap();
+    let old_secret = config.secret.clone();
+    let app = Application::build(config).await.unwrap();
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+
+    let mut config = get_configuration().unwrap();
+    config.secret = SecretKey::new(vec![42; 64]);
+    config.previous_secrets = vec![old_secret];
+    let rotated = Application::build(config).await.unwrap();
+    assert_eq!(me(&rotated, &cookie).await, StatusCode::OK);
+
+    // Once the old key is dropped, its cookies are no longer accepted
+    let mut config = get_configuration().unwrap();
+    config.secret = SecretKey::new(vec![42; 64]);
+    let dropped = Application::build(config).await.unwrap();
+    assert_eq!(me(&dropped, &cookie).await, StatusCode::UNAUTHORIZED);
+}
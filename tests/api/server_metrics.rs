@@ -0,0 +1,66 @@
+use http::{header, Request, StatusCode};
+use hyper::Body;
+use secrecy::Secret;
+use tonsail_server::{configuration::get_configuration, Application};
+use tower::ServiceExt;
+
+const TOKEN: &str = "prometheus-scrapes-with-this-token";
+
+async fn scrape(app: &Application, token: Option<&str>) -> http::Response<axum::body::BoxBody> {
+    let mut request = Request::builder().uri("/internal/metrics");
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    app.router
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn exposes_request_metrics_by_matched_route() {
+    let mut config = get_configuration().unwrap();
+    config.telemetry.metrics_token = Some(Secret::new(TOKEN.to_string()));
+    let app = Application::build(config).await.unwrap();
+
+    for (method, uri) in [
+        ("GET", "/v1/me"),
+        ("GET", "/no/such/route"),
+        ("BREW", "/v1/me"),
+    ] {
+        let request = Request::builder().method(method).uri(uri);
+        app.router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(scrape(&app, None).await.status(), StatusCode::UNAUTHORIZED);
+    let response = scrape(&app, Some(TOKEN)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        body.contains(r#"tonsail_http_requests_total{method="GET",route="/v1/me",status="401"} 1"#)
+    );
+    assert!(body.contains(r#"route="unmatched""#));
+    assert!(!body.contains("/no/such/route"));
+    assert!(body.contains("tonsail_active_sessions"));
+    assert!(body.contains(r#"method="other""#));
+    assert!(!body.contains("BREW"));
+    assert!(body.contains(r#"tonsail_pool_connections{pool="questdb",state="idle"}"#));
+    assert!(body.contains(r#"tonsail_pool_connections{pool="mysql",state="active"}"#));
+    assert!(body.contains("# TYPE tonsail_ingested_samples_total counter"));
+}
+
+#[tokio::test]
+async fn metrics_are_hidden_without_a_token() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    let response = scrape(&app, Some("anything")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,115 @@
+use http::{Request, StatusCode};
+use hyper::Body;
+use prisma_client_rust::serde_json::{self, json, Value};
+use tonsail_server::{
+    configuration::get_configuration, domain::share::MAX_FAILED_ATTEMPTS, Application,
+};
+use tower::ServiceExt;
+
+use crate::util::{login, seed_database, send, send_json, start_run};
+
+/// Requests a shared resource without a session.
+async fn visit(app: &Application, uri: &str, password: Option<&str>) -> (StatusCode, Value) {
+    let mut request = Request::builder().uri(uri);
+    if let Some(password) = password {
+        request = request.header("x-share-password", password);
+    }
+    let response = app
+        .router
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn shared_runs_are_readable_until_revoked() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = start_run(&app, &cookie, "Shared").await;
+    let samples = json!({ "samples": [
+        { "name": "vus", "ts": "2023-03-15T00:00:00Z", "value": 5.0 },
+        { "name": "vus", "ts": "2023-03-15T00:00:10Z", "value": 15.0 }
+    ]});
+    let uri = format!("/v1/runs/{run_id}/samples");
+    send_json(&app, &cookie, &uri, &samples).await;
+    let uri = format!("/v1/runs/{run_id}/status");
+    send(&app, &cookie, "PUT", &uri, Some("status=FINISHED")).await;
+
+    let shares_uri = format!("/v1/runs/{run_id}/shares");
+    let form = "expiresInHours=24&password=open-sesame";
+    let (status, link) = send(&app, &cookie, "POST", &shares_uri, Some(form)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(link["hasPassword"], true);
+    let url = link["url"].as_str().unwrap().to_string();
+
+    let (status, _) = visit(&app, &url, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = visit(&app, &url, Some("wrong-password")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, run) = visit(&app, &url, Some("open-sesame")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run["id"], run_id.as_str());
+    assert_eq!(run["testName"], "Shared");
+    assert_eq!(run["metrics"][0]["name"], "vus");
+    assert_eq!(run["metrics"][0]["mean"], 10.0);
+
+    let metrics_url = format!("{url}/metrics?name=vus");
+    let (status, series) = visit(&app, &metrics_url, Some("open-sesame")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(series["values"].as_array().unwrap().len(), 2);
+
+    let (_, links) = send(&app, &cookie, "GET", &shares_uri, None).await;
+    let listed = &links.as_array().unwrap()[0];
+    assert!(listed["url"].is_null());
+    let link_id = listed["id"].as_str().unwrap();
+
+    let uri =
+        format!("/v1/organizations/orgid1/audit?action=share_link.accessed&target_id={link_id}");
+    let (_, page) = send(&app, &cookie, "GET", &uri, None).await;
+    assert_eq!(page["items"].as_array().unwrap().len(), 2);
+    let uri =
+        format!("/v1/organizations/orgid1/audit?action=share_link.denied&target_id={link_id}");
+    let (_, page) = send(&app, &cookie, "GET", &uri, None).await;
+    assert_eq!(page["items"].as_array().unwrap().len(), 2);
+
+    let uri = format!("{shares_uri}/{link_id}");
+    let (status, _) = send(&app, &cookie, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = visit(&app, &url, Some("open-sesame")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, &cookie, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn links_lock_after_too_many_wrong_passwords() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = start_run(&app, &cookie, "Guessed").await;
+    let uri = format!("/v1/runs/{run_id}/status");
+    send(&app, &cookie, "PUT", &uri, Some("status=FINISHED")).await;
+
+    let shares_uri = format!("/v1/runs/{run_id}/shares");
+    let form = "password=open-sesame";
+    let (_, link) = send(&app, &cookie, "POST", &shares_uri, Some(form)).await;
+    let url = link["url"].as_str().unwrap().to_string();
+
+    for _ in 0..MAX_FAILED_ATTEMPTS {
+        let (status, _) = visit(&app, &url, Some("wrong-password")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    // Once locked, not even the right password is checked
+    let (status, _) = visit(&app, &url, Some("open-sesame")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
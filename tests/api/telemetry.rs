@@ -0,0 +1,59 @@
+use std::sync::{Arc, Mutex};
+use tonsail_server::{configuration::get_configuration, Application};
+use tracing::{
+    info_span,
+    span::{Attributes, Id},
+    Instrument, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    Layer, Registry,
+};
+
+use crate::util::{login, seed_database};
+
+/// Target of every new span, with the name of the root span it belongs to.
+#[derive(Clone, Default)]
+struct NewSpans(Arc<Mutex<Vec<(String, Option<String>)>>>);
+
+impl<S> Layer<S> for NewSpans
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let root = ctx
+            .span(id)
+            .and_then(|span| span.scope().last())
+            .map(|root| root.name().to_string());
+        let target = attrs.metadata().target().to_string();
+        self.0.lock().unwrap().push((target, root));
+    }
+}
+
+#[tokio::test]
+async fn prisma_queries_and_redis_commands_are_child_spans() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+    seed_database().await;
+
+    let spans = NewSpans::default();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(spans.clone()));
+    // Logging in reads the user through Prisma and stores the session in Redis
+    login(&app.router, "graham@bell.com", "Gr@h@mBell69")
+        .instrument(info_span!("request"))
+        .await;
+
+    let spans = spans.0.lock().unwrap();
+    let under_request = |prefixes: &[&str]| {
+        spans.iter().any(|(target, root)| {
+            prefixes.iter().any(|p| target.starts_with(p)) && root.as_deref() == Some("request")
+        })
+    };
+    assert!(under_request(&[
+        "quaint",
+        "query_core",
+        "sql_query_connector"
+    ]));
+    assert!(under_request(&["fred"]));
+}
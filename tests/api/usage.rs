@@ -0,0 +1,111 @@
+use http::StatusCode;
+use std::time::Duration;
+use tonsail_server::{configuration::get_configuration, Application};
+
+use crate::util::{login, register, seed_database, send, start_run};
+
+#[tokio::test]
+async fn runs_are_metered_and_limited_by_the_plan() {
+    let mut config = get_configuration().unwrap();
+    config.plans.free.max_concurrent_runs = 1;
+    let app = Application::build(config).await.unwrap();
+
+    // A fresh organization, so no other test's runs count against the limits
+    let (cookie, org_id) = register(&app).await;
+    let form = format!("name=Metered&organization_id={org_id}");
+    let (_, project) = send(&app, &cookie, "POST", "/v1/projects", Some(&form)).await;
+    let form = format!(
+        "name=Metered&project_id={}",
+        project["id"].as_str().unwrap()
+    );
+    let (_, test) = send(&app, &cookie, "POST", "/v1/tests", Some(&form)).await;
+    let test_id = test["id"].as_str().unwrap();
+    let usage_uri = format!("/v1/organizations/{org_id}/usage");
+
+    // The free plan allows 50 virtual users
+    let form = format!("test_id={test_id}&vus=51");
+    let (status, _) = send(&app, &cookie, "POST", "/v1/runs/new", Some(&form)).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+
+    let form = format!("test_id={test_id}&vus=10&duration_secs=60");
+    let (status, run) = send(&app, &cookie, "POST", "/v1/runs/new", Some(&form)).await;
+    assert_eq!(status, StatusCode::OK);
+    let status_uri = format!("/v1/runs/{}/status", run["id"].as_str().unwrap());
+    let (_, other) = send(&app, &cookie, "POST", "/v1/runs/new", Some(&form)).await;
+    let other_uri = format!("/v1/runs/{}/status", other["id"].as_str().unwrap());
+
+    let (status, _) = send(&app, &cookie, "PUT", &status_uri, Some("status=FINISHED")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, run) = send(&app, &cookie, "PUT", &status_uri, Some("status=STARTED")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(run["startedAt"].is_string());
+
+    // One run at a time on the free plan
+    let (status, _) = send(&app, &cookie, "PUT", &other_uri, Some("status=STARTED")).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+
+    let (status, run) = send(&app, &cookie, "PUT", &status_uri, Some("status=FINISHED")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run["status"], "FINISHED");
+    // Finishing twice does not meter the run twice
+    let (status, _) = send(&app, &cookie, "PUT", &status_uri, Some("status=FINISHED")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = send(&app, &cookie, "PUT", &other_uri, Some("status=STARTED")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, usage) = send(&app, &cookie, "GET", &usage_uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(usage["plan"], "FREE");
+    assert_eq!(usage["limits"]["maxVus"], 50);
+    assert_eq!(usage["runs"], 2);
+}
+
+#[tokio::test]
+async fn runs_beyond_the_maximum_duration_are_cut_off() {
+    let mut config = get_configuration().unwrap();
+    config.plans.free.max_run_duration_secs = 0;
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = start_run(&app, &cookie, "Overtime").await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let uri = format!("/v1/runs/{run_id}/status");
+    let (status, run) = send(&app, &cookie, "PUT", &uri, Some("status=FINISHED")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run["status"], "ABORTED");
+    assert_eq!(run["finishedAt"], run["startedAt"]);
+}
+
+#[tokio::test]
+async fn runs_of_other_organizations_are_not_found() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let owner = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+    let run_id = start_run(&app, &owner, "Foreign").await;
+    let (_, run) = send(&app, &owner, "GET", &format!("/v1/runs/{run_id}"), None).await;
+    let form = format!("test_id={}", run["testId"].as_str().unwrap());
+
+    let (stranger, _) = register(&app).await;
+    let (status, _) = send(&app, &stranger, "POST", "/v1/runs/new", Some(&form)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let uri = format!("/v1/runs/{run_id}/status");
+    let (status, _) = send(&app, &stranger, "PUT", &uri, Some("status=FINISHED")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn rejects_a_malformed_period() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    seed_database().await;
+    let cookie = login(&app.router, "graham@bell.com", "Gr@h@mBell69").await;
+
+    let uri = "/v1/organizations/orgid1/usage?period=october";
+    let (status, _) = send(&app, &cookie, "GET", uri, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
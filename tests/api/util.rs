@@ -1,9 +1,140 @@
-use prisma_client_rust::raw;
+use axum::Router;
+use http::{Request, StatusCode};
+use hyper::Body;
+use prisma_client_rust::serde_json::{self, Value};
+use serde_urlencoded::to_string;
 use sqlx::{Connection, PgConnection};
 use tonsail_server::{
-    prisma::{organization, user, PrismaClient},
-    util::hash::hash_password,
+    admin::seed_catalog,
+    domain::auth::{AuthLoginForm, AuthRegisterForm},
+    prisma::{organization, user, PrismaClient, UserRole},
+    util::{hash::hash_password, nano_id::generate_id},
+    Application,
 };
+use tower::ServiceExt;
+
+/// Logs in and returns the session cookie to send with later requests.
+pub async fn login(router: &Router, email: &str, password: &str) -> String {
+    let body = Body::from(
+        to_string(AuthLoginForm {
+            email: email.to_string(),
+            password: password.to_string(),
+        })
+        .unwrap(),
+    );
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .header(
+                    http::header::CONTENT_TYPE,
+                    "application/x-www-form-urlencoded",
+                )
+                .uri("/v1/login")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = response.headers()[http::header::SET_COOKIE]
+        .to_str()
+        .unwrap();
+    cookie.split(';').next().unwrap().to_string()
+}
+
+/// Registers a new owner with an organization of their own, returns the cookie and its id.
+pub async fn register(app: &Application) -> (String, String) {
+    let email = format!("{}@tonsail.test", generate_id());
+    let form = to_string(AuthRegisterForm {
+        name: "Alexander Bell".to_string(),
+        email: email.clone(),
+        password: "Gr@h@mBell69".to_string(),
+    })
+    .unwrap();
+    let (status, user) = send(app, "", "POST", "/v1/register", Some(&form)).await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = login(&app.router, &email, "Gr@h@mBell69").await;
+    (cookie, user["organizationId"].as_str().unwrap().to_string())
+}
+
+/// Sends a request with an optional form body and returns the status and the JSON body.
+pub async fn send(
+    app: &Application,
+    cookie: &str,
+    method: &str,
+    uri: &str,
+    form: Option<&str>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(http::header::COOKIE, cookie);
+    if form.is_some() {
+        request = request.header(
+            http::header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded",
+        );
+    }
+    let body = form.map_or_else(Body::empty, |f| Body::from(f.to_string()));
+    let response = app
+        .router
+        .clone()
+        .oneshot(request.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+/// Posts `body` as JSON and returns the status and the JSON body.
+pub async fn send_json(
+    app: &Application,
+    cookie: &str,
+    uri: &str,
+    body: &Value,
+) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header(http::header::COOKIE, cookie)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+/// Creates a project and a test in the organization and returns the test id.
+pub async fn create_test(app: &Application, cookie: &str, org_id: &str, name: &str) -> String {
+    let form = format!("name={name}&organization_id={org_id}");
+    let (_, project) = send(app, cookie, "POST", "/v1/projects", Some(&form)).await;
+    let form = format!("name={name}&project_id={}", project["id"].as_str().unwrap());
+    let (_, test) = send(app, cookie, "POST", "/v1/tests", Some(&form)).await;
+    test["id"].as_str().unwrap().to_string()
+}
+
+/// Creates a project, a test and a started run in `orgid1` and returns the run id.
+pub async fn start_run(app: &Application, cookie: &str, name: &str) -> String {
+    start_org_run(app, cookie, "orgid1", name).await
+}
+
+/// Starts a run in a new project and test of the organization, returns its id.
+pub async fn start_org_run(app: &Application, cookie: &str, org_id: &str, name: &str) -> String {
+    let test_id = create_test(app, cookie, org_id, name).await;
+    let form = format!("test_id={test_id}");
+    let (_, run) = send(app, cookie, "POST", "/v1/runs/new", Some(&form)).await;
+    let run_id = run["id"].as_str().unwrap().to_string();
+    let uri = format!("/v1/runs/{run_id}/status");
+    let (_, run) = send(app, cookie, "PUT", &uri, Some("status=STARTED")).await;
+    assert_eq!(run["status"], "STARTED");
+    run_id
+}
 
 pub async fn seed_database() {
     // MySQL seeding
@@ -30,31 +161,15 @@ pub async fn seed_database() {
                     hash_password("Gr@h@mBell69".as_bytes()),
                     "Graham Bell".to_string(),
                     organization::id::equals(new_org.id.clone()),
-                    vec![],
+                    vec![user::role::set(UserRole::Owner)],
                 ),
-                vec![],
+                vec![user::role::set(UserRole::Owner)],
             )
             .exec()
             .await
             .unwrap();
 
-        let _catalog = client
-            ._execute_raw(raw!(
-            "INSERT INTO tonsail.MetricsCatalog (label,value,`group`,unit,description) VALUES
-            ('VUs', 'vus', 'general', 'count', 'The number of virtual users'),
-            ('Request rate', 'http_request_rate', 'HTTP', 'rps', 'The rate at which requests are made to a given URL'),
-            ('Response time', 'http_response_rate', 'HTTP', 'ms', 'The time spent waiting for the full response'),
-            ('Failure rate', 'http_failure_rate', 'HTTP', '%', 'The percentage of requests that resulted in a failure'),
-            ('Handshake time', 'http_handshake_time', 'HTTP', 'ms', 'The time it takes to establish a connection with the server'),
-            ('Waiting time', 'http_waiting_time', 'HTTP', 'ms', 'The time spent waiting for a response from the server'),
-            ('Blocked time', 'http_blocked_time', 'HTTP', 'ms', 'The time spent waiting for an available connection slot'),
-            ('Sent data', 'http_sent_bytes', 'HTTP', 'bytes', 'The amount of data sent in the request'),
-            ('Received data', 'http_recv_bytes', 'HTTP', 'bytes', 'The amount of data received in the response'),
-            ('CPU usage', 'cpu_usage', 'System', '%', 'The percentage of CPU utilization'),
-            ('Memory usage', 'mem_usage', 'System', '%', 'The percentage of memory utilization');"
-            ))
-            .exec()
-            .await.unwrap();
+        seed_catalog(&client).await.unwrap();
     }
 
     // QuestDB seeding
@@ -63,8 +178,16 @@ pub async fn seed_database() {
             .await
             .unwrap();
 
-        sqlx::query("CREATE table metrics (name Symbol, runID String, scenario String, url String, method Symbol, status Symbol, ts Timestamp, value Float)").execute(&mut conn).await.unwrap();
-        sqlx::query("INSERT INTO metrics
+        // The server migrations create the table, the samples are only inserted once
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT count() FROM metrics WHERE runID = '3r2f039ffktv'")
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+        if count > 0 {
+            return;
+        }
+        sqlx::query("INSERT INTO metrics (name, runID, scenario, url, method, status, ts, value)
     SELECT
         'http_request_rate' name,
         '3r2f039ffktv' runID,
@@ -0,0 +1,37 @@
+use http::{Request, StatusCode};
+use hyper::Body;
+use tonsail_server::{configuration::get_configuration, Application};
+use tower::ServiceExt;
+
+async fn get(app: &Application, uri: &str) -> http::Response<axum::body::BoxBody> {
+    app.router
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn unversioned_routes_are_deprecated() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    let response = get(&app, "/me").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["deprecation"], "true");
+    assert!(response.headers().contains_key("sunset"));
+    assert_eq!(
+        response.headers()[http::header::LINK],
+        "</v1/me>; rel=\"successor-version\""
+    );
+}
+
+#[tokio::test]
+async fn versioned_routes_are_not_deprecated() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+
+    let response = get(&app, "/v1/me").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!response.headers().contains_key("deprecation"));
+}
@@ -1,5 +1,6 @@
 use http::{Request, StatusCode};
 use hyper::Body;
+use prisma_client_rust::serde_json::{self, Value};
 use tonsail_server::{configuration::get_configuration, Application};
 use tower::ServiceExt;
 
@@ -20,3 +21,93 @@ async fn returns_200_when_application_is_healthy() {
 
     assert_eq!(response.status(), StatusCode::OK);
 }
+
+#[tokio::test]
+async fn returns_200_when_application_is_alive() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+    let response = app
+        .router
+        .oneshot(
+            Request::builder()
+                .uri("/health/live")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn reports_every_dependency_when_ready() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+    let response = app
+        .router
+        .oneshot(
+            Request::builder()
+                .uri("/health/ready")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let readiness: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(readiness["status"], "up");
+    for dependency in ["mysql", "questdb", "redis"] {
+        assert_eq!(readiness["dependencies"][dependency]["status"], "up");
+        assert!(readiness["dependencies"][dependency]["latencyMs"].is_u64());
+    }
+}
+
+#[tokio::test]
+async fn returns_503_when_draining() {
+    let config = get_configuration().unwrap();
+    let app = Application::build(config).await.unwrap();
+    app.shutdown_token().cancel();
+
+    let response = app
+        .router
+        .oneshot(
+            Request::builder()
+                .uri("/health/ready")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let readiness: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(readiness["status"], "draining");
+}
+
+#[tokio::test]
+async fn keeps_accepting_connections_during_the_pre_stop_delay() {
+    let mut config = get_configuration().unwrap();
+    config.application.pre_stop_delay_secs = 1;
+    config.application.drain_timeout_secs = 1;
+    let app = Application::build(config).await.unwrap();
+    let uri = format!("http://{}/health/ready", app.server.local_addr());
+    let shutdown = app.shutdown_token();
+    let server = tokio::spawn(app.run_until_stopped());
+
+    shutdown.cancel();
+    let response = hyper::Client::new()
+        .get(uri.parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    server.await.unwrap().unwrap();
+    assert!(hyper::Client::new()
+        .get(uri.parse().unwrap())
+        .await
+        .is_err());
+}